@@ -0,0 +1,75 @@
+//! 每次投递的堆分配次数
+//!
+//! `cargo bench --bench alloc`
+extern crate vnbase;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use vnbase::run_loop;
+
+const POSTS: usize = 100_000;
+
+struct Counting;
+
+static ALLOCS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        ALLOCS.fetch_add(1, Ordering::Relaxed);
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+/// 投递 POSTS 条捕获 captured 字节的消息并全部执行，返回平均每条的分配次数
+fn allocs_per_post(captured: usize) -> f64 {
+    let (tx, rx) = mpsc::channel();
+    let th = thread::spawn(move || {
+        tx.send(run_loop::clone_handle()).unwrap();
+        run_loop::run();
+    });
+    let handle = rx.recv().unwrap();
+    let (tx, rx) = mpsc::channel();
+
+    let before = ALLOCS.load(Ordering::Relaxed);
+    for i in 0..POSTS {
+        match captured {
+            8 => handle.post(move || { let _ = i; }),
+            24 => {
+                let data = [i; 3];
+                handle.post(move || { let _ = data[0]; });
+            },
+            _ => {
+                let data = [i as u8; 256];
+                handle.post(move || { let _ = data[0]; });
+            },
+        }
+    }
+    handle.post(move || tx.send(()).unwrap());
+    rx.recv().unwrap();
+    let allocs = ALLOCS.load(Ordering::Relaxed) - before;
+
+    handle.stop();
+    th.join().unwrap();
+    allocs as f64 / POSTS as f64
+}
+
+fn main() {
+    for &captured in &[8, 24, 256] {
+        println!("{:>3} bytes captured: {:.3} allocations/post", captured, allocs_per_post(captured));
+    }
+}
@@ -0,0 +1,78 @@
+//! 通道和逐个投递把值送入循环的吞吐量
+//!
+//! `cargo bench --bench channel`
+extern crate vnbase;
+
+use std::cell::RefCell;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use vnbase::run_loop;
+
+const ITEMS: usize = 1_000_000;
+
+/// 循环线程上的接收者，收到 ITEMS 个值后通知
+fn start_loop<F>(bind: F) -> (run_loop::Handle, thread::JoinHandle<()>, mpsc::Receiver<()>)
+    where F: FnOnce(Box<FnMut(usize)>) + Send + 'static {
+    let (tx, rx) = mpsc::channel();
+    let (done_tx, done_rx) = mpsc::channel();
+    let th = thread::spawn(move || {
+        let mut received = 0;
+        bind(Box::new(move |i| {
+            let _ = i;
+            received += 1;
+            if received == ITEMS {
+                done_tx.send(()).unwrap();
+            }
+        }));
+        tx.send(run_loop::clone_handle()).unwrap();
+        run_loop::run();
+    });
+    (rx.recv().unwrap(), th, done_rx)
+}
+
+/// 每个值单独投递
+fn per_item_post() -> f64 {
+    let (tx, rx) = mpsc::channel();
+    let (handle, th, done) = start_loop(move |f| {
+        tx.send(run_loop::new_object(RefCell::new(f))).unwrap();
+    });
+    let obj = rx.recv().unwrap();
+    let start = Instant::now();
+    for i in 0..ITEMS {
+        obj.post(move |f| (f.borrow_mut())(i));
+    }
+    done.recv().unwrap();
+    let elapsed = start.elapsed();
+    drop(obj);
+    handle.stop();
+    th.join().unwrap();
+    ITEMS as f64 / secs(elapsed)
+}
+
+/// 通过通道发送，capacity 为 `None` 时不限容量
+fn channel(capacity: Option<usize>) -> f64 {
+    let (tx, rx) = run_loop::channel(capacity);
+    let (handle, th, done) = start_loop(move |f| rx.for_each(f));
+    let start = Instant::now();
+    for i in 0..ITEMS {
+        tx.send(i).unwrap();
+    }
+    done.recv().unwrap();
+    let elapsed = start.elapsed();
+    drop(tx);
+    handle.stop();
+    th.join().unwrap();
+    ITEMS as f64 / secs(elapsed)
+}
+
+fn secs(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 * 1e-9
+}
+
+fn main() {
+    println!("per-item post:      {:>12.0} items/s", (0..5).map(|_| per_item_post()).fold(0.0, f64::max));
+    println!("unbounded channel:  {:>12.0} items/s", (0..5).map(|_| channel(None)).fold(0.0, f64::max));
+    println!("bounded channel 1k: {:>12.0} items/s", (0..5).map(|_| channel(Some(1024))).fold(0.0, f64::max));
+}
@@ -0,0 +1,59 @@
+//! 不均衡的突发工作在池中的完成时间
+//!
+//! `cargo bench --bench pool`
+extern crate vnbase;
+
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use vnbase::run_loop::{self, LoopPool};
+
+const JOBS: usize = 400;
+const LOOPS: usize = 4;
+
+/// 占用当前线程一段时间
+fn work() {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_micros(200) {}
+}
+
+/// 所有工作按同一个键投递，落在同一个循环上
+fn keyed(pool: &LoopPool) -> Duration {
+    let (tx, rx) = mpsc::channel();
+    let start = Instant::now();
+    for _ in 0..JOBS {
+        let tx = tx.clone();
+        pool.post_keyed(&0, move || {
+            work();
+            tx.send(()).unwrap();
+        });
+    }
+    for _ in 0..JOBS {
+        rx.recv().unwrap();
+    }
+    start.elapsed()
+}
+
+/// 所有工作放入共享队列，空闲的循环取走
+fn stealable(pool: &LoopPool) -> Duration {
+    let (tx, rx) = mpsc::channel();
+    let start = Instant::now();
+    for _ in 0..JOBS {
+        let tx = tx.clone();
+        pool.post_stealable(move || {
+            work();
+            tx.send(()).unwrap();
+        });
+    }
+    for _ in 0..JOBS {
+        rx.recv().unwrap();
+    }
+    start.elapsed()
+}
+
+fn main() {
+    let pool = run_loop::pool(LOOPS);
+    println!("one loop:  {:>12?}", (0..5).map(|_| keyed(&pool)).min().unwrap());
+    println!("stealable: {:>12?}", (0..5).map(|_| stealable(&pool)).min().unwrap());
+    pool.shutdown().unwrap();
+}
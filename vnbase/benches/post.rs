@@ -0,0 +1,128 @@
+//! 投递吞吐量和消息延迟
+//!
+//! `cargo bench --bench post`
+extern crate vnbase;
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use vnbase::run_loop;
+
+const POSTS: usize = 1_000_000;
+const ROUNDS: usize = 20_000;
+
+fn start_loop() -> (run_loop::Handle, thread::JoinHandle<()>) {
+    start_sharded_loop(1)
+}
+
+/// 队列分为 shards 片的循环
+fn start_sharded_loop(shards: usize) -> (run_loop::Handle, thread::JoinHandle<()>) {
+    let (tx, rx) = mpsc::channel();
+    let th = thread::spawn(move || {
+        run_loop::RunLoopBuilder::new().producer_shards(shards).install().unwrap();
+        tx.send(run_loop::clone_handle()).unwrap();
+        run_loop::run();
+    });
+    (rx.recv().unwrap(), th)
+}
+
+/// 其它线程连续投递，到最后一条消息执行为止
+fn throughput(payload: usize) -> f64 {
+    let (handle, th) = start_loop();
+    let (tx, rx) = mpsc::channel();
+    let start = Instant::now();
+    for i in 0..POSTS {
+        if payload == 0 {
+            handle.post(move || { let _ = i; });
+        }
+        else {
+            let data = [i as u8; 256];
+            handle.post(move || { let _ = data[0]; });
+        }
+    }
+    handle.post(move || tx.send(()).unwrap());
+    rx.recv().unwrap();
+    let elapsed = start.elapsed();
+    handle.stop();
+    th.join().unwrap();
+    POSTS as f64 / secs(elapsed)
+}
+
+/// 每 size 条消息用 `Handle::batch` 一起投递，到最后一条消息执行为止
+fn batched(size: usize) -> f64 {
+    let (handle, th) = start_loop();
+    let (tx, rx) = mpsc::channel();
+    let start = Instant::now();
+    for n in 0..POSTS / size {
+        handle.batch(|b| for i in 0..size {
+            let i = n * size + i;
+            b.post(move || { let _ = i; });
+        });
+    }
+    handle.post(move || tx.send(()).unwrap());
+    rx.recv().unwrap();
+    let elapsed = start.elapsed();
+    handle.stop();
+    th.join().unwrap();
+    (POSTS / size * size) as f64 / secs(elapsed)
+}
+
+/// producers 个线程同时投递到分为 shards 片的队列，到所有消息执行为止
+fn contended(producers: usize, shards: usize) -> f64 {
+    let (handle, th) = start_sharded_loop(shards);
+    let (tx, rx) = mpsc::channel();
+    let per_thread = POSTS / producers;
+    let start = Instant::now();
+    let threads: Vec<_> = (0..producers).map(|_| {
+        let handle = handle.clone();
+        let tx = tx.clone();
+        thread::spawn(move || {
+            for i in 0..per_thread {
+                handle.post(move || { let _ = i; });
+            }
+            handle.post(move || tx.send(()).unwrap());
+        })
+    }).collect();
+    for _ in 0..producers {
+        rx.recv().unwrap();
+    }
+    let elapsed = start.elapsed();
+    for th in threads {
+        th.join().unwrap();
+    }
+    handle.stop();
+    th.join().unwrap();
+    (per_thread * producers) as f64 / secs(elapsed)
+}
+
+/// 投递到执行的往返时间，每次往返之间循环处于空闲状态
+fn latency() -> Duration {
+    let (handle, th) = start_loop();
+    let (tx, rx) = mpsc::channel();
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        let tx = tx.clone();
+        handle.post(move || tx.send(()).unwrap());
+        rx.recv().unwrap();
+    }
+    let elapsed = start.elapsed();
+    handle.stop();
+    th.join().unwrap();
+    elapsed / ROUNDS as u32
+}
+
+fn secs(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 * 1e-9
+}
+
+fn main() {
+    let best = |payload| (0..5).map(|_| throughput(payload)).fold(0.0, f64::max);
+    println!("small closures: {:>12.0} posts/s", best(0));
+    println!("large closures: {:>12.0} posts/s", best(256));
+    println!("batches of 64:  {:>12.0} posts/s", (0..5).map(|_| batched(64)).fold(0.0, f64::max));
+    println!("8 producers:    {:>12.0} posts/s", (0..5).map(|_| contended(8, 1)).fold(0.0, f64::max));
+    println!("16 producers:   {:>12.0} posts/s", (0..5).map(|_| contended(16, 1)).fold(0.0, f64::max));
+    println!("16 producers, 16 shards: {:>12.0} posts/s", (0..5).map(|_| contended(16, 16)).fold(0.0, f64::max));
+    println!("round trip:     {:>12?}", (0..5).map(|_| latency()).min().unwrap());
+}
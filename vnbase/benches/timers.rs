@@ -0,0 +1,99 @@
+//! 大量定时器时二叉堆和时间轮的比较
+//!
+//! `cargo bench --bench timers`
+extern crate vnbase;
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use vnbase::run_loop::{self, Timer, TimerStrategy};
+
+const TIMERS: u64 = 50_000;
+/// 同时到期的定时器数
+const BURST: u64 = 10_000;
+
+/// 伪随机的时长，在 0 到 max 毫秒之间
+fn spread(i: u64, max: u64) -> Duration {
+    Duration::from_micros(i.wrapping_mul(2_654_435_761) % (max * 1000))
+}
+
+/// 启动、重新启动和取消 TIMERS 个定时器，返回三步各自的耗时
+fn churn(strategy: TimerStrategy) -> (Duration, Duration, Duration) {
+    run_loop::set_timer_strategy(strategy);
+    let start = Instant::now();
+    let timers: Vec<Timer> = (0..TIMERS).map(|i| {
+        run_loop::new_timer().with_callback(|| {}).and_start(spread(i, 10_000) + Duration::from_secs(1))
+    }).collect();
+    let started = start.elapsed();
+
+    let start = Instant::now();
+    for (i, t) in timers.iter().enumerate() {
+        t.start(spread(i as u64 + 7, 10_000) + Duration::from_secs(1));
+    }
+    let restarted = start.elapsed();
+
+    let start = Instant::now();
+    for t in &timers {
+        t.cancel();
+    }
+    let cancelled = start.elapsed();
+    (started, restarted, cancelled)
+}
+
+/// TIMERS 个定时器在 50 毫秒内陆续到期，返回运行到全部触发的时间
+fn fire(strategy: TimerStrategy) -> Duration {
+    run_loop::set_timer_strategy(strategy);
+    let fired = Rc::new(Cell::new(0));
+    let start = Instant::now();
+    let _timers: Vec<Timer> = (0..TIMERS).map(|i| {
+        let fired = fired.clone();
+        run_loop::new_timer()
+            .with_callback_once(move || {
+                fired.set(fired.get() + 1);
+                if fired.get() == TIMERS {
+                    run_loop::stop();
+                }
+            })
+            .and_start(spread(i, 50))
+    }).collect();
+    run_loop::run();
+    start.elapsed()
+}
+
+/// BURST 个定时器在线程阻塞期间同时到期，返回运行到全部触发的时间
+fn burst(strategy: TimerStrategy) -> Duration {
+    run_loop::set_timer_strategy(strategy);
+    let fired = Rc::new(Cell::new(0));
+    let _timers: Vec<Timer> = (0..BURST).map(|i| {
+        let fired = fired.clone();
+        run_loop::new_timer()
+            .with_callback_once(move || {
+                fired.set(fired.get() + 1);
+                if fired.get() == BURST {
+                    run_loop::stop();
+                }
+            })
+            .and_start(spread(i, 5))
+    }).collect();
+    thread::sleep(Duration::from_millis(10));
+    let start = Instant::now();
+    run_loop::run();
+    start.elapsed()
+}
+
+fn main() {
+    let strategies = [
+        ("heap", TimerStrategy::Heap),
+        ("wheel 1ms", TimerStrategy::Wheel(Duration::from_millis(1))),
+    ];
+    for &(name, strategy) in &strategies {
+        let (started, restarted, cancelled) = (0..5).map(|_| churn(strategy)).min().unwrap();
+        let fired = (0..5).map(|_| fire(strategy)).min().unwrap();
+        let burst = (0..5).map(|_| burst(strategy)).min().unwrap();
+        println!("{:<10} start {:>10?}  restart {:>10?}  cancel {:>10?}  fire all {:>10?}  burst {:>10?}",
+            name, started, restarted, cancelled, fired, burst);
+    }
+    run_loop::set_timer_strategy(TimerStrategy::Heap);
+}
@@ -0,0 +1,73 @@
+//! 在 winit 的窗口线程中使用循环：周期历程请求重绘，其它线程投递的函数更新标题
+//!
+//! `cargo run --example winit --features winit`，关闭窗口或三秒后退出
+extern crate vnbase;
+extern crate winit;
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+use vnbase::run_loop::{self, Schedule};
+use winit::application::ApplicationHandler;
+use winit::event::WindowEvent;
+use winit::event_loop::{ActiveEventLoop, EventLoop};
+use winit::window::{Window, WindowId};
+
+struct App {
+    window: Rc<Cell<Option<&'static Window>>>,
+    frames: Rc<Cell<u32>>,
+    redraw: Option<Schedule>,
+}
+
+impl ApplicationHandler for App {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.redraw.is_some() {
+            return;
+        }
+        let window = event_loop.create_window(Window::default_attributes().with_title("vnbase")).unwrap();
+        // 窗口和事件循环一样一直存在到进程退出
+        let window: &'static Window = Box::leak(Box::new(window));
+        self.window.set(Some(window));
+        self.redraw = Some(run_loop::new_schedule()
+            .with_period(Duration::from_millis(16))
+            .with_callback(move |_| window.request_redraw())
+            .and_start());
+    }
+
+    fn window_event(&mut self, _event_loop: &ActiveEventLoop, _id: WindowId, event: WindowEvent) {
+        match event {
+            WindowEvent::CloseRequested => run_loop::stop(),
+            WindowEvent::RedrawRequested => self.frames.set(self.frames.get() + 1),
+            _ => {},
+        }
+    }
+}
+
+fn main() {
+    let event_loop = EventLoop::with_user_event().build().unwrap();
+    let mut app = App {
+        window: Rc::new(Cell::new(None)),
+        frames: Rc::new(Cell::new(0)),
+        redraw: None,
+    };
+
+    // 其它线程每秒通过循环更新一次标题
+    let (window, frames) = (app.window.clone(), app.frames.clone());
+    let title = run_loop::new_object(move || {
+        if let Some(window) = window.get() {
+            window.set_title(&format!("vnbase: {} frames", frames.get()));
+        }
+    });
+    thread::spawn(move || {
+        for _ in 0..3 {
+            thread::sleep(Duration::from_secs(1));
+            title.post(|update| update());
+        }
+        title.post(|_| run_loop::stop());
+    });
+
+    run_loop::winit::run_app(event_loop, &mut app).unwrap();
+    println!("{} frames", app.frames.get());
+}
@@ -3,6 +3,20 @@
 //! 
 //! 提供基础功能代码实现
 
+#![cfg_attr(feature = "nightly", feature(coerce_unsized, unsize))]
+
+#[cfg(feature = "io")]
+extern crate mio;
+#[cfg(any(all(feature = "io", unix), all(feature = "futex", any(target_os = "linux", target_os = "android")), all(feature = "signal", unix)))]
+extern crate libc;
+#[cfg(feature = "futures")]
+extern crate futures;
+#[cfg(all(feature = "winit", not(target_arch = "wasm32")))]
+extern crate winit;
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+extern crate wasm_bindgen;
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+extern crate web_time;
 
 pub mod run_loop;
 
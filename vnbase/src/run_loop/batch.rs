@@ -0,0 +1,41 @@
+//! 在投递方收集多个函数后一起投递，见 `Handle::batch`
+use std::mem;
+use std::sync::Arc;
+
+use super::core::Core;
+
+/// 收集要投递的函数，释放时按收集的顺序一起投递，只唤醒循环一次
+pub struct Batch<'a> {
+    core: &'a Arc<Core>,
+    msgs: Vec<Box<FnOnce() + Send>>,
+}
+
+impl<'a> Batch<'a> {
+    pub fn new(core: &'a Arc<Core>) -> Batch<'a> {
+        Batch {
+            core: core,
+            msgs: Vec::new(),
+        }
+    }
+
+    /// 收集一个函数，释放 `Batch` 时投递
+    pub fn post<T>(&mut self, msg: T) where T: FnOnce() + Send + 'static {
+        self.msgs.push(Box::new(msg));
+    }
+
+    /// 已经收集、尚未投递的函数个数
+    pub fn len(&self) -> usize {
+        self.msgs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.msgs.is_empty()
+    }
+}
+
+impl<'a> Drop for Batch<'a> {
+    fn drop(&mut self) {
+        // 循环已经销毁时和 `Handle::post` 一样直接丢弃
+        let _ = self.core.try_post_all(mem::take(&mut self.msgs));
+    }
+}
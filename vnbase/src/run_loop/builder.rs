@@ -0,0 +1,202 @@
+//! 在第一次使用前配置线程的循环，见 `run_loop::builder`
+use std::error;
+use std::fmt;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use super::{Handle, PanicPolicy, TimerStrategy, WaitStrategy};
+
+/// `run` 在退出请求之后如何处理已经投递的消息
+///
+/// 和 `RunLoopBuilder::panic_policy` 一样只对阻塞的 `run` 有效，wasm32 上不受影响。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopPolicy {
+    /// 处理完当前的消息后立即返回，剩余的消息留在队列中，再次 `run` 时处理，默认方式
+    Immediate,
+    /// 返回前处理完收到退出请求时已经在排队的消息，之后投递的消息留在队列中
+    Drain,
+}
+
+/// 当前线程的循环已经创建，不能再配置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstallError;
+
+impl fmt::Display for InstallError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("run loop is already in use on this thread")
+    }
+}
+
+impl error::Error for InstallError {}
+
+/// 线程的循环的配置，见 `run_loop::builder`
+///
+/// 没有设置的项和直接使用循环时相同。除 `producer_shards` 外都可以在之后用对应的 `run_loop::set_*` 修改。
+#[derive(Debug, Clone)]
+pub struct RunLoopBuilder {
+    name: Option<String>,
+    shards: Option<usize>,
+    timer_capacity: usize,
+    msg_batch: usize,
+    wait_strategy: Option<WaitStrategy>,
+    timer_strategy: TimerStrategy,
+    coalescing: Duration,
+    panic_policy: PanicPolicy,
+    stop_policy: StopPolicy,
+}
+
+impl RunLoopBuilder {
+    pub fn new() -> Self {
+        RunLoopBuilder {
+            name: None,
+            shards: None,
+            timer_capacity: 0,
+            msg_batch: super::DEFAULT_MSG_BATCH,
+            wait_strategy: None,
+            timer_strategy: TimerStrategy::Heap,
+            coalescing: Duration::from_secs(0),
+            panic_policy: PanicPolicy::Report,
+            stop_policy: StopPolicy::Immediate,
+        }
+    }
+
+    /// 循环的名字，见 `run_loop::set_name`；`spawn_with` 同时用作线程的名字
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(String::from(name));
+        self
+    }
+
+    /// 消息队列的分片数，只能在创建时指定，默认为 1，最多 16，超出范围时取最近的有效值
+    ///
+    /// 每个投递线程固定使用其中一个分片，很多线程同时向同一个循环投递时可以减少争用；
+    /// 同一个线程投递的消息仍然按顺序执行，不同线程之间的顺序本来就不确定。
+    /// 每个分片会预先分配一个块，只在确实有大量并发投递时使用。
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop::{self, RunLoopBuilder};
+    /// use std::sync::mpsc;
+    /// use std::thread;
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let th = thread::spawn(move || {
+    ///     RunLoopBuilder::new().producer_shards(4).install().unwrap();
+    ///     assert_eq!(run_loop::producer_shards(), 4);
+    ///     tx.send(run_loop::clone_handle()).unwrap();
+    ///     run_loop::run();
+    /// });
+    /// let handle = rx.recv().unwrap();
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let producers: Vec<_> = (0..8).map(|p| {
+    ///     let handle = handle.clone();
+    ///     let tx = tx.clone();
+    ///     thread::spawn(move || for i in 0..2000 {
+    ///         let tx = tx.clone();
+    ///         handle.post(move || tx.send((p, i)).unwrap());
+    ///     })
+    /// }).collect();
+    /// let mut next = [0; 8];
+    /// for _ in 0..8 * 2000 {
+    ///     let (p, i) = rx.recv().unwrap();
+    ///     assert_eq!(next[p], i);
+    ///     next[p] += 1;
+    /// }
+    /// for p in producers {
+    ///     p.join().unwrap();
+    /// }
+    ///
+    /// handle.stop();
+    /// th.join().unwrap();
+    /// ```
+    pub fn producer_shards(mut self, n: usize) -> Self {
+        self.shards = Some(n);
+        self
+    }
+
+    /// 预先分配能容纳 n 个定时器的空间，见 `run_loop::reserve_timers`，使用时间轮时不需要
+    pub fn timer_capacity(mut self, n: usize) -> Self {
+        self.timer_capacity = n;
+        self
+    }
+
+    /// 每轮最多处理的消息数，见 `run_loop::set_message_batch_limit`
+    pub fn message_batch_limit(mut self, limit: usize) -> Self {
+        self.msg_batch = limit;
+        self
+    }
+
+    /// 见 `run_loop::set_wait_strategy`
+    pub fn wait_strategy(mut self, strategy: WaitStrategy) -> Self {
+        self.wait_strategy = Some(strategy);
+        self
+    }
+
+    /// 定时器的管理方式和精度，见 `run_loop::set_timer_strategy`
+    pub fn timer_strategy(mut self, strategy: TimerStrategy) -> Self {
+        self.timer_strategy = strategy;
+        self
+    }
+
+    /// 定时器的合并窗口，为零时关闭，见 `run_loop::set_timer_coalescing`
+    pub fn timer_coalescing(mut self, window: Duration) -> Self {
+        self.coalescing = window;
+        self
+    }
+
+    /// 回调 panic 时 `run` 的处理方式，默认为 `PanicPolicy::Report`，panic 从 `run` 传出
+    ///
+    /// `PanicPolicy::Restart` 时 `run` 在 panic 之后继续处理后面的消息和定时器，直到退出请求。
+    pub fn panic_policy(mut self, policy: PanicPolicy) -> Self {
+        self.panic_policy = policy;
+        self
+    }
+
+    /// 见 `StopPolicy`，默认为 `StopPolicy::Immediate`
+    pub fn stop_policy(mut self, policy: StopPolicy) -> Self {
+        self.stop_policy = policy;
+        self
+    }
+
+    /// 按配置创建当前线程的循环，循环已经创建（已经使用过 `run_loop` 的函数）时返回错误
+    pub fn install(self) -> Result<(), InstallError> {
+        super::create_loop(self.shards)?;
+        if let Some(ref name) = self.name {
+            super::set_name(name);
+        }
+        super::set_message_batch_limit(self.msg_batch);
+        if let Some(strategy) = self.wait_strategy {
+            super::set_wait_strategy(strategy);
+        }
+        super::set_timer_strategy(self.timer_strategy);
+        if self.timer_capacity > 0 {
+            super::reserve_timers(self.timer_capacity);
+        }
+        super::set_timer_coalescing(self.coalescing);
+        super::set_policies(self.panic_policy, self.stop_policy);
+        Ok(())
+    }
+
+    /// 见 `run_loop::spawn_with`
+    pub(super) fn spawn(self) -> (Handle, JoinHandle<()>) {
+        let mut thread = thread::Builder::new();
+        if let Some(ref name) = self.name {
+            thread = thread.name(name.clone());
+        }
+        let (tx, rx) = mpsc::channel();
+        let thread = thread.spawn(move || {
+            self.install().expect("new thread already has a run loop");
+            tx.send(super::clone_handle()).unwrap();
+            drop(tx);
+            super::run();
+        }).expect("failed to spawn run loop thread");
+        (rx.recv().unwrap(), thread)
+    }
+}
+
+impl Default for RunLoopBuilder {
+    fn default() -> Self {
+        RunLoopBuilder::new()
+    }
+}
@@ -0,0 +1,201 @@
+//! 把数据流送入循环的多生产者通道，见 `run_loop::channel`
+//!
+//! 发送端把值放入共享队列，只在队列没有等待执行的取出消息时投递一条，
+//! 循环执行取出消息时把队列中的值一次全部交给回调。
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::sync::mpsc::{SendError, TrySendError};
+
+use super::ObjectHandle;
+
+/// 绑定到循环的回调，随对象释放时关闭通道
+struct Callback<T: Send + 'static> {
+    f: RefCell<Box<FnMut(T)>>,
+    inner: Weak<Inner<T>>,
+}
+
+impl<T: Send + 'static> Drop for Callback<T> {
+    fn drop(&mut self) {
+        // 所有值都已交给回调，或者循环已经结束
+        close(&self.inner);
+    }
+}
+
+struct Inner<T: Send + 'static> {
+    state: Mutex<State<T>>,
+    /// 有界通道的队列有空位或接收端关闭时通知
+    not_full: Condvar,
+}
+
+struct State<T: Send + 'static> {
+    items: VecDeque<T>,
+    capacity: Option<usize>,
+    senders: usize,
+    /// 接收端已释放，或者绑定的循环已经结束
+    closed: bool,
+    /// 已经投递了取出消息，尚未执行
+    scheduled: bool,
+    /// `for_each` 绑定的回调
+    bound: Option<ObjectHandle<Callback<T>>>,
+}
+
+impl<T: Send + 'static> State<T> {
+    fn is_full(&self) -> bool {
+        self.capacity.is_some_and(|cap| self.items.len() >= cap)
+    }
+}
+
+pub fn channel<T: Send + 'static>(capacity: Option<usize>) -> (LoopSender<T>, LoopReceiver<T>) {
+    let inner = Arc::new(Inner {
+        state: Mutex::new(State {
+            items: VecDeque::new(),
+            // 容量为 0 的通道无法发送，按 1 处理
+            capacity: capacity.map(|cap| cap.max(1)),
+            senders: 1,
+            closed: false,
+            scheduled: false,
+            bound: None,
+        }),
+        not_full: Condvar::new(),
+    });
+    (LoopSender { inner: inner.clone() }, LoopReceiver { inner: Some(inner) })
+}
+
+/// 发送端，可以复制到多个线程
+pub struct LoopSender<T: Send + 'static> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T: Send + 'static> LoopSender<T> {
+    /// 发送值，有界通道已满时阻塞到有空位，接收端已经不存在时返回原值
+    pub fn send(&self, t: T) -> Result<(), SendError<T>> {
+        let mut state = self.inner.state.lock().unwrap();
+        loop {
+            if state.closed {
+                return Err(SendError(t));
+            }
+            if !state.is_full() {
+                break;
+            }
+            state = self.inner.not_full.wait(state).unwrap();
+        }
+        push(&self.inner, &mut state, t);
+        Ok(())
+    }
+
+    /// 发送值，有界通道已满时立即返回 `TrySendError::Full`
+    pub fn try_send(&self, t: T) -> Result<(), TrySendError<T>> {
+        let mut state = self.inner.state.lock().unwrap();
+        if state.closed {
+            return Err(TrySendError::Disconnected(t));
+        }
+        if state.is_full() {
+            return Err(TrySendError::Full(t));
+        }
+        push(&self.inner, &mut state, t);
+        Ok(())
+    }
+}
+
+impl<T: Send + 'static> Clone for LoopSender<T> {
+    fn clone(&self) -> Self {
+        self.inner.state.lock().unwrap().senders += 1;
+        LoopSender { inner: self.inner.clone() }
+    }
+}
+
+impl<T: Send + 'static> Drop for LoopSender<T> {
+    fn drop(&mut self) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.senders -= 1;
+        // 最后一个发送端释放后，取出剩余的值并释放回调
+        if state.senders == 0 && !state.scheduled {
+            schedule(&self.inner, &mut state);
+        }
+    }
+}
+
+/// 接收端，`for_each` 之前释放时通道中的值随之释放
+pub struct LoopReceiver<T: Send + 'static> {
+    /// `for_each` 取走后为 None
+    inner: Option<Arc<Inner<T>>>,
+}
+
+impl<T: Send + 'static> LoopReceiver<T> {
+    /// 绑定到当前线程的循环，通道中的值按发送的顺序交给 f
+    ///
+    /// 所有发送端释放并且值都交给 f 之后 f 随之释放。循环结束时通道关闭，剩余的值随之释放。
+    pub fn for_each<F>(mut self, f: F) where F: FnMut(T) + 'static {
+        // 已经绑定，之后由回调对象关闭通道
+        let inner = self.inner.take().unwrap();
+        let obj = super::new_object(Callback {
+            f: RefCell::new(Box::new(f)),
+            inner: Arc::downgrade(&inner),
+        });
+        let mut state = inner.state.lock().unwrap();
+        state.bound = Some(obj);
+        if !state.items.is_empty() || state.senders == 0 {
+            schedule(&inner, &mut state);
+        }
+    }
+}
+
+impl<T: Send + 'static> Drop for LoopReceiver<T> {
+    fn drop(&mut self) {
+        if let Some(ref inner) = self.inner {
+            close(&Arc::downgrade(inner));
+        }
+    }
+}
+
+/// 接收端不再存在：之后的发送都失败，释放剩余的值，唤醒阻塞的发送端
+fn close<T: Send + 'static>(inner: &Weak<Inner<T>>) {
+    if let Some(inner) = inner.upgrade() {
+        let items = {
+            let mut state = inner.state.lock().unwrap();
+            state.closed = true;
+            mem::take(&mut state.items)
+        };
+        inner.not_full.notify_all();
+        // 值的析构中可能使用同一个通道，不能持有锁
+        drop(items);
+    }
+}
+
+fn push<T: Send + 'static>(inner: &Arc<Inner<T>>, state: &mut State<T>, t: T) {
+    state.items.push_back(t);
+    if !state.scheduled && state.bound.is_some() {
+        schedule(inner, state);
+    }
+}
+
+/// 投递一条取出消息
+fn schedule<T: Send + 'static>(inner: &Arc<Inner<T>>, state: &mut State<T>) {
+    if let Some(ref obj) = state.bound {
+        state.scheduled = true;
+        let inner = inner.clone();
+        obj.post(move |cb| drain(&inner, cb));
+    }
+}
+
+/// 在绑定的循环上取出所有值交给回调
+fn drain<T: Send + 'static>(inner: &Inner<T>, cb: &Callback<T>) {
+    let (items, done) = {
+        let mut state = inner.state.lock().unwrap();
+        state.scheduled = false;
+        (mem::take(&mut state.items), state.senders == 0)
+    };
+    inner.not_full.notify_all();
+    let mut f = cb.f.borrow_mut();
+    for t in items {
+        f(t);
+    }
+    drop(f);
+    // 发送端都已释放，不会再有新的值
+    if done {
+        let bound = inner.state.lock().unwrap().bound.take();
+        drop(bound);
+    }
+}
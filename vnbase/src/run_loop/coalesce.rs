@@ -0,0 +1,88 @@
+//! 按键合并的消息，见 `Handle::post_coalesced`
+//!
+//! 队列中的消息不能替换，同一个键只在队列中放一个转发消息，函数保存在这里；
+//! 再次投递时只替换保存的函数，转发消息执行时取出最后一次投递的函数执行。
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::mem;
+
+/// 类型擦除的键，不同类型的键互不相等
+trait Key: Send {
+    fn as_any(&self) -> &Any;
+    fn key_eq(&self, other: &Key) -> bool;
+    fn key_hash(&self) -> u64;
+}
+
+impl<K> Key for K where K: Eq + Hash + Send + 'static {
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn key_eq(&self, other: &Key) -> bool {
+        other.as_any().downcast_ref::<K>() == Some(self)
+    }
+
+    fn key_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        TypeId::of::<K>().hash(&mut hasher);
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+type Task = Box<FnOnce() + Send>;
+
+/// 等待执行的合并消息
+pub struct Coalesced {
+    /// 键的哈希值对应的转发消息
+    tokens: HashMap<u64, Vec<u64>>,
+    /// 转发消息对应的键和最后一次投递的函数
+    pending: HashMap<u64, (Box<Key>, Task)>,
+    next_token: u64,
+}
+
+impl Coalesced {
+    pub fn new() -> Coalesced {
+        Coalesced {
+            tokens: HashMap::new(),
+            pending: HashMap::new(),
+            next_token: 0,
+        }
+    }
+
+    /// 保存 key 对应的函数，返回需要投递的转发消息的标识；
+    /// 已有等待执行的函数时替换它并返回被替换的函数，由调用者在解锁后释放
+    pub fn insert<K>(&mut self, key: K, f: Task) -> Result<u64, Task>
+        where K: Eq + Hash + Send + 'static {
+        let hash = key.key_hash();
+        let tokens = self.tokens.entry(hash).or_default();
+        for token in tokens.iter() {
+            let entry = self.pending.get_mut(token).unwrap();
+            if key.key_eq(&*entry.0) {
+                return Err(mem::replace(&mut entry.1, f));
+            }
+        }
+        self.next_token += 1;
+        let token = self.next_token;
+        tokens.push(token);
+        self.pending.insert(token, (Box::new(key), f));
+        Ok(token)
+    }
+
+    /// 转发消息执行时取出函数，之后同一个键的投递重新排队
+    pub fn take(&mut self, token: u64) -> Option<Task> {
+        let (key, f) = self.pending.remove(&token)?;
+        let hash = key.key_hash();
+        let empty = {
+            let tokens = self.tokens.get_mut(&hash).unwrap();
+            tokens.retain(|&t| t != token);
+            tokens.is_empty()
+        };
+        if empty {
+            self.tokens.remove(&hash);
+        }
+        Some(f)
+    }
+}
@@ -0,0 +1,59 @@
+use std::marker::PhantomData;
+
+use super::{Handle, Timer, Schedule, ObjectHandle};
+
+/// 当前线程循环的上下文，传给 `with_callback_ctx` 注册的回调
+///
+/// 方法和 `run_loop` 中的同名函数相同，只是把对当前线程循环的依赖显式地写在参数中。
+/// 上下文只在回调执行期间有效，不能发送到其它线程。
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop;
+/// use std::time::Duration;
+///
+/// let _timer = run_loop::new_timer()
+///     .with_callback_ctx(|ctx| {
+///         let handle = ctx.clone_handle();
+///         ctx.post(move || handle.stop());
+///     })
+///     .and_start(Duration::from_millis(10));
+///
+/// run_loop::run();
+/// ```
+pub struct Context {
+    phantom: PhantomData<*const ()>,
+}
+
+impl Context {
+    pub(super) fn new() -> Self {
+        Context {
+            phantom: PhantomData,
+        }
+    }
+
+    /// 向当前线程的循环投递函数
+    pub fn post<T>(&self, msg: T) where T: FnOnce() + 'static + Send {
+        super::clone_handle().post(msg);
+    }
+
+    pub fn stop(&self) {
+        super::stop();
+    }
+
+    pub fn clone_handle(&self) -> Handle {
+        super::clone_handle()
+    }
+
+    pub fn new_timer(&self) -> Timer {
+        super::new_timer()
+    }
+
+    pub fn new_schedule(&self) -> Schedule {
+        super::new_schedule()
+    }
+
+    pub fn new_object<T>(&self, obj: T) -> ObjectHandle<T> where T: 'static {
+        super::new_object(obj)
+    }
+}
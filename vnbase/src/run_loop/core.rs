@@ -1,113 +1,555 @@
 
-use std::sync::{Mutex, Condvar};
+use std::sync::{Mutex, MutexGuard, Condvar, PoisonError};
+use std::sync::atomic::{self, AtomicU8, AtomicU64, Ordering};
 use std::rc::Rc;
-use std::cell::Cell;
-use std::time::Instant;
+use std::cell::{Cell, RefCell};
+use std::time::Duration;
 use std::ptr;
+use std::cmp::Reverse;
+use std::thread::{self, Thread};
+use std::sync::Arc;
+
+use super::Instant;
+use super::shards::Shards;
+use super::wheel::TimerWheel;
+use super::coalesce::Coalesced;
+#[cfg(feature = "futex")]
+use super::futex::{self, Futex};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// `Core::sleeping` 的取值
+const AWAKE: u8 = 0;
+/// 循环可能正在等待，投递后需要加锁检查是否唤醒
+const SLEEPING: u8 = 1;
+/// 循环可能正在 futex 上等待，投递后不加锁直接唤醒
+#[cfg(feature = "futex")]
+const ON_FUTEX: u8 = 2;
+/// 循环已经销毁，投递的函数直接丢弃
+const DEAD: u8 = 3;
+
+/// 到期时间距离现在的上限，更长的时长视为永不到期
+const FAR_FUTURE: Duration = Duration::from_secs(100 * 365 * 24 * 3600);
+
+/// base 之后 d 的时刻，d 超过约一百年时按一百年计算，不会溢出
+pub fn saturating_deadline(base: Instant, d: Duration) -> Instant {
+    base + d.min(FAR_FUTURE)
+}
+
+/// 加锁，其它线程持有锁时 panic 也照常取得锁
+///
+/// 这些锁保护的状态在修改的每一步都是完整的，持有锁时被中断不会留下无效的状态，
+/// 没有必要让一次 panic 使之后所有的投递和等待都跟着 panic。
+pub fn lock<T: ?Sized>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// 调用 f，f panic 时先以 reset 恢复 cell 中的状态再继续展开
+///
+/// 定时器和周期任务执行回调时处于 Processing 状态，已经不在堆中，panic 之后没有人会再改变这个状态。
+pub fn reset_on_unwind<S, G, F, R>(cell: &RefCell<S>, reset: G, f: F) -> R where G: FnOnce(&mut S), F: FnOnce() -> R {
+    struct Guard<'a, S: 'a, G: FnOnce(&mut S)> {
+        cell: &'a RefCell<S>,
+        reset: Option<G>,
+    }
+
+    impl<'a, S, G> Drop for Guard<'a, S, G> where G: FnOnce(&mut S) {
+        fn drop(&mut self) {
+            if let Some(reset) = self.reset.take() {
+                reset(&mut self.cell.borrow_mut());
+            }
+        }
+    }
+
+    let mut guard = Guard { cell: cell, reset: Some(reset) };
+    let r = f();
+    guard.reset = None;
+    r
+}
 
 pub struct Core {
-    pub msgs: Mutex<MsgQueue>,
+    pub queue: Shards,
+    /// 等待和唤醒的状态，投递消息不需要加锁
+    pub ctrl: Mutex<Control>,
     pub cond: Condvar,
+    #[cfg(feature = "futex")]
+    pub futex: Futex,
+    sleeping: AtomicU8,
+    /// `Handle::post_coalesced` 投递的等待执行的函数
+    pub coalesced: Mutex<Coalesced>,
+    pub id: u64,
+    /// 循环的名字，默认为循环所在线程的名字，见 `run_loop::set_name`
+    pub name: Mutex<Option<String>>,
+    /// 循环销毁后释放队列中的消息时持有，保证同时只有一个线程取出
+    clearing: Mutex<()>,
 }
 
 impl Core {
-    pub fn new() -> Core {
+    pub fn new(shards: usize) -> Core {
         Core {
-            msgs: Mutex::new(MsgQueue::new()),
+            queue: Shards::new(shards),
+            ctrl: Mutex::new(Control::new()),
             cond: Condvar::new(),
+            #[cfg(feature = "futex")]
+            futex: Futex::new(),
+            sleeping: AtomicU8::new(AWAKE),
+            coalesced: Mutex::new(Coalesced::new()),
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            name: Mutex::new(thread::current().name().map(String::from)),
+            clearing: Mutex::new(()),
         }
     }
 
+    /// 取得 ctrl 的锁，见 `lock`
+    pub fn lock(&self) -> MutexGuard<'_, Control> {
+        lock(&self.ctrl)
+    }
+
+    /// 投递函数，循环已经销毁时直接丢弃
     pub fn post<T>(&self, msg: T) where T: FnOnce() + Send + 'static {
-        let mut msgs = self.msgs.lock().unwrap();
-        msgs.push(msg);
-        if msgs.state == State::Waiting {
-            msgs.state = State::MsgArrived;
-            self.cond.notify_one();
+        let _ = self.try_post(msg);
+    }
+
+    /// 投递函数，循环已经销毁时返回原函数
+    ///
+    /// 和循环的销毁同时投递的函数由投递者释放，不会留在队列中。
+    pub fn try_post<T>(&self, msg: T) -> Result<(), T> where T: FnOnce() + Send + 'static {
+        if self.is_dead() {
+            return Err(msg);
+        }
+        self.queue.push(msg);
+        self.notify();
+        self.clear_if_dead();
+        Ok(())
+    }
+
+    /// 依次投递多个函数，只检查一次是否需要唤醒，循环已经销毁时返回原函数
+    pub fn try_post_all(&self, msgs: Vec<Box<FnOnce() + Send>>) -> Result<(), Vec<Box<FnOnce() + Send>>> {
+        if self.is_dead() {
+            return Err(msgs);
+        }
+        if msgs.is_empty() {
+            return Ok(());
+        }
+        for msg in msgs {
+            self.queue.push(msg);
+        }
+        self.notify();
+        self.clear_if_dead();
+        Ok(())
+    }
+
+    /// 入队后循环已经销毁时，由投递者释放队列中的消息
+    ///
+    /// 和 `kill` 配对：要么这里看到 DEAD，要么循环销毁时的 `clear` 看到这条消息。
+    fn clear_if_dead(&self) {
+        if self.is_dead() {
+            self.clear();
+        }
+    }
+
+    /// 循环销毁后释放队列中的所有消息，可以在任意线程调用
+    ///
+    /// 释放的消息中再次投递到本循环时直接丢弃，不会重入。
+    pub fn clear(&self) {
+        debug_assert!(self.is_dead());
+        let _guard = lock(&self.clearing);
+        atomic::fence(Ordering::SeqCst);
+        // 循环不再取出消息，锁保证同时只有一个线程取出
+        unsafe { self.queue.clear(); }
+    }
+
+    /// 消息入队后唤醒可能正在等待的循环
+    fn notify(&self) {
+        // 和 `prepare_wait` 配对：要么这里看到 sleeping，要么循环在等待前看到这条消息
+        match self.sleeping.load(Ordering::SeqCst) {
+            AWAKE => {},
+            #[cfg(feature = "futex")]
+            ON_FUTEX => {
+                // 只由一个投递者唤醒
+                if self.sleeping.compare_exchange(ON_FUTEX, AWAKE, Ordering::SeqCst, Ordering::Relaxed).is_ok() {
+                    self.futex.wake();
+                }
+            },
+            _ => {
+                let mut ctrl = self.lock();
+                if ctrl.state == State::Waiting {
+                    ctrl.state = State::MsgArrived;
+                    self.wake(&ctrl);
+                }
+                else if ctrl.state == State::External {
+                    self.wake_external(&mut ctrl);
+                }
+            },
         }
     }
 
+    /// 循环销毁时调用，之后的投递都被丢弃
+    pub fn kill(&self) {
+        let mut ctrl = self.lock();
+        ctrl.state = State::Dead;
+        self.sleeping.store(DEAD, Ordering::SeqCst);
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.sleeping.load(Ordering::SeqCst) == DEAD
+    }
+
     pub fn stop(&self) {
-        let mut msgs = self.msgs.lock().unwrap();
-        if msgs.state == State::Waiting {
-            self.cond.notify_one();
+        let mut ctrl = self.lock();
+        if ctrl.state == State::Dead {
+            return;
+        }
+        if ctrl.state == State::Waiting {
+            self.wake(&ctrl);
+        }
+        else if ctrl.state == State::External {
+            self.wake_external(&mut ctrl);
         }
-        msgs.state = State::Stopping;
+        ctrl.state = State::Stopping;
     }
-}
 
-pub struct MsgQueue {
-    list: Option<(Box<Action>, *mut Action)>,
-    pub state: State,
-}
+    /// 由外部事件循环驱动时，处理完一轮后进入空闲，之后第一次投递或退出请求调用唤醒函数
+    ///
+    /// 没有设置唤醒函数时不做处理；已有消息时不进入空闲，由调用者安排立即再处理一轮。
+    pub fn prepare_external_wait(&self, ctrl: &mut Control) {
+        if ctrl.external.is_none() {
+            return;
+        }
+        ctrl.state = State::External;
+        self.sleeping.store(SLEEPING, Ordering::SeqCst);
+        if !self.queue.is_empty() {
+            self.leave_external(ctrl);
+        }
+    }
 
-unsafe impl Send for MsgQueue {}
+    /// 离开外部驱动的空闲，循环可以重新开始处理
+    pub fn leave_external(&self, ctrl: &mut Control) {
+        if ctrl.state == State::External {
+            ctrl.state = State::Stopped;
+            self.sleeping.store(AWAKE, Ordering::Relaxed);
+        }
+    }
 
-impl MsgQueue {
-    fn new() -> MsgQueue {
-        MsgQueue {
-            list: None,
-            state: State::Stopped,
+    fn wake_external(&self, ctrl: &mut Control) {
+        self.leave_external(ctrl);
+        if let Some(ref f) = ctrl.external {
+            f();
+        }
+    }
+
+    /// 在持有锁时准备等待，等待前已有消息到达时返回 false，不应再等待
+    pub fn prepare_wait(&self, ctrl: &mut Control) -> bool {
+        ctrl.state = State::Waiting;
+        let sleeping = self.sleep_mode(ctrl);
+        self.sleeping.store(sleeping, Ordering::SeqCst);
+        if self.queue.is_empty() {
+            true
+        }
+        else {
+            self.end_wait(ctrl);
+            false
         }
     }
 
-    pub fn drain(&mut self) -> Option<Box<Action>> {
-        match self.list.take() {
-            Some((head,_)) => Some(head),
-            None => None,
+    /// 选择等待方式，使用 futex 时在公布等待之前读取唤醒序号，之后的唤醒都会改变它
+    #[cfg(feature = "futex")]
+    fn sleep_mode(&self, ctrl: &mut Control) -> u8 {
+        let available = futex::SUPPORTED && ctrl.park.is_none();
+        #[cfg(feature = "io")]
+        let available = available && ctrl.waker.is_none();
+        #[cfg(all(windows, feature = "windows-pump"))]
+        let available = available && ctrl.pump.is_none();
+        if available {
+            ctrl.futex_seq = Some(self.futex.seq());
+            ON_FUTEX
+        }
+        else {
+            SLEEPING
         }
     }
 
-    fn push<T>(&mut self, t: T) where T: FnOnce() + Send + 'static {
-        let mut node: Box<Action> = Box::new(ActionNode {
-            f: Some(t), next: None,
-        });
-        let tail = node.as_mut() as *mut _;
-        match self.list {
-            Some((_, ref mut t)) => unsafe {
-                 (**t).set_next(node); *t = tail;
-            },
-            None => self.list = Some((node, tail)),
+    #[cfg(not(feature = "futex"))]
+    fn sleep_mode(&self, _: &mut Control) -> u8 {
+        SLEEPING
+    }
+
+    /// 结束等待，state 为 Waiting 或 MsgArrived
+    pub fn end_wait(&self, ctrl: &mut Control) {
+        ctrl.state = State::Running;
+        self.sleeping.store(AWAKE, Ordering::Relaxed);
+        #[cfg(feature = "futex")]
+        {
+            ctrl.futex_seq = None;
+        }
+    }
+
+    fn wake(&self, ctrl: &Control) {
+        #[cfg(feature = "io")]
+        {
+            if let Some(ref waker) = ctrl.waker {
+                waker.wake().expect("failed to wake run loop");
+                return;
+            }
+        }
+        #[cfg(feature = "futex")]
+        {
+            if ctrl.futex_seq.is_some() {
+                self.futex.wake();
+                return;
+            }
         }
+        #[cfg(all(windows, feature = "windows-pump"))]
+        {
+            if let Some(ref event) = ctrl.pump {
+                event.set();
+                return;
+            }
+        }
+        if let Some(ref thread) = ctrl.park {
+            thread.unpark();
+            return;
+        }
+        self.cond.notify_one();
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
-pub enum State {
-    Stopped,
-    Stopping,
-    Running,
-    Waiting,
-    MsgArrived,
+/// 循环的运行状态和等待方式
+pub struct Control {
+    pub state: State,
+    /// 使用 `WaitStrategy::Park` 时循环所在的线程
+    pub park: Option<Thread>,
+    /// 正在 futex 上等待时，等待前读取的唤醒序号
+    #[cfg(feature = "futex")]
+    pub futex_seq: Option<u32>,
+    #[cfg(feature = "io")]
+    pub waker: Option<Arc<super::wakeup::Wakeup>>,
+    /// 由外部事件循环驱动时的唤醒函数，见 `run_loop::set_external_waker`
+    pub external: Option<Arc<Fn() + Send + Sync>>,
+    /// 使用 `WaitStrategy::MessagePump` 时唤醒循环的事件
+    #[cfg(all(windows, feature = "windows-pump"))]
+    pub pump: Option<Arc<super::win_pump::PumpEvent>>,
 }
 
-pub trait Action : Send {
-    fn process(&mut self) -> Option<Box<Action>>;
-    fn set_next(&mut self, msg: Box<Action>);
+impl Control {
+    fn new() -> Control {
+        Control {
+            state: State::Stopped,
+            park: None,
+            #[cfg(feature = "futex")]
+            futex_seq: None,
+            #[cfg(feature = "io")]
+            waker: None,
+            external: None,
+            #[cfg(all(windows, feature = "windows-pump"))]
+            pump: None,
+        }
+    }
 }
 
-struct ActionNode<T> {
-    f: Option<T>,
-    next: Option<Box<Action>>,
+/// 循环管理定时器和周期历程的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerStrategy {
+    /// 二叉堆，精确按到期时间排序，默认方式
+    Heap,
+    /// 以给定的精度分刻度的分层时间轮，启动、调整和取消都是常数时间，适合数以万计的定时器
+    ///
+    /// 到期时间向上取整到刻度，定时器最多推迟一个精度触发；同时到期的定时器之间不保证先后。
+    Wheel(Duration),
 }
 
-impl<T> Action for ActionNode<T> where T: FnOnce() + Send {
-    fn process(&mut self) -> Option<Box<Action>> {
-        match self.f.take() {
-            Some(t) => t(),
-            None => unreachable!(),
+/// 按 `TimerStrategy` 选择的定时器容器
+pub enum TimerQueue {
+    Heap(TimedActionBinaryHeap),
+    Wheel(TimerWheel),
+}
+
+impl TimerQueue {
+    pub fn new(strategy: TimerStrategy) -> TimerQueue {
+        match strategy {
+            TimerStrategy::Heap => TimerQueue::Heap(TimedActionBinaryHeap::new()),
+            TimerStrategy::Wheel(resolution) => TimerQueue::Wheel(TimerWheel::new(resolution)),
+        }
+    }
+
+    pub fn strategy(&self) -> TimerStrategy {
+        match *self {
+            TimerQueue::Heap(_) => TimerStrategy::Heap,
+            TimerQueue::Wheel(ref w) => TimerStrategy::Wheel(w.resolution()),
+        }
+    }
+
+    pub fn push(&mut self, act: Rc<TimedAction>, time: Instant) {
+        debug_assert!(!act.node().scheduled.get(), "timed action pushed twice");
+        act.node().scheduled.set(true);
+        match *self {
+            TimerQueue::Heap(ref mut h) => h.push(act, time),
+            TimerQueue::Wheel(ref mut w) => w.push(act, time),
+        }
+    }
+
+    /// 返回一个在 time 之前到期的定时器，不从容器中移除
+    pub fn peek(&mut self, time: Instant) -> Option<Rc<TimedAction>> {
+        match *self {
+            TimerQueue::Heap(ref h) => h.peek(time),
+            TimerQueue::Wheel(ref mut w) => w.peek(time),
+        }
+    }
+
+    /// 取出一个在 time 之前到期的定时器，标记为等待处理
+    ///
+    /// 处理之前取消的定时器清除标记，重新启动的只更新到期时间，由调用者检查。
+    pub fn pop_due(&mut self, time: Instant) -> Option<Rc<TimedAction>> {
+        let act = self.peek(time)?;
+        self.remove(act.node());
+        act.node().due.set(true);
+        Some(act)
+    }
+
+    pub fn len(&self) -> usize {
+        match *self {
+            TimerQueue::Heap(ref h) => h.len(),
+            TimerQueue::Wheel(ref w) => w.len(),
+        }
+    }
+
+    /// 预留空间，使用时间轮时不做处理
+    pub fn reserve(&mut self, additional: usize) {
+        if let TimerQueue::Heap(ref mut h) = *self {
+            h.reserve(additional);
+        }
+    }
+
+    /// 不再分配内存能容纳的定时器数，使用时间轮时等于定时器数
+    pub fn capacity(&self) -> usize {
+        match *self {
+            TimerQueue::Heap(ref h) => h.capacity(),
+            TimerQueue::Wheel(ref w) => w.len(),
         }
-        self.next.take()
     }
 
-    fn set_next(&mut self, msg: Box<Action>) {
-        self.next = Some(msg);
+    /// 定时器远少于容量时释放多余的空间，使用时间轮时不做处理
+    pub fn compact(&mut self) {
+        if let TimerQueue::Heap(ref mut h) = *self {
+            h.compact();
+        }
+    }
+
+    pub fn count_periodic(&self) -> usize {
+        match *self {
+            TimerQueue::Heap(ref h) => h.count_periodic(),
+            TimerQueue::Wheel(ref w) => w.count_periodic(),
+        }
+    }
+
+    /// 下一次需要处理定时器的时刻，使用时间轮时不晚于最早的到期时间
+    pub fn peek_time(&self) -> Option<Instant> {
+        match *self {
+            TimerQueue::Heap(ref h) => h.peek_time(),
+            TimerQueue::Wheel(ref w) => w.peek_time(),
+        }
+    }
+
+    /// 不在容器中的节点不做处理
+    pub fn adjust(&mut self, node: &TimedActionNode, time: Instant) {
+        if !node.scheduled.get() {
+            if node.due.get() {
+                node.time.set(time);
+            }
+            return;
+        }
+        match *self {
+            TimerQueue::Heap(ref mut h) => h.adjust(node, time),
+            TimerQueue::Wheel(ref mut w) => w.adjust(node, time),
+        }
     }
+
+    /// 不在容器中的节点不做处理，重复移除不会影响其它定时器
+    pub fn remove(&mut self, node: &TimedActionNode) {
+        if !node.scheduled.replace(false) {
+            node.due.set(false);
+            return;
+        }
+        match *self {
+            TimerQueue::Heap(ref mut h) => h.remove(node),
+            TimerQueue::Wheel(ref mut w) => w.remove(node),
+        }
+    }
+
+    /// 改用另一种方式，已经启动的定时器按原来的到期时间移入
+    pub fn set_strategy(&mut self, strategy: TimerStrategy) {
+        if self.strategy() == strategy {
+            return;
+        }
+        let all = match *self {
+            TimerQueue::Heap(ref mut h) => h.drain(),
+            TimerQueue::Wheel(ref mut w) => w.drain(),
+        };
+        *self = TimerQueue::new(strategy);
+        for act in all {
+            act.node().scheduled.set(false);
+            let time = act.node().time.get();
+            self.push(act, time);
+        }
+    }
+}
+
+/// 循环没有消息和到期的定时器时的等待方式
+///
+/// 启用 io 的循环由 `poll` 等待，不受此设置影响。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitStrategy {
+    /// 在条件变量上等待，默认方式
+    Condvar,
+    /// 使用 `thread::park` 等待，投递和退出时 `unpark` 循环所在的线程
+    ///
+    /// 其它模块对该线程的 `unpark` 也会让循环醒来重新检查消息和定时器。
+    Park,
+    /// 使用 `MsgWaitForMultipleObjectsEx` 等待，同时取出并分发线程的窗口消息，需要开启 `windows-pump` 特性
+    ///
+    /// 用于拥有窗口的线程，回调和窗口过程在同一个线程交替执行。取到 `WM_QUIT` 时循环退出。
+    #[cfg(all(windows, feature = "windows-pump"))]
+    MessagePump,
+}
+
+/// `run_loop::run_pending` 处理完一轮后，外部事件循环接下来应当如何等待
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PumpStatus {
+    /// 没有到期时间，等待唤醒函数被调用
+    Wait,
+    /// 最迟在这个时刻再调用一次，唤醒函数被调用时提前
+    WaitUntil(Instant),
+    /// 循环被要求退出
+    Stop,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum State {
+    Stopped,
+    Stopping,
+    Running,
+    Waiting,
+    MsgArrived,
+    /// 由外部事件循环驱动，处理完一轮后空闲
+    External,
+    /// 循环已经销毁
+    Dead,
 }
 
 pub struct TimedActionNode {
-    time: Cell<Instant>,
-    index: Cell<usize>,
+    pub time: Cell<Instant>,
+    /// 在堆或者时间轮的槽中的位置
+    pub index: Cell<usize>,
+    /// 使用时间轮时所在的槽
+    pub slot: Cell<usize>,
+    /// 是否在堆或者时间轮中，由 `TimerQueue` 维护
+    pub scheduled: Cell<bool>,
+    /// 已经到期取出，等待本轮处理
+    pub due: Cell<bool>,
+    /// 到期时间相同时优先级高的先执行
+    pub priority: Cell<i32>,
+    /// 加入堆的顺序，到期时间和优先级都相同时先加入的先执行
+    pub seq: Cell<u64>,
 }
 
 impl TimedActionNode {
@@ -115,23 +557,43 @@ impl TimedActionNode {
         TimedActionNode {
             time: Cell::new(Instant::now()),
             index: Cell::new(0),
+            slot: Cell::new(0),
+            scheduled: Cell::new(false),
+            due: Cell::new(false),
+            priority: Cell::new(0),
+            seq: Cell::new(0),
         }
     }
+
+    /// 在堆中比较的键：到期时间、优先级（高的在前）和加入的顺序
+    fn key(&self) -> (Instant, Reverse<i32>, u64) {
+        (self.time.get(), Reverse(self.priority.get()), self.seq.get())
+    }
 }
 
 pub trait TimedAction {
     fn node(&self) -> &TimedActionNode;
     fn process(&self) -> Option<Instant>;
+
+    /// 是否是周期历程，仅用于统计
+    fn is_periodic(&self) -> bool {
+        false
+    }
 }
 
+/// 收缩堆时保留的最小容量
+const MIN_HEAP_CAPACITY: usize = 64;
+
 pub struct TimedActionBinaryHeap {
-    data: Vec<Rc<TimedAction>>
+    data: Vec<Rc<TimedAction>>,
+    next_seq: u64,
 }
 
 impl TimedActionBinaryHeap {
     pub fn new() -> TimedActionBinaryHeap {
         TimedActionBinaryHeap {
             data: Vec::new(),
+            next_seq: 0,
         }
     }
 
@@ -141,6 +603,7 @@ impl TimedActionBinaryHeap {
             let node = act.node();
             node.time.set(time);
             node.index.set(index);
+            node.seq.set(self.next_seq());
         }
         self.data.push(act);
         self.sift_up(index);
@@ -161,6 +624,11 @@ impl TimedActionBinaryHeap {
     }
     */
 
+    /// 取出所有定时器，到期时间保留在节点中
+    pub fn drain(&mut self) -> Vec<Rc<TimedAction>> {
+        ::std::mem::take(&mut self.data)
+    }
+
     pub fn peek(&self, time: Instant) -> Option<Rc<TimedAction>> {
         if self.data.is_empty() {
             None
@@ -176,6 +644,30 @@ impl TimedActionBinaryHeap {
         }
     }
 
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.data.reserve(additional);
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.data.capacity()
+    }
+
+    /// 元素不到容量的四分之一时收缩到两倍元素数，只改变容量，不影响堆的顺序
+    pub fn compact(&mut self) {
+        let len = self.data.len();
+        if self.data.capacity() > MIN_HEAP_CAPACITY && len * 4 < self.data.capacity() {
+            self.data.shrink_to((len * 2).max(MIN_HEAP_CAPACITY));
+        }
+    }
+
+    pub fn count_periodic(&self) -> usize {
+        self.data.iter().filter(|ta| ta.is_periodic()).count()
+    }
+
     pub fn peek_time(&self) -> Option<Instant> {
         if self.data.is_empty() {
             None
@@ -186,26 +678,52 @@ impl TimedActionBinaryHeap {
     }
 
     pub fn adjust(&mut self, node: &TimedActionNode, time: Instant) {
-        node.time.set(time);
         let index = node.index.get();
+        if !self.holds(index, node) {
+            return;
+        }
+        node.time.set(time);
+        node.seq.set(self.next_seq());
         if self.sift_up(index) == index {
             self.sift_down(index);
         }
     }
 
+    fn next_seq(&mut self) -> u64 {
+        self.next_seq += 1;
+        self.next_seq
+    }
+
     pub fn remove(&mut self, node: &TimedActionNode) {
         let index = node.index.get();
+        if !self.holds(index, node) {
+            return;
+        }
         let last = self.data.len() - 1;
         if index == last {
             self.data.pop();
+            // 全部取消或者触发后释放多余的空间
+            if self.data.is_empty() {
+                self.compact();
+            }
         }
         else {
             unsafe { self.swap(index, last); }
             self.data.pop();
-            self.sift_down(index);
+            // 移来的节点可能比原来的父节点更早到期
+            if self.sift_up(index) == index {
+                self.sift_down(index);
+            }
         }
     }
 
+    /// index 处是否是 node，不是时说明节点已经不在堆中
+    fn holds(&self, index: usize, node: &TimedActionNode) -> bool {
+        let found = self.data.get(index).is_some_and(|ta| ::std::ptr::eq(ta.node(), node));
+        debug_assert!(found, "timed action is not in the heap");
+        found
+    }
+
     fn sift_up(&mut self, index: usize) -> usize {
         let mut index = index;
         unsafe {
@@ -214,7 +732,7 @@ impl TimedActionBinaryHeap {
                 {
                     let parent_node = self.data.get_unchecked(parent).node();
                     let index_node = self.data.get_unchecked(index).node();
-                    if index_node.time >= parent_node.time {
+                    if index_node.key() >= parent_node.key() {
                         break;
                     }
                 }
@@ -239,14 +757,14 @@ impl TimedActionBinaryHeap {
                     let right = child + 1;
                     if right < end {
                         let right_node: *const _ = self.data.get_unchecked(right).node();
-                        if (*right_node).time < (*child_node).time {
+                        if (*right_node).key() < (*child_node).key() {
                             child = right;
                             child_node = right_node;
                         }
                     }
                     
                     let index_node = self.data.get_unchecked(index).node();
-                    if index_node.time < (*child_node).time {
+                    if index_node.key() < (*child_node).key() {
                         break;
                     }
                 }
@@ -0,0 +1,141 @@
+//! 供 C/C++ 代码向循环投递回调的接口，需要开启 `ffi` 特性
+//!
+//! 对应的 C 声明：
+//! ```c
+//! typedef struct VnHandle VnHandle;
+//!
+//! VnHandle *vn_handle_clone_current(void);
+//! int vn_handle_post(const VnHandle *handle, void (*cb)(void *), void *user_data);
+//! int vn_handle_stop(const VnHandle *handle);
+//! void vn_handle_release(VnHandle *handle);
+//! ```
+//!
+//! 句柄可以在任意线程使用，用完后由 `vn_handle_release` 释放。Rust 的 panic 不会越过这些函数，
+//! 发生时按失败返回。空指针按失败处理；句柄释放后不能再传入任何函数，重复释放是未定义行为。
+//!
+//! # Examples
+//! ```
+//! use vnbase::run_loop;
+//! use vnbase::run_loop::ffi::*;
+//! use std::os::raw::c_void;
+//! use std::ptr;
+//! use std::sync::atomic::{AtomicUsize, Ordering};
+//! use std::sync::mpsc;
+//! use std::thread;
+//!
+//! extern "C" fn incr(user_data: *mut c_void) {
+//!     let counter = unsafe { &*(user_data as *const AtomicUsize) };
+//!     counter.fetch_add(1, Ordering::SeqCst);
+//! }
+//!
+//! let (tx, rx) = mpsc::channel();
+//! let th = thread::spawn(move || {
+//!     tx.send(vn_handle_clone_current() as usize).unwrap();
+//!     run_loop::run();
+//! });
+//! let handle = rx.recv().unwrap() as *mut VnHandle;
+//!
+//! static COUNTER: AtomicUsize = AtomicUsize::new(0);
+//! let user_data = &COUNTER as *const AtomicUsize as *mut c_void;
+//! unsafe {
+//!     for _ in 0..100 {
+//!         assert_eq!(vn_handle_post(handle, Some(incr), user_data), 1);
+//!     }
+//!     while COUNTER.load(Ordering::SeqCst) < 100 {
+//!         thread::yield_now();
+//!     }
+//!     assert_eq!(vn_handle_stop(handle), 1);
+//!     th.join().unwrap();
+//!
+//!     // 循环已经结束
+//!     assert_eq!(vn_handle_post(handle, Some(incr), user_data), 0);
+//!     assert_eq!(vn_handle_post(handle, None, user_data), 0);
+//!     assert_eq!(vn_handle_post(ptr::null(), Some(incr), user_data), 0);
+//!     assert_eq!(vn_handle_stop(ptr::null()), 0);
+//!     vn_handle_release(handle);
+//!     vn_handle_release(ptr::null_mut());
+//! }
+//! ```
+use std::os::raw::{c_int, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use super::Handle;
+
+/// 交给 C 代码的不透明句柄
+pub struct VnHandle {
+    handle: Handle,
+}
+
+/// C 代码提供的回调和参数
+struct Callback {
+    cb: extern "C" fn(*mut c_void),
+    user_data: *mut c_void,
+}
+
+// user_data 交给哪个线程由 C 代码负责
+unsafe impl Send for Callback {}
+
+impl Callback {
+    fn call(self) {
+        (self.cb)(self.user_data)
+    }
+}
+
+unsafe fn handle_ref<'a>(handle: *const VnHandle) -> Option<&'a Handle> {
+    handle.as_ref().map(|h| &h.handle)
+}
+
+/// 取得当前线程循环的句柄，失败时返回空指针
+#[no_mangle]
+pub extern "C" fn vn_handle_clone_current() -> *mut VnHandle {
+    panic::catch_unwind(|| {
+        Box::into_raw(Box::new(VnHandle {
+            handle: super::clone_handle(),
+        }))
+    }).unwrap_or(ptr::null_mut())
+}
+
+/// 向循环投递 cb，在循环所在的线程以 user_data 调用，投递成功返回 1，否则返回 0
+///
+/// 返回 0 时 cb 不会被调用，user_data 仍由调用者释放。投递成功后循环在执行前结束时 cb 同样不会被调用。
+///
+/// # Safety
+/// handle 为空或者来自 `vn_handle_clone_current` 且尚未释放；cb 必须可以在循环所在的线程以 user_data 调用。
+#[no_mangle]
+pub unsafe extern "C" fn vn_handle_post(handle: *const VnHandle, cb: Option<extern "C" fn(*mut c_void)>, user_data: *mut c_void) -> c_int {
+    let (handle, cb) = match (handle_ref(handle), cb) {
+        (Some(handle), Some(cb)) => (handle, cb),
+        _ => return 0,
+    };
+    let callback = Callback {
+        cb: cb,
+        user_data: user_data,
+    };
+    let posted = panic::catch_unwind(AssertUnwindSafe(|| handle.try_post(move || callback.call()).is_ok()));
+    posted.unwrap_or(false) as c_int
+}
+
+/// 请求循环退出，见 `Handle::stop`，成功返回 1，句柄无效时返回 0
+///
+/// # Safety
+/// handle 为空或者来自 `vn_handle_clone_current` 且尚未释放。
+#[no_mangle]
+pub unsafe extern "C" fn vn_handle_stop(handle: *const VnHandle) -> c_int {
+    match handle_ref(handle) {
+        Some(handle) => panic::catch_unwind(AssertUnwindSafe(|| handle.stop())).is_ok() as c_int,
+        None => 0,
+    }
+}
+
+/// 释放句柄，空指针不做处理
+///
+/// # Safety
+/// handle 为空或者来自 `vn_handle_clone_current` 且尚未释放，释放后不能再使用，重复释放是未定义行为。
+#[no_mangle]
+pub unsafe extern "C" fn vn_handle_release(handle: *mut VnHandle) {
+    if handle.is_null() {
+        return;
+    }
+    let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(handle))));
+}
@@ -0,0 +1,79 @@
+//! 把 `std::sync::mpsc::Receiver` 收到的值转交给循环，见 `run_loop::forward_receiver`
+//!
+//! 转发线程阻塞在 `recv_timeout` 上，收到的值送入 `run_loop::channel`，由循环成批交给回调。
+//! 不能向别人的通道插入结束标记，停止时转发线程最多等待一个轮询间隔后退出。
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use super::LoopSender;
+
+/// 转发线程检查是否需要停止的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// 转发的控制句柄，释放时停止转发并等待转发线程退出，最多阻塞一个轮询间隔
+///
+/// 释放后回调不再被调用，接收端已经随转发线程释放。
+pub struct ForwardHandle {
+    stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ForwardHandle {
+    /// 停止转发并等待转发线程退出，同释放
+    pub fn stop(self) {
+        drop(self);
+    }
+
+    /// 发送端已经全部释放，或者已经停止
+    pub fn is_finished(&self) -> bool {
+        self.thread.as_ref().is_none_or(|t| t.is_finished())
+    }
+}
+
+impl Drop for ForwardHandle {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+pub fn forward<T, F>(rx: Receiver<T>, mut f: F) -> ForwardHandle where T: Send + 'static, F: FnMut(T) + 'static {
+    let stopped = Arc::new(AtomicBool::new(false));
+    // 不限容量：循环在 `stop` 中等待转发线程时，转发线程不能因为通道已满而阻塞
+    let (tx, loop_rx) = super::channel(None);
+    let s = stopped.clone();
+    loop_rx.for_each(move |t| {
+        if !s.load(Ordering::SeqCst) {
+            f(t);
+        }
+    });
+    let s = stopped.clone();
+    let thread = thread::Builder::new()
+        .name(String::from("vnbase-forward"))
+        .spawn(move || bridge(rx, tx, &s))
+        .expect("failed to spawn forward thread");
+    ForwardHandle {
+        stopped: stopped,
+        thread: Some(thread),
+    }
+}
+
+/// 发送端全部释放、停止或者循环结束时返回
+fn bridge<T: Send + 'static>(rx: Receiver<T>, tx: LoopSender<T>, stopped: &AtomicBool) {
+    while !stopped.load(Ordering::SeqCst) {
+        match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(t) => {
+                if tx.send(t).is_err() {
+                    return;
+                }
+            },
+            Err(RecvTimeoutError::Timeout) => {},
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
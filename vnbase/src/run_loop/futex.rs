@@ -0,0 +1,108 @@
+//! 以地址等待的唤醒原语，Linux 上使用 futex，Windows 上使用 `WaitOnAddress`
+//!
+//! 其它平台不支持，循环仍然使用条件变量。
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+pub const SUPPORTED: bool = cfg!(any(target_os = "linux", target_os = "android", windows));
+
+pub struct Futex {
+    /// 每次唤醒加一，等待者以此判断等待前是否已经被唤醒
+    seq: AtomicU32,
+}
+
+impl Futex {
+    pub fn new() -> Futex {
+        Futex {
+            seq: AtomicU32::new(0),
+        }
+    }
+
+    pub fn seq(&self) -> u32 {
+        self.seq.load(Ordering::Acquire)
+    }
+
+    pub fn wake(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        sys::wake(&self.seq);
+    }
+
+    /// seq 仍为 expected 时等待，直到被唤醒或超时，可能提前返回
+    pub fn wait(&self, expected: u32, timeout: Option<Duration>) {
+        sys::wait(&self.seq, expected, timeout);
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod sys {
+    use std::ptr;
+    use std::sync::atomic::AtomicU32;
+    use std::time::Duration;
+    use libc;
+
+    pub fn wait(word: &AtomicU32, expected: u32, timeout: Option<Duration>) {
+        let ts = timeout.map(|d| libc::timespec {
+            tv_sec: d.as_secs().min(libc::time_t::MAX as u64) as libc::time_t,
+            tv_nsec: d.subsec_nanos() as libc::c_long,
+        });
+        let ts_ptr = match ts {
+            Some(ref ts) => ts as *const libc::timespec,
+            None => ptr::null(),
+        };
+        // 超时、被信号打断或值已经改变时都直接返回，由调用者重新检查
+        unsafe {
+            libc::syscall(libc::SYS_futex, word as *const AtomicU32, libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG, expected, ts_ptr);
+        }
+    }
+
+    pub fn wake(word: &AtomicU32) {
+        unsafe {
+            libc::syscall(libc::SYS_futex, word as *const AtomicU32, libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG, 1);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::os::raw::c_void;
+    use std::sync::atomic::AtomicU32;
+    use std::time::Duration;
+
+    const INFINITE: u32 = 0xFFFF_FFFF;
+
+    #[link(name = "synchronization")]
+    extern "system" {
+        fn WaitOnAddress(address: *const c_void, compare: *const c_void, size: usize, ms: u32) -> i32;
+        fn WakeByAddressSingle(address: *const c_void);
+    }
+
+    pub fn wait(word: &AtomicU32, expected: u32, timeout: Option<Duration>) {
+        let ms = match timeout {
+            // 向上取整，避免在到期前醒来后空转
+            Some(d) => {
+                let ms = d.as_secs().saturating_mul(1000) + (d.subsec_nanos() as u64 + 999_999) / 1_000_000;
+                ms.min(INFINITE as u64 - 1) as u32
+            },
+            None => INFINITE,
+        };
+        unsafe {
+            WaitOnAddress(word as *const AtomicU32 as *const c_void, &expected as *const u32 as *const c_void, 4, ms);
+        }
+    }
+
+    pub fn wake(word: &AtomicU32) {
+        unsafe { WakeByAddressSingle(word as *const AtomicU32 as *const c_void); }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", windows)))]
+mod sys {
+    use std::sync::atomic::AtomicU32;
+    use std::time::Duration;
+
+    pub fn wait(_: &AtomicU32, _: u32, _: Option<Duration>) {
+        unreachable!();
+    }
+
+    pub fn wake(_: &AtomicU32) {}
+}
@@ -0,0 +1,112 @@
+//! 多个循环的统一关闭
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+
+use super::Handle;
+
+/// 一组运行在各自线程上的循环，按依赖的顺序或同时关闭
+///
+/// 关闭时向循环投递 `stop`，循环先处理完此前已经投递的消息再退出，然后等待线程结束。
+/// 组内不能包含当前线程的循环，否则关闭时会一直等待。
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop::{self, LoopGroup};
+/// use std::sync::{Arc, Mutex};
+///
+/// let log = Arc::new(Mutex::new(Vec::new()));
+/// let mut group = LoopGroup::new();
+/// let handles: Vec<_> = ["db", "service", "frontend"].iter().map(|&name| {
+///     let log = log.clone();
+///     group.spawn(move || run_loop::on_exit(move || log.lock().unwrap().push(format!("{} exited", name))))
+/// }).collect();
+///
+/// // 已经投递的消息在退出前处理
+/// for (h, name) in handles.iter().zip(["db", "service", "frontend"].iter()) {
+///     let log = log.clone();
+///     h.post(move || log.lock().unwrap().push(format!("{} drained", name)));
+/// }
+///
+/// group.shutdown_ordered().unwrap();
+/// let log = log.lock().unwrap();
+/// let exited: Vec<_> = log.iter().filter(|s| s.ends_with("exited")).cloned().collect();
+/// assert_eq!(exited, vec!["db exited", "service exited", "frontend exited"]);
+/// assert_eq!(log.len(), 6);
+/// assert!(log.iter().position(|s| s == "frontend drained") < log.iter().position(|s| s == "frontend exited"));
+/// ```
+pub struct LoopGroup {
+    loops: Vec<(Handle, JoinHandle<()>)>,
+}
+
+impl Default for LoopGroup {
+    fn default() -> Self {
+        LoopGroup::new()
+    }
+}
+
+impl LoopGroup {
+    pub fn new() -> Self {
+        LoopGroup {
+            loops: Vec::new(),
+        }
+    }
+
+    /// 加入已经在 thread 上运行的循环
+    pub fn add(&mut self, handle: Handle, thread: JoinHandle<()>) {
+        self.loops.push((handle, thread));
+    }
+
+    /// 在新线程上执行 init 后运行循环，加入组中并返回它的句柄
+    pub fn spawn<F>(&mut self, init: F) -> Handle where F: FnOnce() + Send + 'static {
+        let (tx, rx) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            tx.send(super::clone_handle()).unwrap();
+            drop(tx);
+            init();
+            super::run();
+        });
+        let handle = rx.recv().unwrap();
+        self.add(handle.clone(), thread);
+        handle
+    }
+
+    pub fn len(&self) -> usize {
+        self.loops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.loops.is_empty()
+    }
+
+    /// 按加入的顺序逐个关闭，前一个线程结束后才关闭下一个
+    ///
+    /// 有线程 panic 时仍然关闭其余的循环，返回第一个 panic。
+    pub fn shutdown_ordered(self) -> thread::Result<()> {
+        let mut result = Ok(());
+        for (handle, thread) in self.loops {
+            handle.post(super::stop);
+            let r = thread.join();
+            if result.is_ok() {
+                result = r;
+            }
+        }
+        result
+    }
+
+    /// 同时关闭所有循环，等待全部线程结束
+    ///
+    /// 有线程 panic 时返回第一个 panic。
+    pub fn shutdown_all(self) -> thread::Result<()> {
+        for (handle, _) in &self.loops {
+            handle.post(super::stop);
+        }
+        let mut result = Ok(());
+        for (_, thread) in self.loops {
+            let r = thread.join();
+            if result.is_ok() {
+                result = r;
+            }
+        }
+        result
+    }
+}
@@ -0,0 +1,88 @@
+use super::ObjectHandle;
+use super::oneshot;
+
+/// 循环内对象处理类型 M 的消息
+///
+/// 和投递闭包相比，对象能处理的消息由实现的 `Handler` 列出。
+/// 对象在所在的线程串行处理消息，和 `post` 一样只获得共享引用，需要修改状态时使用 `Cell` 或 `RefCell`。
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop::{self, Handler};
+/// use std::cell::Cell;
+/// use std::thread;
+///
+/// struct Counter (Cell<u32>);
+///
+/// struct Add (u32);
+/// impl Handler<Add> for Counter {
+///     type Output = ();
+///     fn handle(&self, msg: Add) {
+///         self.0.set(self.0.get() + msg.0);
+///     }
+/// }
+///
+/// struct Get;
+/// impl Handler<Get> for Counter {
+///     type Output = u32;
+///     fn handle(&self, _: Get) -> u32 {
+///         self.0.get()
+///     }
+/// }
+///
+/// let obj = run_loop::new_object(Counter(Cell::new(0)));
+/// let handle = run_loop::clone_handle();
+/// let th = thread::spawn(move || {
+///     let senders: Vec<_> = (0..4).map(|_| {
+///         let obj = obj.clone();
+///         thread::spawn(move || for _ in 0..100 { obj.send(Add(1)); })
+///     }).collect();
+///     for th in senders {
+///         th.join().unwrap();
+///     }
+///     let total = obj.send_and_wait(Get).unwrap();
+///     let later = obj.send_async(Get);
+///     obj.send(Add(1));
+///     let later = later.recv().unwrap();
+///     handle.stop();
+///     (total, later)
+/// });
+///
+/// run_loop::run();
+/// assert_eq!(th.join().unwrap(), (400, 400));
+/// ```
+pub trait Handler<M> {
+    type Output;
+    fn handle(&self, msg: M) -> Self::Output;
+}
+
+impl<T: ?Sized> ObjectHandle<T> {
+    /// 向对象发送消息，在对象所在的线程处理，忽略处理结果
+    pub fn send<M>(&self, msg: M) where T: Handler<M>, M: Send + 'static {
+        self.post(move |obj| {
+            obj.handle(msg);
+        });
+    }
+
+    /// 向对象发送消息，处理结果通过返回的接收端取得
+    pub fn send_async<M>(&self, msg: M) -> oneshot::Receiver<T::Output>
+        where T: Handler<M>, M: Send + 'static, T::Output: Send + 'static {
+        let (tx, rx) = oneshot::channel();
+        self.post(move |obj| {
+            let _ = tx.send(obj.handle(msg));
+        });
+        rx
+    }
+
+    /// 向对象发送消息并阻塞等待处理结果
+    ///
+    /// 在对象所在的线程调用时直接处理。对象在处理前被释放时返回 `Canceled`。
+    #[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+    pub fn send_and_wait<M>(&self, msg: M) -> Result<T::Output, oneshot::Canceled>
+        where T: Handler<M>, M: Send + 'static, T::Output: Send + 'static {
+        match self.get_ref() {
+            Some(obj) => Ok(obj.handle(msg)),
+            None => self.send_async(msg).recv(),
+        }
+    }
+}
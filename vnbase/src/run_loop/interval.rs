@@ -0,0 +1,125 @@
+//! 以异步流的形式使用周期历程
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use super::Instant;
+use super::schedule::Schedule;
+
+/// 周期触发的异步流，每次触发产生触发的时刻，见 `run_loop::interval_stream`
+///
+/// 没有及时取走的多次触发合并为一次，只保留最近的时刻，合并掉的次数见 `missed`。释放时取消周期历程。
+pub struct IntervalStream {
+    state: Rc<RefCell<State>>,
+    schedule: Schedule,
+}
+
+struct State {
+    /// 还没有取走的最近一次触发的时刻
+    tick: Option<Instant>,
+    /// tick 之前没有取走、已经合并的触发次数
+    merged: u64,
+    /// 上一次取出的时刻之前合并的触发次数
+    missed: u64,
+    waker: Option<Waker>,
+}
+
+impl IntervalStream {
+    pub fn new(period: Duration) -> IntervalStream {
+        let state = Rc::new(RefCell::new(State {
+            tick: None,
+            merged: 0,
+            missed: 0,
+            waker: None,
+        }));
+        let s = state.clone();
+        let schedule = Schedule::new()
+            .with_period(period)
+            .with_cancel_on_drop(true)
+            .with_callback(move |_| {
+                let waker = {
+                    let mut s = s.borrow_mut();
+                    if s.tick.is_some() {
+                        s.merged += 1;
+                    }
+                    s.tick = Some(Instant::now());
+                    s.waker.take()
+                };
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+            })
+            .and_start();
+        IntervalStream {
+            state: state,
+            schedule: schedule,
+        }
+    }
+
+    /// 取出下一个触发的时刻，还没有触发时在触发后唤醒 cx
+    ///
+    /// 和 `Stream::poll_next` 相同，周期历程不会结束，因此不会返回 `None`。
+    pub fn poll_next(&mut self, cx: &mut Context) -> Poll<Option<Instant>> {
+        let mut state = self.state.borrow_mut();
+        match state.tick.take() {
+            Some(t) => {
+                state.missed = state.merged;
+                state.merged = 0;
+                Poll::Ready(Some(t))
+            },
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            },
+        }
+    }
+
+    /// 上一次取出的时刻之前因为没有及时取走而合并掉的触发次数
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::time::Duration;
+    ///
+    /// let mut stream = run_loop::interval_stream(Duration::from_millis(5));
+    /// let _stop = run_loop::new_timer()
+    ///     .with_callback_once(run_loop::stop)
+    ///     .and_start(Duration::from_millis(50));
+    /// run_loop::run();
+    ///
+    /// // 期间的多次触发只留下最近的一次
+    /// assert!(run_loop::block_on(stream.next()).is_some());
+    /// assert!(stream.missed() > 0);
+    /// ```
+    pub fn missed(&self) -> u64 {
+        self.state.borrow().missed
+    }
+
+    /// 等待下一个触发的时刻
+    pub fn next<'a>(&'a mut self) -> IntervalNext<'a> {
+        IntervalNext {
+            stream: self,
+        }
+    }
+
+    /// 内部的周期历程，可以调整周期或者取消
+    pub fn schedule(&self) -> &Schedule {
+        &self.schedule
+    }
+}
+
+/// `IntervalStream::next` 返回的 `Future`
+pub struct IntervalNext<'a> {
+    stream: &'a mut IntervalStream,
+}
+
+impl<'a> Future for IntervalNext<'a> {
+    type Output = Option<Instant>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        self.stream.poll_next(cx)
+    }
+}
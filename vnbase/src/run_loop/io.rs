@@ -0,0 +1,355 @@
+//! IO 就绪事件，需要开启 `io` 特性
+//!
+//! 在当前线程注册 IO 源后，循环的等待由条件变量改为 `mio::Poll`，
+//! 可以同时等待 IO、定时器和其它线程投递的函数。IO 回调在循环所在的线程执行。
+//!
+//! # Examples
+//! ```
+//! use vnbase::run_loop;
+//! use vnbase::run_loop::io::{self, Interest, Token};
+//! use std::net::TcpStream;
+//! use std::thread;
+//!
+//! let mut listener = mio::net::TcpListener::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+//! let addr = listener.local_addr().unwrap();
+//!
+//! let th = thread::spawn(move || {
+//!     TcpStream::connect(addr).unwrap()
+//! });
+//!
+//! io::register(&mut listener, Token(0), Interest::READABLE, |_| {
+//!     run_loop::stop();
+//! }).unwrap();
+//!
+//! run_loop::run();
+//! assert!(listener.accept().is_ok());
+//! th.join().unwrap();
+//! ```
+//!
+//! 阻塞在 `poll` 中时，其它线程的投递通过 eventfd 或自管道唤醒循环：
+//! ```
+//! use vnbase::run_loop;
+//! use vnbase::run_loop::io::{self, Interest, Token};
+//! use std::thread;
+//! use std::time::Duration;
+//!
+//! let mut listener = mio::net::TcpListener::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+//! io::register(&mut listener, Token(0), Interest::READABLE, |_| {}).unwrap();
+//!
+//! let handle = run_loop::clone_handle();
+//! let th = thread::spawn(move || {
+//!     thread::sleep(Duration::from_millis(50));
+//!     handle.post(run_loop::stop);
+//! });
+//! run_loop::run();
+//! th.join().unwrap();
+//! ```
+use std::collections::HashMap;
+use std::io;
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use mio::{Poll, Events};
+
+use super::wakeup::Wakeup;
+
+pub use mio::{Interest, Token};
+pub use mio::event::{Event, Source};
+
+/// 循环内部用于唤醒的保留标记，注册时不能使用
+pub const WAKER_TOKEN: Token = Token(usize::MAX);
+
+type Callback = Box<FnMut(&Event)>;
+
+pub(crate) struct Reactor {
+    poll: Poll,
+    events: Events,
+    wakeup: Arc<Wakeup>,
+    callbacks: HashMap<Token, Option<Callback>>,
+    /// `Registration` 自动分配的标记，从保留标记往下递减
+    next_token: usize,
+    /// 使循环退出的等待错误，见 `take_error`
+    error: Option<io::Error>,
+}
+
+impl Reactor {
+    pub(crate) fn new() -> io::Result<(Reactor, Arc<Wakeup>)> {
+        let poll = Poll::new()?;
+        let wakeup = Arc::new(Wakeup::new(poll.registry(), WAKER_TOKEN)?);
+        Ok((Reactor {
+            poll: poll,
+            events: Events::with_capacity(256),
+            wakeup: wakeup.clone(),
+            callbacks: HashMap::new(),
+            next_token: WAKER_TOKEN.0,
+            error: None,
+        }, wakeup))
+    }
+
+    pub(crate) fn set_error(&mut self, e: io::Error) {
+        self.error = Some(e);
+    }
+
+    pub(crate) fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    /// 分配一个没有被使用的标记
+    fn alloc_token(&mut self) -> Token {
+        loop {
+            self.next_token = self.next_token.checked_sub(1).unwrap_or(WAKER_TOKEN.0 - 1);
+            let token = Token(self.next_token);
+            if !self.callbacks.contains_key(&token) {
+                return token;
+            }
+        }
+    }
+}
+
+fn check_token(token: Token) -> io::Result<()> {
+    if token == WAKER_TOKEN {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, "token is reserved by run loop"))
+    }
+    else {
+        Ok(())
+    }
+}
+
+/// 在当前线程注册 IO 源，就绪时在循环中调用 callback
+///
+/// 同一个 token 重复注册会替换之前的回调
+pub fn register<S, F>(source: &mut S, token: Token, interest: Interest, callback: F) -> io::Result<()>
+    where S: Source + ?Sized, F: FnMut(&Event) + 'static {
+    check_token(token)?;
+    super::with_reactor(|reactor| {
+        reactor.poll.registry().register(source, token, interest)?;
+        reactor.callbacks.insert(token, Some(Box::new(callback)));
+        Ok(())
+    })
+}
+
+/// 修改 IO 源关注的事件
+pub fn reregister<S>(source: &mut S, token: Token, interest: Interest) -> io::Result<()>
+    where S: Source + ?Sized {
+    check_token(token)?;
+    super::with_reactor(|reactor| {
+        reactor.poll.registry().reregister(source, token, interest)
+    })
+}
+
+/// 注销 IO 源，并移除 token 对应的回调
+pub fn deregister<S>(source: &mut S, token: Token) -> io::Result<()>
+    where S: Source + ?Sized {
+    super::with_reactor(|reactor| {
+        reactor.callbacks.remove(&token);
+        reactor.poll.registry().deregister(source)
+    })
+}
+
+/// 注册在当前线程循环上的 IO 源，释放时注销
+///
+/// 和 `register` 不同，标记自动分配，从 `usize::MAX - 1` 往下递减，手动注册时应避开这一段。
+/// 回调在循环所在的线程执行，可以访问循环内的对象。只能在注册的线程使用，因此不是 `Send`。
+///
+/// # Examples
+/// 两个线程各运行一个循环，客户端发送后等待服务端回显：
+/// ```
+/// extern crate mio;
+/// extern crate vnbase;
+///
+/// use vnbase::run_loop;
+/// use vnbase::run_loop::io::{Interest, Registration};
+/// use mio::net::{TcpListener, TcpStream};
+/// use std::cell::RefCell;
+/// use std::io::{ErrorKind, Read, Write};
+/// use std::rc::Rc;
+/// use std::sync::mpsc;
+/// use std::thread;
+///
+/// // 读出所有可读的数据，返回对端是否已经关闭
+/// fn drain(stream: &mut TcpStream, buf: &mut Vec<u8>) -> bool {
+///     let mut chunk = [0; 64];
+///     loop {
+///         match stream.read(&mut chunk) {
+///             Ok(0) => return true,
+///             Ok(n) => buf.extend_from_slice(&chunk[..n]),
+///             Err(ref e) if e.kind() == ErrorKind::WouldBlock => return false,
+///             Err(e) => panic!("{}", e),
+///         }
+///     }
+/// }
+///
+/// let (tx, rx) = mpsc::channel();
+/// let server = thread::spawn(move || {
+///     let listener = TcpListener::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+///     tx.send(listener.local_addr().unwrap()).unwrap();
+///
+///     let acceptor: Rc<RefCell<Option<Registration<TcpListener>>>> = Rc::new(RefCell::new(None));
+///     let conn: Rc<RefCell<Option<Registration<TcpStream>>>> = Rc::new(RefCell::new(None));
+///     let (a, c) = (acceptor.clone(), conn.clone());
+///     *acceptor.borrow_mut() = Some(Registration::new(listener, Interest::READABLE, move |_| {
+///         let (stream, _) = a.borrow_mut().as_mut().unwrap().get_mut().accept().unwrap();
+///         let c2 = c.clone();
+///         let mut buf = Vec::new();
+///         *c.borrow_mut() = Some(Registration::new(stream, Interest::READABLE, move |_| {
+///             let mut conn = c2.borrow_mut();
+///             let closed = {
+///                 let stream = conn.as_mut().unwrap().get_mut();
+///                 let closed = drain(stream, &mut buf);
+///                 stream.write_all(&buf).unwrap();
+///                 closed
+///             };
+///             buf.clear();
+///             if closed {
+///                 // 在自己的回调中注销
+///                 *conn = None;
+///                 run_loop::stop();
+///             }
+///         }).unwrap());
+///     }).unwrap());
+///     run_loop::run();
+///     acceptor.borrow_mut().take();
+/// });
+///
+/// let reply = Rc::new(RefCell::new(Vec::new()));
+/// let client: Rc<RefCell<Option<Registration<TcpStream>>>> = Rc::new(RefCell::new(None));
+/// let (c, r) = (client.clone(), reply.clone());
+/// let stream = TcpStream::connect(rx.recv().unwrap()).unwrap();
+/// *client.borrow_mut() = Some(Registration::new(stream, Interest::WRITABLE, move |event| {
+///     let mut client = c.borrow_mut();
+///     let reg = client.as_mut().unwrap();
+///     if event.is_writable() {
+///         reg.get_mut().write_all(b"hello").unwrap();
+///         reg.reregister(Interest::READABLE).unwrap();
+///     }
+///     else {
+///         drain(reg.get_mut(), &mut r.borrow_mut());
+///         if r.borrow().len() == 5 {
+///             run_loop::stop();
+///         }
+///     }
+/// }).unwrap());
+/// run_loop::run();
+/// assert_eq!(&reply.borrow()[..], b"hello");
+///
+/// // 关闭连接，服务端随之退出
+/// client.borrow_mut().take();
+/// server.join().unwrap();
+/// ```
+pub struct Registration<S> where S: Source {
+    source: S,
+    token: Token,
+    phantom: PhantomData<Rc<()>>,
+}
+
+impl<S> Registration<S> where S: Source {
+    /// 在当前线程注册 source，就绪时在循环中调用 callback
+    pub fn new<F>(mut source: S, interest: Interest, callback: F) -> io::Result<Registration<S>>
+        where F: FnMut(&Event) + 'static {
+        let token = super::with_reactor(|reactor| {
+            let token = reactor.alloc_token();
+            reactor.poll.registry().register(&mut source, token, interest)?;
+            reactor.callbacks.insert(token, Some(Box::new(callback)));
+            Ok(token)
+        })?;
+        Ok(Registration {
+            source: source,
+            token: token,
+            phantom: PhantomData,
+        })
+    }
+
+    /// 修改关注的事件，回调不变
+    pub fn reregister(&mut self, interest: Interest) -> io::Result<()> {
+        reregister(&mut self.source, self.token, interest)
+    }
+
+    /// 分配的标记
+    pub fn token(&self) -> Token {
+        self.token
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.source
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.source
+    }
+
+    /// 注销并取回 IO 源
+    pub fn into_inner(mut self) -> S {
+        let _ = deregister(&mut self.source, self.token);
+        let source = unsafe { ptr::read(&self.source) };
+        mem::forget(self);
+        source
+    }
+}
+
+impl<S> Drop for Registration<S> where S: Source {
+    fn drop(&mut self) {
+        // 循环已经销毁时 IO 源也不会再被等待
+        let _ = deregister(&mut self.source, self.token);
+    }
+}
+
+/// 取出使当前线程循环退出的等待错误
+///
+/// 等待 IO 被信号打断时循环会重新等待；其它错误使循环无法继续等待，`run` 随之返回，
+/// 错误保存到调用本函数为止。
+///
+/// ```
+/// use vnbase::run_loop::io;
+///
+/// assert!(io::take_error().is_none());
+/// ```
+pub fn take_error() -> Option<io::Error> {
+    super::take_io_error()
+}
+
+/// 等待 IO 事件并分发，返回是否因超时而返回，等待出错时不分发事件
+pub(crate) fn poll(io: &::std::cell::RefCell<Option<Reactor>>, timeout: Option<Duration>) -> io::Result<bool> {
+    let mut events = {
+        let mut reactor = io.borrow_mut();
+        let reactor = reactor.as_mut().unwrap();
+        let mut events = ::std::mem::replace(&mut reactor.events, Events::with_capacity(0));
+        match reactor.poll.poll(&mut events, timeout) {
+            Ok(()) => {},
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => {
+                reactor.events = events;
+                return Err(e);
+            },
+        }
+        events
+    };
+
+    let timed_out = events.is_empty();
+    for event in events.iter() {
+        let token = event.token();
+        if token == WAKER_TOKEN {
+            io.borrow().as_ref().unwrap().wakeup.reset();
+            continue;
+        }
+        let cb = match io.borrow_mut().as_mut().unwrap().callbacks.get_mut(&token) {
+            Some(cb) => cb.take(),
+            None => None,
+        };
+        if let Some(mut cb) = cb {
+            cb(event);
+            if let Some(slot) = io.borrow_mut().as_mut().unwrap().callbacks.get_mut(&token) {
+                if slot.is_none() {
+                    *slot = Some(cb);
+                }
+            }
+        }
+    }
+
+    events.clear();
+    io.borrow_mut().as_mut().unwrap().events = events;
+    Ok(timed_out)
+}
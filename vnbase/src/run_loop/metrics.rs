@@ -0,0 +1,84 @@
+use std::time::Duration;
+
+use super::core::State;
+
+/// 循环的运行状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopState {
+    /// 没有运行
+    Stopped,
+    /// 已经请求退出，尚未退出
+    Stopping,
+    /// 正在处理消息或定时器
+    Running,
+    /// 正在等待消息或定时器
+    Waiting,
+}
+
+impl<'a> From<&'a State> for LoopState {
+    fn from(state: &'a State) -> LoopState {
+        match *state {
+            State::Stopped | State::Dead => LoopState::Stopped,
+            State::Stopping => LoopState::Stopping,
+            State::Running | State::MsgArrived => LoopState::Running,
+            State::Waiting | State::External => LoopState::Waiting,
+        }
+    }
+}
+
+/// 当前线程循环的统计快照，见 `run_loop::metrics`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoopMetrics {
+    /// 尚未处理的消息数
+    pub pending_msgs: usize,
+    /// 已经启动的定时器数
+    pub active_timers: usize,
+    /// 已经启动的周期历程数
+    pub active_schedules: usize,
+    /// 存在的循环内对象数
+    pub objects: usize,
+    pub state: LoopState,
+    /// 循环启动以来处理的消息总数
+    pub processed_msgs: u64,
+    /// 循环的名字，见 `run_loop::set_name`
+    pub name: Option<String>,
+}
+
+/// 循环处理消息和定时器的跟踪事件，见 `run_loop::set_trace_hook`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// 开始处理一条消息
+    MsgStart,
+    /// 消息处理完毕
+    MsgEnd { elapsed: Duration },
+    /// 开始处理一个到期的定时器或周期历程
+    TimerStart,
+    /// 定时器处理完毕
+    TimerEnd { elapsed: Duration },
+}
+
+/// 循环停滞的报告，见 `run_loop::set_watchdog`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchdogInfo {
+    /// 尚未处理的消息数
+    pub pending_msgs: usize,
+    /// 循环启动以来处理的消息总数
+    pub processed_msgs: u64,
+    /// 有消息等待却没有处理任何消息的时长
+    pub stalled_for: Duration,
+}
+
+/// 当前线程循环的消息队列统计，见 `run_loop::queue_stats`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueStats {
+    /// 尚未取出的消息数
+    pub len: usize,
+    /// 曾经同时排队的最多消息数，可以用 `run_loop::reset_queue_high_watermark` 重新记录
+    ///
+    /// 队列分片时为各分片最高水位之和，见 `RunLoopBuilder::producer_shards`
+    pub high_watermark: usize,
+    /// 投递的消息总数
+    pub total_pushed: usize,
+    /// 取出处理的消息总数
+    pub total_processed: usize,
+}
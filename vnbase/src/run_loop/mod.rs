@@ -28,23 +28,104 @@
 //! th.join().unwrap();
 //! ```
 mod core;
+mod queue;
+mod shards;
+mod wheel;
 mod timer;
 mod schedule;
+mod interval;
 mod object;
+mod throttle;
+mod context;
+mod metrics;
+mod signal;
+mod handler;
+mod registry;
+mod group;
+mod coalesce;
+mod spawn;
+mod pending_timer;
+mod batch;
+mod channel;
+mod pool;
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+mod scope;
+mod forward;
+mod origin;
+mod builder;
+pub mod oneshot;
+#[cfg(feature = "io")]
+pub mod io;
+#[cfg(feature = "net")]
+pub mod net;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(all(feature = "winit", not(target_arch = "wasm32")))]
+pub mod winit;
+#[cfg(feature = "io")]
+mod wakeup;
+#[cfg(feature = "futex")]
+mod futex;
+#[cfg(all(windows, feature = "windows-pump"))]
+mod win_pump;
+#[cfg(all(unix, feature = "signal"))]
+mod unix_signal;
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+mod wasm;
 
-pub use self::timer::Timer;
-pub use self::schedule::Schedule;
+pub use self::timer::{Timer, TimerGuard};
+pub use self::pending_timer::{PendingTimer, TimerHandle};
+pub use self::batch::Batch;
+pub use self::channel::{LoopSender, LoopReceiver};
+pub use self::pool::{LoopPool, PanicPolicy};
+pub use self::builder::{RunLoopBuilder, StopPolicy, InstallError};
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+pub use self::scope::Scope;
+pub use self::forward::ForwardHandle;
+pub use self::schedule::{Schedule, ScheduleGuard};
+pub use self::interval::{IntervalStream, IntervalNext};
+pub use self::throttle::Throttle;
+pub use self::context::Context;
+pub use self::signal::LoopSignal;
+pub use self::handler::Handler;
+pub use self::registry::{register_object, unregister_object, lookup_object, ObjectLookup};
+pub use self::metrics::{LoopMetrics, LoopState, QueueStats, TraceEvent, WatchdogInfo};
+pub use self::core::{WaitStrategy, TimerStrategy, PumpStatus};
+pub use self::group::LoopGroup;
 
 pub use self::object::ObjectHandle;
 pub use self::object::ObjectWeak;
+pub use self::object::AccessError;
+pub use self::spawn::SpawnError;
+#[cfg(all(unix, feature = "signal"))]
+pub use self::unix_signal::{Signal, SignalHandle};
+pub use self::object::LoopObject;
 
 use self::core::Core;
 use self::core::State;
+use self::origin::Origin;
 
-use std::sync::{Arc, MutexGuard};
-use std::time::{Duration, Instant};
-use std::cell::RefCell;
+use std::sync::Arc;
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+use std::sync::MutexGuard;
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::future::Future;
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+use std::task::{self, Poll, Wake};
+use std::time::Duration;
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+use std::time::Instant;
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+use web_time::Instant;
+use std::cell::{RefCell, Cell};
 use std::rc::Rc;
+use std::fmt;
+use std::mem;
+use std::ops::ControlFlow;
+use std::collections::VecDeque;
+use std::hash::Hash;
+use std::thread;
 
 /// 消息循环句柄
 /// 
@@ -60,16 +141,558 @@ pub struct Handle {
 
 impl Handle {
     /// 向循环投递一个函数，该函数会在循环所在的线程执行
+    ///
+    /// 同一个线程投递的函数按投递的顺序执行，和通过循环内对象句柄投递的函数共用同一个顺序。
+    /// 投递不加锁，只有循环可能正在等待时才加锁唤醒。
+    ///
+    /// 循环刚好在投递时进入等待，也不会错过唤醒：
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::sync::mpsc;
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let th = thread::spawn(move || {
+    ///     tx.send(run_loop::clone_handle()).unwrap();
+    ///     run_loop::run();
+    /// });
+    /// let handle = rx.recv().unwrap();
+    ///
+    /// // 每次往返后循环都会进入等待
+    /// let (tx, rx) = mpsc::channel();
+    /// for i in 0..20000 {
+    ///     let tx = tx.clone();
+    ///     handle.post(move || tx.send(i).unwrap());
+    ///     assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(i));
+    /// }
+    ///
+    /// // 多个线程同时投递，每个线程的顺序不变
+    /// let (tx, rx) = mpsc::channel();
+    /// let producers: Vec<_> = (0..8).map(|p| {
+    ///     let handle = handle.clone();
+    ///     let tx = tx.clone();
+    ///     thread::spawn(move || for i in 0..5000 {
+    ///         let tx = tx.clone();
+    ///         handle.post(move || tx.send((p, i)).unwrap());
+    ///     })
+    /// }).collect();
+    /// let mut next = [0; 8];
+    /// for _ in 0..8 * 5000 {
+    ///     let (p, i) = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    ///     assert_eq!(next[p], i);
+    ///     next[p] += 1;
+    /// }
+    /// for p in producers {
+    ///     p.join().unwrap();
+    /// }
+    ///
+    /// handle.stop();
+    /// th.join().unwrap();
+    /// ```
+    #[cfg_attr(feature = "debug-origin", track_caller)]
     pub fn post<T>(&self, msg: T) where T: FnOnce() + 'static + Send {
-        self.core.post(msg);
+        let origin = Origin::caller();
+        self.core.post(move || {
+            origin.enter();
+            msg()
+        });
+    }
+
+    /// 投递函数，循环所在的线程已经结束时返回原函数
+    ///
+    /// `post` 在这种情况下直接丢弃函数，函数捕获的资源随之释放，不会在队列中累积。
+    ///
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::sync::Arc;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::thread;
+    ///
+    /// struct Guard (Arc<AtomicUsize>);
+    /// impl Drop for Guard {
+    ///     fn drop(&mut self) {
+    ///         self.0.fetch_add(1, Ordering::SeqCst);
+    ///     }
+    /// }
+    ///
+    /// let (handle, obj) = thread::spawn(|| (run_loop::clone_handle(), run_loop::new_object(1))).join().unwrap();
+    /// assert!(!handle.is_alive());
+    ///
+    /// let drops = Arc::new(AtomicUsize::new(0));
+    /// let guard = Guard(drops.clone());
+    /// handle.post(move || drop(guard));
+    /// assert_eq!(drops.load(Ordering::SeqCst), 1);
+    ///
+    /// let guard = Guard(drops.clone());
+    /// let f = handle.try_post(move || drop(guard)).unwrap_err();
+    /// assert_eq!(drops.load(Ordering::SeqCst), 1);
+    /// drop(f);
+    /// assert_eq!(drops.load(Ordering::SeqCst), 2);
+    ///
+    /// // 对象随循环释放，之后通过句柄投递的函数同样直接丢弃
+    /// let guard = Guard(drops.clone());
+    /// obj.post(move |_| drop(guard));
+    /// assert_eq!(drops.load(Ordering::SeqCst), 3);
+    /// assert_eq!(obj.strong_count(), 1);
+    /// let weak = obj.downgrade();
+    /// drop(obj);
+    /// assert!(weak.upgrade().is_none());
+    /// ```
+    pub fn try_post<T>(&self, msg: T) -> Result<(), T> where T: FnOnce() + 'static + Send {
+        self.core.try_post(msg)
+    }
+
+    /// 在 f 中收集多个函数，f 返回后按收集的顺序一起投递
+    ///
+    /// 连续投递时每条消息都可能唤醒循环，一起投递只检查和唤醒一次。
+    /// 收集的函数在 f 返回前不会执行；f 发生 panic 时已经收集的函数仍然投递。
+    ///
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::sync::mpsc;
+    /// use std::thread;
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let th = thread::spawn(move || {
+    ///     tx.send(run_loop::clone_handle()).unwrap();
+    ///     run_loop::run();
+    /// });
+    /// let handle = rx.recv().unwrap();
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let n = handle.batch(|b| {
+    ///     for i in 0..10 {
+    ///         let tx = tx.clone();
+    ///         b.post(move || tx.send(i).unwrap());
+    ///     }
+    ///     // 尚未投递
+    ///     assert!(rx.try_recv().is_err());
+    ///     b.len()
+    /// });
+    /// assert_eq!(n, 10);
+    /// assert_eq!(rx.iter().take(10).collect::<Vec<_>>(), (0..10).collect::<Vec<_>>());
+    ///
+    /// handle.stop();
+    /// th.join().unwrap();
+    /// ```
+    pub fn batch<F, R>(&self, f: F) -> R where F: FnOnce(&mut Batch) -> R {
+        let mut batch = Batch::new(&self.core);
+        f(&mut batch)
+    }
+
+    /// 在 f 中投递可以借用栈上数据的函数，所有函数执行完后才返回
+    ///
+    /// f 返回或者 panic 后都等待 `Scope::spawn` 投递的函数执行完；循环已经结束时函数不会执行，直接释放。
+    /// 有函数 panic 时，在它们全部结束后 panic。不能在循环自己的线程调用，否则永远等待。
+    ///
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::panic;
+    /// use std::sync::Mutex;
+    /// use std::sync::mpsc;
+    /// use std::thread;
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let th = thread::spawn(move || {
+    ///     tx.send(run_loop::clone_handle()).unwrap();
+    ///     run_loop::run();
+    /// });
+    /// let handle = rx.recv().unwrap();
+    ///
+    /// // 分成几段在循环上求和，借用局部变量
+    /// let data: Vec<u64> = (1..=1000).collect();
+    /// let sums = Mutex::new(Vec::new());
+    /// handle.scope(|s| {
+    ///     for chunk in data.chunks(100) {
+    ///         let sums = &sums;
+    ///         s.spawn(move || sums.lock().unwrap().push(chunk.iter().sum::<u64>()));
+    ///     }
+    /// });
+    /// assert_eq!(sums.into_inner().unwrap().iter().sum::<u64>(), 500500);
+    ///
+    /// // 函数 panic 时 scope 也 panic，循环需要自己恢复
+    /// let r = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+    ///     handle.scope(|s| s.spawn(|| panic!("spawned work failed")));
+    /// }));
+    /// assert!(r.is_err());
+    /// th.join().unwrap_err();
+    ///
+    /// // 循环已经结束，函数直接释放
+    /// let mut ran = false;
+    /// handle.scope(|s| s.spawn(|| ran = true));
+    /// assert!(!ran);
+    /// ```
+    #[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+    pub fn scope<'env, F, R>(&self, f: F) -> R where F: FnOnce(&Scope<'env>) -> R {
+        assert!(!is_own_handle(self), "Handle::scope called on the loop's own thread");
+        let scope = Scope::new(self.clone());
+        let r = {
+            let _wait = scope::WaitGuard(&scope);
+            f(&scope)
+        };
+        if scope.wait() {
+            panic!("a closure spawned in Handle::scope panicked");
+        }
+        r
+    }
+
+    /// 投递以 key 合并的函数：同一个键已有函数在等待执行时，只把它替换为 f，不再排队
+    ///
+    /// 合并后的函数在第一次投递的位置执行，执行的是最后一次投递的函数。执行开始后再投递同一个键，
+    /// 重新排队。不同类型的键互不影响。适合重新布局之类可以合并的工作。
+    ///
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::sync::{Arc, Mutex};
+    /// use std::thread;
+    ///
+    /// let log = Arc::new(Mutex::new(Vec::new()));
+    /// let handle = run_loop::clone_handle();
+    /// let (h, l) = (handle.clone(), log.clone());
+    /// thread::spawn(move || {
+    ///     for i in 0..3 {
+    ///         let (l1, l2) = (l.clone(), l.clone());
+    ///         h.post_coalesced("relayout", move || l1.lock().unwrap().push(format!("relayout {}", i)));
+    ///         h.post(move || l2.lock().unwrap().push(format!("input {}", i)));
+    ///     }
+    ///     let l2 = l.clone();
+    ///     h.post_coalesced(1u32, move || l2.lock().unwrap().push("other key".to_string()));
+    ///     h.post(run_loop::stop);
+    /// }).join().unwrap();
+    /// run_loop::run();
+    ///
+    /// assert_eq!(*log.lock().unwrap(), vec!["relayout 2", "input 0", "input 1", "input 2", "other key"]);
+    ///
+    /// // 执行后再次投递会重新排队
+    /// let l = log.clone();
+    /// handle.post_coalesced("relayout", move || l.lock().unwrap().push("again".to_string()));
+    /// handle.post(run_loop::stop);
+    /// run_loop::run();
+    /// assert_eq!(log.lock().unwrap().last().unwrap(), "again");
+    /// ```
+    pub fn post_coalesced<K, F>(&self, key: K, f: F)
+        where K: Eq + Hash + Send + 'static, F: FnOnce() + Send + 'static {
+        let inserted = core::lock(&self.core.coalesced).insert(key, Box::new(f));
+        let token = match inserted {
+            Ok(token) => token,
+            // 被替换的函数在解锁后释放
+            Err(_) => return,
+        };
+        let core = Arc::downgrade(&self.core);
+        let forward = move || {
+            let f = core.upgrade().and_then(|core| core::lock(&core.coalesced).take(token));
+            if let Some(f) = f {
+                f();
+            }
+        };
+        if self.core.try_post(forward).is_err() {
+            let f = core::lock(&self.core.coalesced).take(token);
+            drop(f);
+        }
+    }
+
+    /// 在循环所在的线程运行 fut，可以在任何线程调用
+    ///
+    /// fut 在循环中轮询，被唤醒时投递下一次轮询，完成后在循环所在的线程释放。
+    /// fut 只需转移一次线程，因此要求 `Send`。循环所在的线程已经结束时返回 `SpawnError`。
+    ///
+    /// ```
+    /// use vnbase::run_loop::{self, oneshot};
+    /// use std::future::Future;
+    /// use std::pin::Pin;
+    /// use std::sync::{Arc, Mutex};
+    /// use std::task::{Context, Poll};
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// // 等待计时器的结果并记录下来
+    /// struct Record (oneshot::Receiver<u32>, Arc<Mutex<Vec<(u32, u64)>>>);
+    /// impl Future for Record {
+    ///     type Output = ();
+    ///     fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+    ///         match Pin::new(&mut self.0).poll(cx) {
+    ///             Poll::Ready(v) => {
+    ///                 self.1.lock().unwrap().push((v.unwrap(), run_loop::current_id()));
+    ///                 run_loop::stop();
+    ///                 Poll::Ready(())
+    ///             },
+    ///             Poll::Pending => Poll::Pending,
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let (tx, rx) = run_loop::oneshot();
+    /// let _timer = run_loop::new_timer()
+    ///     .with_callback_once(move || tx.send(7).unwrap())
+    ///     .and_start(Duration::from_millis(10));
+    ///
+    /// let log = Arc::new(Mutex::new(Vec::new()));
+    /// let (handle, l) = (run_loop::clone_handle(), log.clone());
+    /// thread::spawn(move || handle.spawn(Record(rx, l)).unwrap()).join().unwrap();
+    /// run_loop::run();
+    /// assert_eq!(*log.lock().unwrap(), vec![(7, run_loop::current_id())]);
+    ///
+    /// let dead = thread::spawn(run_loop::clone_handle).join().unwrap();
+    /// assert_eq!(dead.spawn(std::future::ready(())), Err(run_loop::SpawnError));
+    /// ```
+    pub fn spawn<F>(&self, fut: F) -> Result<(), SpawnError> where F: Future<Output = ()> + Send + 'static {
+        spawn::spawn(self, Box::pin(fut))
+    }
+
+    /// 在任意线程准备一个定时器，`arm` 后在这个循环上创建并启动
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::sync::mpsc;
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// let handle = run_loop::clone_handle();
+    /// let main_id = thread::current().id();
+    /// let (tx, rx) = mpsc::channel();
+    /// thread::spawn(move || {
+    ///     let cancelled = handle.new_timer()
+    ///         .with_callback(|| panic!("cancelled timer fired"))
+    ///         .arm(Duration::from_millis(5));
+    ///     let timer = handle.new_timer()
+    ///         .with_callback_once(move || {
+    ///             assert_eq!(thread::current().id(), main_id);
+    ///             run_loop::stop();
+    ///         })
+    ///         .arm(Duration::from_secs(60));
+    ///     cancelled.cancel();
+    ///     timer.start(Duration::from_millis(10));
+    ///     tx.send(timer).unwrap();
+    /// }).join().unwrap();
+    ///
+    /// run_loop::run();
+    ///
+    /// let timer = rx.recv().unwrap();
+    /// let (tx, rx) = mpsc::channel();
+    /// timer.request_active(move |active| {
+    ///     tx.send(active).unwrap();
+    ///     run_loop::stop();
+    /// });
+    /// run_loop::run();
+    /// assert_eq!(rx.recv(), Ok(false));
+    /// ```
+    pub fn new_timer(&self) -> PendingTimer {
+        PendingTimer::new(self.clone())
+    }
+
+    /// 循环的名字，见 `run_loop::set_name`
+    pub fn name(&self) -> Option<String> {
+        core::lock(&self.core.name).clone()
+    }
+
+    /// 循环是否仍然存在，所在的线程结束后返回 false
+    pub fn is_alive(&self) -> bool {
+        !self.core.is_dead()
+    }
+
+    /// 循环在当前线程时立即执行 f，否则投递
+    ///
+    /// 立即执行时不经过队列，f 会先于之前投递但尚未执行的函数执行，不保证投递的顺序。
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::sync::{Arc, Mutex};
+    /// use std::thread;
+    ///
+    /// let log = Arc::new(Mutex::new(Vec::new()));
+    /// let handle = run_loop::clone_handle();
+    ///
+    /// let l = log.clone();
+    /// handle.post(move || l.lock().unwrap().push("posted"));
+    /// let l = log.clone();
+    /// handle.post_or_run(move || l.lock().unwrap().push("inline"));
+    /// assert_eq!(*log.lock().unwrap(), vec!["inline"]);
+    ///
+    /// let (h, l) = (handle.clone(), log.clone());
+    /// thread::spawn(move || {
+    ///     h.post_or_run(move || l.lock().unwrap().push("remote"));
+    ///     h.post(run_loop::stop);
+    /// }).join().unwrap();
+    /// run_loop::run();
+    /// assert_eq!(*log.lock().unwrap(), vec!["inline", "posted", "remote"]);
+    /// ```
+    pub fn post_or_run<T>(&self, f: T) where T: FnOnce() + 'static + Send {
+        if is_own_handle(self) {
+            f();
+        }
+        else {
+            self.core.post(f);
+        }
+    }
+
+    /// 阻塞到循环处理完此前从当前线程投递的所有函数
+    ///
+    /// 投递一个标记并等待它被执行，之后可以放心读取这些函数在其它线程留下的结果。
+    /// 在循环所在的线程调用时就地处理队列直到标记被执行，相当于对标记调用 `block_on`。
+    /// 循环没有运行时一直等到它运行；循环在处理标记前结束时返回。
+    ///
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::sync::{Arc, Mutex};
+    /// use std::sync::mpsc;
+    /// use std::thread;
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let th = thread::spawn(move || {
+    ///     tx.send(run_loop::clone_handle()).unwrap();
+    ///     run_loop::run();
+    /// });
+    /// let handle = rx.recv().unwrap();
+    ///
+    /// let log = Arc::new(Mutex::new(Vec::new()));
+    /// for i in 0..100 {
+    ///     let l = log.clone();
+    ///     handle.post(move || l.lock().unwrap().push(i));
+    /// }
+    /// handle.flush();
+    /// assert_eq!(log.lock().unwrap().len(), 100);
+    ///
+    /// // 在循环所在的线程调用
+    /// let l = log.clone();
+    /// handle.post(move || {
+    ///     let l2 = l.clone();
+    ///     run_loop::clone_handle().post(move || l2.lock().unwrap().push(100));
+    ///     run_loop::clone_handle().flush();
+    ///     assert_eq!(l.lock().unwrap().len(), 101);
+    ///     run_loop::stop();
+    /// });
+    /// th.join().unwrap();
+    ///
+    /// // 循环已经结束
+    /// handle.flush();
+    /// ```
+    #[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+    pub fn flush(&self) {
+        let (tx, rx) = oneshot::channel();
+        self.core.post(move || {
+            let _ = tx.send(());
+        });
+        if is_own_handle(self) {
+            let _ = block_on(rx);
+        }
+        else {
+            let _ = rx.recv();
+        }
     }
 
     /// 使循环立即退出
+    ///
+    /// 循环正在等待时同样会被唤醒，不必等到下一个定时器到期。
+    ///
+    /// ```
+    /// use vnbase::run_loop::{self, Timer};
+    /// use std::thread;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let _timer = Timer::new().with_callback(|| {}).and_start(Duration::from_secs(60));
+    /// let handle = run_loop::clone_handle();
+    /// let th = thread::spawn(move || {
+    ///     thread::sleep(Duration::from_millis(50));
+    ///     handle.stop();
+    /// });
+    /// let start = Instant::now();
+    /// run_loop::run();
+    /// assert!(start.elapsed() < Duration::from_secs(30));
+    /// th.join().unwrap();
+    /// ```
     pub fn stop(&self) {
         self.core.stop();
     }
+
+    /// 在循环所在的线程获取循环内对象的数量，并以此调用 f
+    ///
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::sync::mpsc;
+    /// use std::thread;
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let th = thread::spawn(move || {
+    ///     let _a = run_loop::new_object(1);
+    ///     let _b = run_loop::new_object("b");
+    ///     tx.send(run_loop::clone_handle()).unwrap();
+    ///     run_loop::run();
+    /// });
+    ///
+    /// let handle = rx.recv().unwrap();
+    /// let (tx, rx) = mpsc::channel();
+    /// handle.request_object_count(move |n| tx.send(n).unwrap());
+    /// assert_eq!(rx.recv().unwrap(), 2);
+    ///
+    /// handle.stop();
+    /// th.join().unwrap();
+    /// ```
+    pub fn request_object_count<F>(&self, f: F) where F: FnOnce(usize) + 'static + Send {
+        self.post(move || f(object_count()));
+    }
+
+    /// 在循环所在的线程获取消息队列的统计，并以此调用 f
+    ///
+    /// 统计包含这次请求本身。
+    ///
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::sync::mpsc;
+    /// use std::thread;
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let th = thread::spawn(move || {
+    ///     tx.send(run_loop::clone_handle()).unwrap();
+    ///     run_loop::run();
+    /// });
+    ///
+    /// let handle = rx.recv().unwrap();
+    /// let (tx, rx) = mpsc::channel();
+    /// handle.request_queue_stats(move |stats| tx.send(stats).unwrap());
+    /// let stats = rx.recv().unwrap();
+    /// assert_eq!(stats.total_pushed, 1);
+    /// assert_eq!(stats.total_processed, 1);
+    ///
+    /// handle.stop();
+    /// th.join().unwrap();
+    /// ```
+    pub fn request_queue_stats<F>(&self, f: F) where F: FnOnce(QueueStats) + 'static + Send {
+        self.post(move || f(queue_stats()));
+    }
+
+    /// 循环的标识，在进程内唯一，循环存在期间不变
+    ///
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::thread;
+    ///
+    /// let handle = run_loop::clone_handle();
+    /// assert_eq!(handle.id(), run_loop::current_id());
+    /// assert_eq!(handle.id(), run_loop::clone_handle().id());
+    ///
+    /// let other = thread::spawn(run_loop::current_id).join().unwrap();
+    /// assert_ne!(handle.id(), other);
+    /// ```
+    pub fn id(&self) -> u64 {
+        self.core.id
+    }
+}
+
+impl fmt::Debug for Handle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Handle")
+            .field("id", &self.core.id)
+            .field("name", &*core::lock(&self.core.name))
+            .finish()
+    }
 }
 
+/// `set_trace_hook` 设置的回调
+type TraceHook = Box<FnMut(TraceEvent)>;
+
 enum WaitingTime {
     Infinite,
     Zero,
@@ -78,31 +701,371 @@ enum WaitingTime {
 
 struct RunLoop {
     core: Arc<Core>,
-    timers: RefCell<core::TimedActionBinaryHeap>,
+    timers: RefCell<core::TimerQueue>,
     objects: RefCell<object::ObjectList>,
+    exit_hooks: RefCell<Vec<Box<FnOnce()>>>,
+    epoch: Instant,
+    processed: Cell<u64>,
+    coalescing: Cell<Option<Duration>>,
+    /// 每轮最多处理的消息数
+    msg_batch: Cell<Option<usize>>,
+    /// 每轮最多处理的到期定时器数
+    timer_batch: Cell<Option<usize>>,
+    /// 复用的到期定时器列表
+    due_buf: Cell<Vec<Rc<core::TimedAction>>>,
+    /// 当前执行的回调结束后执行的函数，见 `run_loop::defer`
+    deferred: RefCell<VecDeque<Box<FnOnce()>>>,
+    /// 循环即将等待时调用，见 `set_idle_hook`
+    idle: RefCell<Option<Box<FnMut() -> bool>>>,
+    /// 正在执行的消息、定时器和空闲工作的嵌套层数，见 `in_loop`
+    dispatching: Cell<usize>,
+    trace: RefCell<Option<TraceHook>>,
+    /// 见 `set_watchdog`
+    watchdog: RefCell<Option<Schedule>>,
+    /// 见 `RunLoopBuilder::panic_policy`
+    panic_policy: Cell<PanicPolicy>,
+    /// 见 `RunLoopBuilder::stop_policy`
+    stop_policy: Cell<StopPolicy>,
+    registry: RefCell<registry::Registry>,
+    /// `Handle::spawn` 和 `spawn_local` 的任务
+    tasks: RefCell<spawn::Tasks>,
+    #[cfg(feature = "io")]
+    io: RefCell<Option<io::Reactor>>,
 }
 
 impl Drop for RunLoop {
     fn drop(&mut self) {
-        self.core.msgs.lock().unwrap().drain();
+        loop {
+            let hook = self.exit_hooks.borrow_mut().pop();
+            match hook {
+                Some(f) => f(),
+                None => break,
+            }
+        }
+        self.core.kill();
+        // 消息中可能持有对象句柄，释放时会再次投递，不能在持有锁时释放。
+        // 丢弃的消息中包括其它线程投递的对象释放，节点随后由 `ObjectList` 统一释放，每个对象只析构一次
+        self.core.clear();
+    }
+}
+
+/// 正在执行回调，回调 panic 时同样恢复层数
+struct Dispatching<'a> (&'a RunLoop);
+
+impl<'a> Dispatching<'a> {
+    fn new(rl: &'a RunLoop) -> Dispatching<'a> {
+        rl.dispatching.set(rl.dispatching.get() + 1);
+        Dispatching(rl)
+    }
+}
+
+impl<'a> Drop for Dispatching<'a> {
+    fn drop(&mut self) {
+        self.0.dispatching.set(self.0.dispatching.get() - 1);
+    }
+}
+
+/// 本轮取出的到期定时器，回调 panic 时把尚未处理的放回容器
+struct DueTimers<'a> {
+    rl: &'a RunLoop,
+    due: Vec<Rc<core::TimedAction>>,
+    next: usize,
+}
+
+impl<'a> Drop for DueTimers<'a> {
+    fn drop(&mut self) {
+        for t in self.due.drain(self.next..) {
+            if t.node().due.replace(false) {
+                let time = t.node().time.get();
+                self.rl.timers.borrow_mut().push(t, time);
+            }
+        }
+        self.due.clear();
+        self.rl.due_buf.set(mem::take(&mut self.due));
+    }
+}
+
+/// 嵌套处理消息时暂存外层回调 `defer` 的函数，离开时放回队首
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+struct DeferScope<'a> {
+    rl: &'a RunLoop,
+    outer: VecDeque<Box<FnOnce()>>,
+}
+
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+impl<'a> DeferScope<'a> {
+    fn new(rl: &'a RunLoop) -> DeferScope<'a> {
+        DeferScope {
+            rl: rl,
+            outer: mem::take(&mut *rl.deferred.borrow_mut()),
+        }
+    }
+}
+
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+impl<'a> Drop for DeferScope<'a> {
+    fn drop(&mut self) {
+        let mut deferred = self.rl.deferred.borrow_mut();
+        self.outer.append(&mut deferred);
+        mem::swap(&mut *deferred, &mut self.outer);
     }
 }
 
 impl RunLoop {
-    fn process_timers(&self) {
-        let mut timers = self.timers.borrow_mut();
+    /// 处理到期的定时器，返回执行的个数
+    fn process_timers(&self) -> usize {
+        let _dispatching = Dispatching::new(self);
         let now = Instant::now();
-        while let Some(t) = timers.peek(now) {
-            drop(timers);
-            let ret = t.process();
-            timers = self.timers.borrow_mut();
+        let limit = self.timer_batch.get().unwrap_or(usize::MAX);
+        // 一次取出所有到期的定时器，回调中嵌套处理定时器时不会再次取到它们
+        let mut batch = DueTimers {
+            rl: self,
+            due: self.due_buf.take(),
+            next: 0,
+        };
+        {
+            let mut timers = self.timers.borrow_mut();
+            while batch.due.len() < limit {
+                match timers.pop_due(now) {
+                    Some(t) => batch.due.push(t),
+                    None => break,
+                }
+            }
+        }
+        let mut n = 0;
+        while let Some(t) = batch.due.get(batch.next).cloned() {
+            batch.next += 1;
+            let (due, time) = (t.node().due.replace(false), t.node().time.get());
+            // 被之前的回调取消
+            if !due {
+                continue;
+            }
+            // 被之前的回调重新启动
+            if time > now {
+                self.timers.borrow_mut().push(t, time);
+                continue;
+            }
+            let _origin = origin::Scope::new();
+            let ret = self.traced(TraceEvent::TimerStart, || t.process(), |elapsed| TraceEvent::TimerEnd { elapsed: elapsed });
+            self.run_deferred();
+            n += 1;
             if let Some(time) = ret {
-                timers.adjust(t.node(), time);
+                self.timers.borrow_mut().push(t, self.coalesce(time));
             }
-            else {
-                timers.remove(t.node());
+        }
+        n
+    }
+
+    /// 调用空闲函数，返回它是否做了工作；在空闲函数中嵌套运行循环时不再调用
+    #[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+    fn run_idle(&self) -> bool {
+        let _dispatching = Dispatching::new(self);
+        let worked = match self.idle.try_borrow_mut() {
+            Ok(mut idle) => idle.as_mut().is_some_and(|f| f()),
+            Err(_) => false,
+        };
+        if worked {
+            self.run_deferred();
+        }
+        worked
+    }
+
+    /// 依次执行已经 `defer` 的函数，包括执行中新加入的
+    fn run_deferred(&self) {
+        loop {
+            let f = self.deferred.borrow_mut().pop_front();
+            match f {
+                Some(f) => f(),
+                None => break,
+            }
+        }
+    }
+
+    /// 设置了跟踪回调时在 f 前后通知，否则直接调用 f
+    fn traced<F, R, E>(&self, start: TraceEvent, f: F, end: E) -> R
+        where F: FnOnce() -> R, E: FnOnce(Duration) -> TraceEvent {
+        if self.trace.borrow().is_none() {
+            return f();
+        }
+        self.emit(start);
+        let t = Instant::now();
+        let ret = f();
+        self.emit(end(t.elapsed()));
+        ret
+    }
+
+    fn emit(&self, ev: TraceEvent) {
+        // 回调中嵌套处理消息时不再通知
+        if let Ok(mut hook) = self.trace.try_borrow_mut() {
+            if let Some(ref mut f) = *hook {
+                f(ev);
+            }
+        }
+    }
+
+    /// 把到期时间向后对齐到合并窗口的整数倍，已到期的时间不做调整
+    fn coalesce(&self, time: Instant) -> Instant {
+        let window = match self.coalescing.get() {
+            Some(window) => window.as_nanos(),
+            None => return time,
+        };
+        if time <= Instant::now() || time <= self.epoch {
+            return time;
+        }
+        let offset = (time - self.epoch).as_nanos();
+        let rounded = match (offset + window - 1).checked_div(window) {
+            Some(n) => n * window,
+            None => return time,
+        };
+        let secs = rounded / 1_000_000_000;
+        if secs > u64::MAX as u128 {
+            return time;
+        }
+        let dur = Duration::new(secs as u64, (rounded % 1_000_000_000) as u32);
+        self.epoch.checked_add(dur).unwrap_or(time)
+    }
+
+    /// 处理消息和定时器，直到循环被要求退出、到达 deadline 或者 woken 被设置
+    ///
+    /// 调用前 state 必须已经是 Running，因退出返回时 state 保持为 Stopping，由调用者处理。
+    /// 可以在回调中嵌套调用。
+    #[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+    fn pump(&self, deadline: Option<Instant>, woken: Option<&AtomicBool>) {
+        // 在回调中嵌套调用时，外层回调 defer 的函数等它返回后再执行
+        let _outer = DeferScope::new(self);
+        process_msgs(self);
+        self.process_timers();
+        let mut ctrl = self.core.lock();
+        loop {
+            match ctrl.state {
+                State::Stopping => {
+                    return;
+                },
+                State::Waiting | State::MsgArrived => {
+                    self.core.end_wait(&mut ctrl);
+                },
+                State::Running => {},
+                State::Stopped | State::External | State::Dead => unreachable!(),
+            }
+            if woken.is_some_and(|w| w.load(Ordering::SeqCst)) {
+                return;
+            }
+            let remaining = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return;
+                    }
+                    Some(deadline - now)
+                },
+                None => None,
+            };
+            if !self.core.queue.is_empty() {
+                drop(ctrl);
+                process_msgs(self);
+                self.process_timers();
+                ctrl = self.core.lock();
+                continue;
+            }
+            let waiting = match (self.calculate_waiting_time(), remaining) {
+                (WaitingTime::Infinite, Some(rem)) => WaitingTime::Duration(rem),
+                (WaitingTime::Duration(dur), Some(rem)) if rem < dur => WaitingTime::Duration(rem),
+                (waiting, _) => waiting,
+            };
+            if !matches!(waiting, WaitingTime::Zero) && self.idle.borrow().is_some() {
+                drop(ctrl);
+                let worked = self.run_idle();
+                ctrl = self.core.lock();
+                if worked {
+                    continue;
+                }
+            }
+            match waiting {
+                WaitingTime::Zero => {
+                    drop(ctrl);
+                    self.process_timers();
+                    ctrl = self.core.lock();
+                },
+                WaitingTime::Infinite => {
+                    self.timers.borrow_mut().compact();
+                    if self.core.prepare_wait(&mut ctrl) {
+                        ctrl = self.wait(ctrl, None).0;
+                    }
+                },
+                WaitingTime::Duration(dur) => {
+                    self.timers.borrow_mut().compact();
+                    if !self.core.prepare_wait(&mut ctrl) {
+                        continue;
+                    }
+                    let (lck, timed_out) = self.wait(ctrl, Some(dur));
+                    if timed_out {
+                        drop(lck);
+                        self.process_timers();
+                        ctrl = self.core.lock();
+                    }
+                    else {
+                        ctrl = lck;
+                    }
+                }
+            }
+        }
+    }
+
+    /// 在 ctrl.state 为 Waiting 时等待，返回是否超时
+    #[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+    fn wait<'a>(&'a self, ctrl: MutexGuard<'a, core::Control>, timeout: Option<Duration>) -> (MutexGuard<'a, core::Control>, bool) {
+        #[cfg(feature = "io")]
+        {
+            if self.io.borrow().is_some() {
+                drop(ctrl);
+                match io::poll(&self.io, timeout) {
+                    Ok(timed_out) => return (self.core.lock(), timed_out),
+                    Err(e) => {
+                        // 无法继续等待，保存错误后退出，见 `io::take_error`
+                        self.io.borrow_mut().as_mut().unwrap().set_error(e);
+                        self.core.stop();
+                        return (self.core.lock(), false);
+                    },
+                }
             }
         }
+        #[cfg(feature = "futex")]
+        {
+            if let Some(seq) = ctrl.futex_seq {
+                drop(ctrl);
+                self.core.futex.wait(seq, timeout);
+                let ctrl = self.core.lock();
+                // 没有被投递或退出唤醒，视为超时
+                let timed_out = timeout.is_some() && self.core.futex.seq() == seq;
+                return (ctrl, timed_out);
+            }
+        }
+        #[cfg(all(windows, feature = "windows-pump"))]
+        {
+            if let Some(event) = ctrl.pump.clone() {
+                drop(ctrl);
+                let timed_out = win_pump::wait(&event, timeout);
+                return (self.core.lock(), timed_out);
+            }
+        }
+        if ctrl.park.is_some() {
+            drop(ctrl);
+            match timeout {
+                None => thread::park(),
+                Some(dur) => thread::park_timeout(dur),
+            }
+            let ctrl = self.core.lock();
+            // 没有被投递或退出唤醒，视为超时，由调用者重新检查定时器
+            let timed_out = timeout.is_some() && ctrl.state == State::Waiting;
+            return (ctrl, timed_out);
+        }
+        match timeout {
+            None => (self.core.cond.wait(ctrl).unwrap_or_else(|e| e.into_inner()), false),
+            Some(dur) => {
+                let (lck, r) = self.core.cond.wait_timeout(ctrl, dur).unwrap_or_else(|e| e.into_inner());
+                (lck, r.timed_out())
+            },
+        }
     }
 
     fn calculate_waiting_time(&self) -> WaitingTime {
@@ -123,13 +1086,58 @@ impl RunLoop {
 }
 
 thread_local! {
+     /// 当前线程的循环是否已经创建，见 `RunLoopBuilder::install`
+     static CREATED: Cell<bool> = const { Cell::new(false) };
+     /// `RunLoopBuilder::install` 指定的队列分片数
+     static SHARDS: Cell<Option<usize>> = const { Cell::new(None) };
      static RUN_LOOP: RunLoop = RunLoop {
-         core: Arc::new(Core::new()),
-         timers: RefCell::new(core::TimedActionBinaryHeap::new()),
+         core: Arc::new(Core::new(create_shards())),
+         timers: RefCell::new(core::TimerQueue::new(TimerStrategy::Heap)),
          objects: RefCell::new(object::ObjectList::new()),
+         exit_hooks: RefCell::new(Vec::new()),
+         epoch: Instant::now(),
+         processed: Cell::new(0),
+         coalescing: Cell::new(None),
+         msg_batch: Cell::new(Some(DEFAULT_MSG_BATCH)),
+         timer_batch: Cell::new(None),
+         due_buf: Cell::new(Vec::new()),
+         deferred: RefCell::new(VecDeque::new()),
+         idle: RefCell::new(None),
+         dispatching: Cell::new(0),
+         trace: RefCell::new(None),
+         watchdog: RefCell::new(None),
+         panic_policy: Cell::new(PanicPolicy::Report),
+         stop_policy: Cell::new(StopPolicy::Immediate),
+         registry: RefCell::new(registry::Registry::new()),
+         tasks: RefCell::new(spawn::Tasks::new()),
+         #[cfg(feature = "io")]
+         io: RefCell::new(None),
      };
 }
 
+/// 创建当前线程的循环时使用的队列分片数
+fn create_shards() -> usize {
+    CREATED.with(|c| c.set(true));
+    SHARDS.with(|s| s.get()).unwrap_or(1)
+}
+
+/// 以 shards 个队列分片创建当前线程的循环，已经创建时返回错误
+fn create_loop(shards: Option<usize>) -> Result<(), InstallError> {
+    if CREATED.with(|c| c.get()) {
+        return Err(InstallError);
+    }
+    SHARDS.with(|s| s.set(shards));
+    RUN_LOOP.with(|_| {});
+    Ok(())
+}
+
+fn set_policies(panic_policy: PanicPolicy, stop_policy: StopPolicy) {
+    RUN_LOOP.with(|rl| {
+        rl.panic_policy.set(panic_policy);
+        rl.stop_policy.set(stop_policy);
+    })
+}
+
 /// 使当前线程的消息循环退出
 pub fn stop() {
     RUN_LOOP.with(|rl| {
@@ -137,164 +1145,2445 @@ pub fn stop() {
     })
 }
 
+/// 注册当前线程的循环退出时执行的函数，后注册的先执行
+///
+/// 这些函数在线程结束、循环销毁时执行，早于剩余的消息、定时器和循环内对象的释放。
+/// 此时循环已经不可访问，函数中不能再调用 `run_loop` 的函数。
+///
+/// ```
+/// use vnbase::run_loop;
+/// use std::sync::{Arc, Mutex};
+/// use std::thread;
+///
+/// let order = Arc::new(Mutex::new(Vec::new()));
+/// let o = order.clone();
+/// thread::spawn(move || {
+///     for i in 0..3 {
+///         let o = o.clone();
+///         run_loop::on_exit(move || o.lock().unwrap().push(i));
+///     }
+/// }).join().unwrap();
+/// assert_eq!(*order.lock().unwrap(), vec![2, 1, 0]);
+/// ```
+pub fn on_exit<T>(f: T) where T: FnOnce() + 'static {
+    RUN_LOOP.with(|rl| {
+        rl.exit_hooks.borrow_mut().push(Box::new(f));
+    })
+}
+
+/// 设置当前线程的循环没有消息和到期的定时器、即将等待时调用的函数
+///
+/// f 返回 true 表示做了工作，循环重新检查消息和定时器，不进入等待。供 `LoopPool` 取走共享的工作。
+fn set_idle_hook<F>(f: F) where F: FnMut() -> bool + 'static {
+    RUN_LOOP.with(|rl| {
+        *rl.idle.borrow_mut() = Some(Box::new(f));
+    })
+}
+
 /// 在当前线程开始消息循环
+///
+/// 回调 panic 时 `run` 随之 panic，之后可以再次 `run`。其它线程的投递不受影响，
+/// 即使 panic 发生在持有循环内部的锁时（如外部唤醒函数中）。
+///
+/// ```
+/// use vnbase::run_loop;
+/// use std::panic;
+/// use std::sync::Arc;
+/// use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+/// use std::thread;
+///
+/// let handle = run_loop::clone_handle();
+/// let count = Arc::new(AtomicUsize::new(0));
+/// let (h, c) = (handle.clone(), count.clone());
+/// let poster = thread::spawn(move || for _ in 0..1000 {
+///     let c = c.clone();
+///     h.post(move || { c.fetch_add(1, Ordering::SeqCst); });
+/// });
+/// handle.post(|| panic!("callback failed"));
+/// assert!(panic::catch_unwind(run_loop::run).is_err());
+/// poster.join().unwrap();
+/// handle.post(run_loop::stop);
+/// run_loop::run();
+/// assert_eq!(count.load(Ordering::SeqCst), 1000);
+///
+/// // 外部唤醒函数在投递的线程、持有锁时 panic
+/// let panicked = Arc::new(AtomicBool::new(false));
+/// let p = panicked.clone();
+/// run_loop::set_external_waker(move || if !p.swap(true, Ordering::SeqCst) {
+///     panic!("waker failed");
+/// });
+/// run_loop::run_pending();
+/// let h = handle.clone();
+/// assert!(thread::spawn(move || h.post(|| {})).join().is_err());
+/// run_loop::clear_external_waker();
+/// let h = handle.clone();
+/// thread::spawn(move || h.post(run_loop::stop)).join().unwrap();
+/// run_loop::run();
+/// ```
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
 pub fn run() {
     RUN_LOOP.with(|rl| {
-        let mut msgs = rl.core.msgs.lock().unwrap();
-            match msgs.state {
+        let stopping = {
+            let mut ctrl = rl.core.lock();
+            rl.core.leave_external(&mut ctrl);
+            match ctrl.state {
                 State::Stopped => {
-                    msgs.state = State::Running;
-                },
-                State::Stopping => {
-                    msgs.state = State::Stopped;
-                    return;
+                    ctrl.state = State::Running;
+                    false
                 },
-                State::Running => { 
+                State::Stopping => true,
+                State::Running => {
                     return;
                 },
                 _ => unreachable!(),
             }
-            process_msgs(msgs);
-            rl.process_timers();
-            msgs = rl.core.msgs.lock().unwrap();
-            loop {
-                match msgs.state {
-                    State::Stopping => {
-                        msgs.state = State::Stopped;
-                        return;
-                    },
-                    State::Waiting | State::MsgArrived => {
-                        msgs.state = State::Running;
-                    },
-                    State::Running => {},
-                    State::Stopped => unreachable!(),
-                }
-                match process_msgs(msgs) {
-                    Some(lck) => msgs = lck,
-                    None => {
-                        rl.process_timers();
-                        msgs = rl.core.msgs.lock().unwrap();
-                        continue;
-                    }
-                }
-                match rl.calculate_waiting_time() {
-                    WaitingTime::Zero => {
-                        drop(msgs);
-                        rl.process_timers();
-                        msgs = rl.core.msgs.lock().unwrap();
-                    },
-                    WaitingTime::Infinite => {
-                        msgs.state = State::Waiting;
-                        msgs = rl.core.cond.wait(msgs).unwrap();
-                    },
-                    WaitingTime::Duration(dur) => {
-                        msgs.state = State::Waiting;
-                        let (mut lck, r) = rl.core.cond.wait_timeout(msgs, dur).unwrap();
-                        if r.timed_out() {
-                            drop(lck);
-                            rl.process_timers();
-                            msgs = rl.core.msgs.lock().unwrap();
-                        }
-                        else {
-                            msgs = lck;
-                        }
-                    }
-                }
+        };
+        let _stopped = StopOnExit(&rl.core);
+        if !stopping {
+            // 回调之外 defer 的函数
+            rl.run_deferred();
+            match rl.panic_policy.get() {
+                PanicPolicy::Report => rl.pump(None, None),
+                // 从 panic 的回调之后继续
+                PanicPolicy::Restart => while ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| rl.pump(None, None))).is_err() {},
             }
-    })
-}
-
-/// 获得当前线程的循环句柄
-pub fn clone_handle() -> Handle {
-    RUN_LOOP.with(|rl| {
-        Handle {
-            core: rl.core.clone(),
+        }
+        if rl.stop_policy.get() == StopPolicy::Drain {
+            process_msgs_upto(rl, usize::MAX);
         }
     })
 }
 
-/// 判断 handle 是否是当前线程的循环句柄
-pub fn is_own_handle(handle: &Handle) -> bool {
-    RUN_LOOP.with(|rl| Arc::ptr_eq(&rl.core, &handle.core))
+/// 开始由宿主的事件循环驱动当前线程的循环，立即返回，`stop` 之后不再驱动
+///
+/// wasm32 上不能阻塞宿主的线程，投递通过 `queueMicrotask`，定时器和周期历程通过 `setTimeout` 安排处理，
+/// 阻塞等待的函数不存在。驱动期间占用了外部唤醒函数，不要再调用 `set_external_waker`、`run_pending`。
+/// 循环的时间使用 `web_time::Instant`，`next_timer_deadline` 等返回的也是这个类型。
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub fn run() {
+    wasm::start();
 }
 
-/// 在当前线程创建定时器
-pub fn new_timer() -> Timer {
-    Timer::new()
+/// 设置由外部事件循环驱动时的唤醒函数，见 `run_pending`
+///
+/// `run_pending` 返回后，第一次投递或退出请求在投递的线程调用 f，之后直到下一次 `run_pending` 不再调用。
+/// f 在持有循环内部的锁时调用，只应通知外部事件循环醒来（如 winit 的 `EventLoopProxy::send_event`）。
+pub fn set_external_waker<F>(f: F) where F: Fn() + Send + Sync + 'static {
+    RUN_LOOP.with(|rl| {
+        rl.core.lock().external = Some(Arc::new(f));
+    })
 }
 
-/// 在当前线程创建周期历程
-pub fn new_schedule() -> Schedule {
-    Schedule::new()
+pub fn clear_external_waker() {
+    RUN_LOOP.with(|rl| {
+        let mut ctrl = rl.core.lock();
+        rl.core.leave_external(&mut ctrl);
+        ctrl.external = None;
+    })
 }
 
-/// 在当前线程创建循环内对象
+/// 处理一轮当前线程的消息和到期的定时器后返回，用于把循环嵌入其它框架的事件循环
+///
+/// 外部事件循环每一轮结束、准备等待之前调用（winit 中为 `about_to_wait`），
+/// 按返回值等待：`Wait`、`WaitUntil` 对应 winit `ControlFlow` 的同名值，`Stop` 时退出。
+/// 其它线程的投递通过 `set_external_waker` 设置的函数唤醒外部事件循环。
+/// 定时器、周期历程和循环内对象照常使用。在回调中调用时不处理，返回 `Wait`。
+/// 开启 `winit` 特性时可以直接使用 `run_loop::winit::run_app`。
 ///
 /// # Examples
+/// 用通道模拟外部事件循环，周期历程驱动重绘，其它线程请求退出：
 /// ```
-/// use vnbase::run_loop;
+/// use vnbase::run_loop::{self, PumpStatus};
+/// use std::cell::Cell;
+/// use std::rc::Rc;
+/// use std::sync::mpsc;
 /// use std::thread;
-/// 
-/// trait MyObj {
-///     fn say_hello(&self);
-/// }
-/// 
-/// struct MOA (i32);
-/// impl MyObj for MOA {
-///     fn say_hello(&self) {
-///         println!("MOA: {}", self.0);
-///     }
-/// }
-/// struct MOB (String);
-/// impl MyObj for MOB {
-///     fn say_hello(&self) {
-///         println!("MOB: {}", self.0);
-///     }
-/// }
-/// 
-/// let mut objs: Vec<run_loop::ObjectHandle<Box<MyObj>>> = Vec::new();
-/// objs.push(run_loop::new_object(Box::new(MOA(100))));
-/// objs.push(run_loop::new_object(Box::new(MOB("Hello".to_string()))));
-/// 
+/// use std::time::{Duration, Instant};
+///
+/// // 相当于 winit 的 EventLoopProxy
+/// let (proxy, events) = mpsc::channel();
+/// run_loop::set_external_waker(move || { let _ = proxy.send(()); });
+///
+/// let frames = Rc::new(Cell::new(0));
+/// let f = frames.clone();
+/// let _redraw = run_loop::new_schedule()
+///     .with_period(Duration::from_millis(5))
+///     .with_callback(move |_| f.set(f.get() + 1))
+///     .and_start();
+///
 /// let handle = run_loop::clone_handle();
 /// let th = thread::spawn(move || {
-///     for obj in objs.iter() {
-///         obj.post(|obj| obj.say_hello());
-///     }
+///     thread::sleep(Duration::from_millis(50));
 ///     handle.post(run_loop::stop);
 /// });
-/// run_loop::run();
+///
+/// loop {
+///     match run_loop::run_pending() {
+///         PumpStatus::Stop => break,
+///         PumpStatus::Wait => events.recv().unwrap(),
+///         PumpStatus::WaitUntil(t) => {
+///             let _ = events.recv_timeout(t.saturating_duration_since(Instant::now()));
+///         },
+///     }
+/// }
 /// th.join().unwrap();
+/// assert!(frames.get() >= 3);
+///
+/// // 之后仍然可以用 run 运行
+/// run_loop::clear_external_waker();
+/// run_loop::clone_handle().post(run_loop::stop);
+/// run_loop::run();
 /// ```
-pub fn new_object<T>(obj: T) -> ObjectHandle<T> where T: 'static {
-    RUN_LOOP.with(move |rl| {
-        rl.objects.borrow_mut().create(obj)
-    })
-}
+pub fn run_pending() -> PumpStatus {
+    RUN_LOOP.with(|rl| {
+        {
+            let mut ctrl = rl.core.lock();
+            rl.core.leave_external(&mut ctrl);
+            match ctrl.state {
+                State::Stopped => {
+                    ctrl.state = State::Running;
+                },
+                State::Stopping => {
+                    ctrl.state = State::Stopped;
+                    return PumpStatus::Stop;
+                },
+                _ => {
+                    return PumpStatus::Wait;
+                },
+            }
+        }
+        let stopped = StopOnExit(&rl.core);
+        rl.run_deferred();
+        process_msgs(rl);
+        rl.process_timers();
+        mem::forget(stopped);
 
-fn process_msgs(mut msgs: MutexGuard<core::MsgQueue>) -> Option<MutexGuard<core::MsgQueue>> {
-    let mut node = msgs.drain();
-    if let Some(mut msg) = node {
-        drop(msgs);
-        node = msg.process();
-        while let Some(mut msg) = node {
-            node = msg.process();
+        let mut ctrl = rl.core.lock();
+        if ctrl.state == State::Stopping {
+            ctrl.state = State::Stopped;
+            return PumpStatus::Stop;
+        }
+        ctrl.state = State::Stopped;
+        rl.core.prepare_external_wait(&mut ctrl);
+        let now = Instant::now();
+        if !rl.core.queue.is_empty() {
+            return PumpStatus::WaitUntil(now);
+        }
+        match rl.calculate_waiting_time() {
+            WaitingTime::Zero => PumpStatus::WaitUntil(now),
+            WaitingTime::Infinite => PumpStatus::Wait,
+            WaitingTime::Duration(d) => PumpStatus::WaitUntil(core::saturating_deadline(now, d)),
+        }
+    })
+}
+
+/// 反复处理当前线程已经投递的消息和已经到期的定时器，直到都没有时返回，不等待之后到期的定时器
+///
+/// 返回执行的消息和定时器的个数。处理中投递的消息、启动后立即到期的定时器都在返回前处理，
+/// 适合在测试中确定地推进循环。回调调用 `stop` 时处理完当前一轮后返回，退出请求随之清除；
+/// 调用前已经请求退出时不做处理。在回调中调用时不处理，返回 0。
+///
+/// ```
+/// use vnbase::run_loop;
+/// use std::cell::RefCell;
+/// use std::rc::Rc;
+/// use std::time::{Duration, Instant};
+///
+/// let log = Rc::new(RefCell::new(Vec::new()));
+/// let l = log.clone();
+/// run_loop::yield_now(move || {
+///     l.borrow_mut().push("first");
+///     let l = l.clone();
+///     run_loop::yield_now(move || l.borrow_mut().push("posted while processing"));
+/// });
+/// let l = log.clone();
+/// let due = run_loop::new_timer().with_callback_once(move || l.borrow_mut().push("due")).and_start(Duration::from_millis(0));
+/// let l = log.clone();
+/// let later = run_loop::new_timer().with_callback_once(move || l.borrow_mut().push("later")).and_start(Duration::from_secs(60));
+///
+/// let start = Instant::now();
+/// assert_eq!(run_loop::process_until_idle(), 3);
+/// assert!(start.elapsed() < Duration::from_secs(1));
+/// assert_eq!(*log.borrow(), vec!["first", "due", "posted while processing"]);
+/// assert!(!due.is_active() && later.is_active());
+/// assert_eq!(run_loop::process_until_idle(), 0);
+///
+/// // stop 结束处理，之后投递的消息留到下一次
+/// run_loop::yield_now(|| {
+///     run_loop::stop();
+///     run_loop::yield_now(|| {});
+/// });
+/// assert_eq!(run_loop::process_until_idle(), 1);
+/// assert_eq!(run_loop::process_until_idle(), 1);
+/// later.cancel();
+/// ```
+pub fn process_until_idle() -> usize {
+    RUN_LOOP.with(|rl| {
+        {
+            let mut ctrl = rl.core.lock();
+            rl.core.leave_external(&mut ctrl);
+            match ctrl.state {
+                State::Stopped => {
+                    ctrl.state = State::Running;
+                },
+                State::Stopping => {
+                    ctrl.state = State::Stopped;
+                    return 0;
+                },
+                _ => {
+                    return 0;
+                },
+            }
+        }
+        let _stopped = StopOnExit(&rl.core);
+        rl.run_deferred();
+        let mut total = 0;
+        loop {
+            let n = process_msgs(rl) + rl.process_timers();
+            total += n;
+            if n == 0 || rl.core.lock().state == State::Stopping {
+                return total;
+            }
+        }
+    })
+}
+
+/// 离开 `run` 时把循环标记为停止，回调 panic 时同样如此，之后可以再次 `run`
+struct StopOnExit<'a> (&'a Core);
+
+impl<'a> Drop for StopOnExit<'a> {
+    fn drop(&mut self) {
+        self.0.lock().state = State::Stopped;
+    }
+}
+
+/// 把 continuation 投递到当前线程队列的末尾后返回，让已经在排队的消息先执行
+///
+/// 只投递到当前线程，因此不要求 `Send`。适合把耗时的工作拆成多段。
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop;
+/// use std::rc::Rc;
+/// use std::cell::RefCell;
+///
+/// fn work(chunk: u32, log: Rc<RefCell<Vec<String>>>) {
+///     log.borrow_mut().push(format!("chunk {}", chunk));
+///     if chunk < 2 {
+///         run_loop::yield_now(move || work(chunk + 1, log));
+///     }
+///     else {
+///         run_loop::stop();
+///     }
+/// }
+///
+/// let log = Rc::new(RefCell::new(Vec::new()));
+/// let l = log.clone();
+/// run_loop::yield_now(move || work(0, l));
+/// run_loop::clone_handle().post(|| println!("queued"));
+/// let l = log.clone();
+/// run_loop::yield_now(move || l.borrow_mut().push(String::from("other")));
+///
+/// run_loop::run();
+/// assert_eq!(*log.borrow(), vec!["chunk 0", "other", "chunk 1", "chunk 2"]);
+/// ```
+pub fn yield_now<T>(continuation: T) where T: FnOnce() + 'static {
+    post_local(continuation);
+}
+
+/// 向当前线程的循环投递分步执行的任务，f 返回 `Continue` 时重新排到队列末尾，返回 `Break` 时结束
+///
+/// 每次执行一步后让出，其它消息和定时器在两步之间执行，适合不能阻塞循环的长任务。
+/// 只投递到当前线程，因此不要求 `Send`。
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop;
+/// use std::cell::RefCell;
+/// use std::ops::ControlFlow;
+/// use std::rc::Rc;
+///
+/// let log = Rc::new(RefCell::new(Vec::new()));
+/// for name in &["a", "b"] {
+///     let log = log.clone();
+///     let mut step = 0;
+///     run_loop::post_stepwise(move || {
+///         step += 1;
+///         log.borrow_mut().push(format!("{}{}", name, step));
+///         if step < 3 { ControlFlow::Continue(()) } else { ControlFlow::Break(()) }
+///     });
+/// }
+/// run_loop::yield_now(|| {
+///     run_loop::yield_now(|| {
+///         run_loop::yield_now(run_loop::stop);
+///     });
+/// });
+/// run_loop::run();
+///
+/// assert_eq!(*log.borrow(), vec!["a1", "b1", "a2", "b2", "a3", "b3"]);
+/// ```
+pub fn post_stepwise<F>(f: F) where F: FnMut() -> ControlFlow<()> + 'static {
+    fn step<F>(mut f: F) where F: FnMut() -> ControlFlow<()> + 'static {
+        if let ControlFlow::Continue(()) = f() {
+            post_local(move || step(f));
+        }
+    }
+    post_local(move || step(f));
+}
+
+/// 在当前执行的消息或定时器回调返回后、取下一条消息之前执行 f
+///
+/// 和 `yield_now` 不同，f 不排到队列末尾，先于其它已经在排队的消息执行。
+/// 同一个回调中 defer 的函数按顺序执行，f 中再次 defer 的函数也在本轮执行。
+/// 回调中嵌套处理消息（如 `sleep_blocking`）时，f 等到外层回调返回后才执行；
+/// 在回调之外调用时，f 在下一次 `run` 处理消息之前执行。
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop;
+/// use std::cell::RefCell;
+/// use std::rc::Rc;
+///
+/// let log = Rc::new(RefCell::new(Vec::new()));
+/// let l = log.clone();
+/// run_loop::yield_now(move || {
+///     let l1 = l.clone();
+///     run_loop::defer(move || {
+///         l1.borrow_mut().push("cleanup");
+///         let l2 = l1.clone();
+///         run_loop::defer(move || l2.borrow_mut().push("nested cleanup"));
+///     });
+///     let l1 = l.clone();
+///     run_loop::yield_now(move || l1.borrow_mut().push("requeued"));
+///     l.borrow_mut().push("handler");
+/// });
+/// let l = log.clone();
+/// run_loop::yield_now(move || l.borrow_mut().push("next"));
+/// run_loop::yield_now(|| run_loop::yield_now(run_loop::stop));
+///
+/// run_loop::run();
+/// assert_eq!(*log.borrow(), vec!["handler", "cleanup", "nested cleanup", "next", "requeued"]);
+/// ```
+pub fn defer<F>(f: F) where F: FnOnce() + 'static {
+    RUN_LOOP.with(|rl| {
+        rl.deferred.borrow_mut().push_back(Box::new(f));
+    })
+}
+
+/// 阻塞当前线程 d 时间，期间继续处理消息和定时器
+///
+/// 和 `thread::sleep` 不同，等待期间投递的函数和到期的定时器照常执行，因此可能重入调用者的回调。
+/// 只能在循环所在的线程调用，可以在循环的回调中调用。循环被要求退出时提前返回，
+/// 退出请求保留给外层的 `run`；在 `run` 之外调用时，下一次 `run` 会立即返回。
+///
+/// ```
+/// use vnbase::run_loop;
+/// use std::sync::{Arc, Mutex};
+/// use std::time::{Duration, Instant};
+///
+/// let log = Arc::new(Mutex::new(Vec::new()));
+/// let handle = run_loop::clone_handle();
+///
+/// let l = log.clone();
+/// handle.post(move || {
+///     l.lock().unwrap().push("first");
+///     let start = Instant::now();
+///     run_loop::sleep_blocking(Duration::from_millis(50));
+///     assert!(start.elapsed() >= Duration::from_millis(50));
+///     l.lock().unwrap().push("woke");
+/// });
+/// let l = log.clone();
+/// handle.post(move || l.lock().unwrap().push("second"));
+///
+/// let l = log.clone();
+/// let _timer = run_loop::new_timer()
+///     .with_callback_once(move || l.lock().unwrap().push("timer"))
+///     .and_start(Duration::from_millis(10));
+/// let _stop = run_loop::new_timer()
+///     .with_callback_once(run_loop::stop)
+///     .and_start(Duration::from_millis(100));
+///
+/// run_loop::run();
+/// assert_eq!(*log.lock().unwrap(), vec!["first", "second", "timer", "woke"]);
+/// ```
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+pub fn sleep_blocking(d: Duration) {
+    let deadline = core::saturating_deadline(Instant::now(), d);
+    RUN_LOOP.with(|rl| {
+        let outside = {
+            let mut ctrl = rl.core.lock();
+            rl.core.leave_external(&mut ctrl);
+            match ctrl.state {
+                State::Stopping => return,
+                State::Stopped => {
+                    ctrl.state = State::Running;
+                    true
+                },
+                _ => false,
+            }
+        };
+        rl.pump(Some(deadline), None);
+        if outside {
+            let mut ctrl = rl.core.lock();
+            if ctrl.state != State::Stopping {
+                ctrl.state = State::Stopped;
+            }
+        }
+    })
+}
+
+/// 在当前线程运行循环直到 fut 完成，返回它的结果
+///
+/// 等待期间照常处理消息和定时器，fut 被唤醒时重新轮询，唤醒可以来自任何线程。
+/// 可以在 `run` 之外调用，也可以在循环的回调中调用。等待期间循环被要求退出时继续运行直到 fut 完成，
+/// 退出请求保留给外层的 `run`；在 `run` 之外调用时，下一次 `run` 会立即返回。
+///
+/// ```
+/// use vnbase::run_loop;
+/// use std::thread;
+/// use std::time::Duration;
+///
+/// let (tx, rx) = run_loop::oneshot();
+/// let ticks = std::rc::Rc::new(std::cell::Cell::new(0));
+/// let t = ticks.clone();
+/// let _schedule = run_loop::repeat_while(Duration::from_millis(1), move || {
+///     t.set(t.get() + 1);
+///     true
+/// });
+/// let th = thread::spawn(move || {
+///     thread::sleep(Duration::from_millis(30));
+///     tx.send(42).unwrap();
+/// });
+///
+/// assert_eq!(run_loop::block_on(rx), Ok(42));
+/// assert!(ticks.get() > 0);
+/// th.join().unwrap();
+///
+/// // 之后仍然可以运行循环
+/// run_loop::clone_handle().post(run_loop::stop);
+/// run_loop::run();
+/// assert_eq!(run_loop::block_on(std::future::ready(2)), 2);
+/// ```
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+pub fn block_on<F>(fut: F) -> F::Output where F: Future {
+    let mut fut = Box::pin(fut);
+    RUN_LOOP.with(|rl| {
+        let waker = Arc::new(LoopWaker {
+            handle: Handle { core: rl.core.clone() },
+            woken: AtomicBool::new(false),
+        });
+        let task_waker = task::Waker::from(waker.clone());
+        let mut cx = task::Context::from_waker(&task_waker);
+        let mut restore = RestoreState {
+            core: &rl.core,
+            outside: false,
+            stopping: false,
+        };
+        {
+            let mut ctrl = rl.core.lock();
+            rl.core.leave_external(&mut ctrl);
+            match ctrl.state {
+                State::Stopping => {
+                    restore.stopping = true;
+                    ctrl.state = State::Running;
+                },
+                State::Stopped => {
+                    restore.outside = true;
+                    ctrl.state = State::Running;
+                },
+                _ => {},
+            }
+        }
+        loop {
+            waker.woken.store(false, Ordering::SeqCst);
+            if let Poll::Ready(output) = fut.as_mut().poll(&mut cx) {
+                return output;
+            }
+            rl.pump(None, Some(&waker.woken));
+            let mut ctrl = rl.core.lock();
+            if ctrl.state == State::Stopping {
+                restore.stopping = true;
+                ctrl.state = State::Running;
+            }
+        }
+    })
+}
+
+/// 在当前线程的循环上运行不要求 `Send` 的 `Future`，循环运行时开始轮询
+///
+/// 和 `Handle::spawn` 共用循环中的任务表，任务在完成或者循环销毁时释放。
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop;
+/// use std::cell::Cell;
+/// use std::future::Future;
+/// use std::pin::Pin;
+/// use std::rc::Rc;
+/// use std::task::{Context, Poll};
+/// use std::time::Duration;
+///
+/// // 收到计时器的结果后记录下来
+/// struct Record (run_loop::oneshot::Receiver<u32>, Rc<Cell<u32>>);
+/// impl Future for Record {
+///     type Output = ();
+///     fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+///         match Pin::new(&mut self.0).poll(cx) {
+///             Poll::Ready(v) => {
+///                 self.1.set(v.unwrap());
+///                 run_loop::stop();
+///                 Poll::Ready(())
+///             },
+///             Poll::Pending => Poll::Pending,
+///         }
+///     }
+/// }
+///
+/// let (tx, rx) = run_loop::oneshot();
+/// let _timer = run_loop::new_timer()
+///     .with_callback_once(move || tx.send(7).unwrap())
+///     .and_start(Duration::from_millis(10));
+///
+/// let result = Rc::new(Cell::new(0));
+/// run_loop::spawn_local(Record(rx, result.clone()));
+/// assert_eq!(result.get(), 0);
+/// run_loop::run();
+/// assert_eq!(result.get(), 7);
+/// ```
+pub fn spawn_local<F>(fut: F) where F: Future<Output = ()> + 'static {
+    spawn::spawn_local(Box::pin(fut));
+}
+
+/// 唤醒 `block_on` 的循环，投递一个空函数让等待中的循环醒来
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+struct LoopWaker {
+    handle: Handle,
+    woken: AtomicBool,
+}
+
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+impl Wake for LoopWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if !self.woken.swap(true, Ordering::SeqCst) {
+            self.handle.post(|| {});
+        }
+    }
+}
+
+/// 离开 `block_on` 时恢复循环的状态，包括 panic 时
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+struct RestoreState<'a> {
+    core: &'a Core,
+    outside: bool,
+    stopping: bool,
+}
+
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+impl<'a> Drop for RestoreState<'a> {
+    fn drop(&mut self) {
+        let mut ctrl = self.core.lock();
+        if self.stopping {
+            ctrl.state = State::Stopping;
+        }
+        else if self.outside {
+            ctrl.state = State::Stopped;
+        }
+    }
+}
+
+/// 设置当前线程循环的名字，用于日志和调试输出
+///
+/// 没有设置时为循环所在线程的名字。名字可以通过 `Handle::name` 在其它线程读取，
+/// 也出现在 `Handle` 的 `Debug` 输出和 `metrics` 中。
+///
+/// ```
+/// use vnbase::run_loop;
+/// use std::thread;
+///
+/// let handle = thread::Builder::new().name(String::from("worker")).spawn(|| {
+///     let handle = run_loop::clone_handle();
+///     assert_eq!(run_loop::name().as_ref().map(|s| &s[..]), Some("worker"));
+///     run_loop::set_name("io-1");
+///     assert_eq!(run_loop::metrics().name, Some(String::from("io-1")));
+///     handle
+/// }).unwrap().join().unwrap();
+///
+/// assert_eq!(handle.name(), Some(String::from("io-1")));
+/// assert!(format!("{:?}", handle).contains("io-1"));
+/// ```
+pub fn set_name(name: &str) {
+    RUN_LOOP.with(|rl| {
+        *core::lock(&rl.core.name) = Some(String::from(name));
+    })
+}
+
+/// 当前线程循环的名字，见 `set_name`
+pub fn name() -> Option<String> {
+    RUN_LOOP.with(|rl| core::lock(&rl.core.name).clone())
+}
+
+/// 收到信号时在当前线程的循环中调用 f，需要开启 `signal` 特性
+///
+/// 信号处理函数只向管道写入信号编号，f 在之后由循环执行，可以做任何事情。
+/// 同一个信号的所有注册都会执行。返回的 `SignalHandle` 释放时注销，
+/// 同一个信号的最后一个注册注销时恢复注册前的处理方式。
+/// 短时间内多次到达的同一个信号可能只执行一次。
+///
+/// # Examples
+/// ```
+/// extern crate libc;
+/// extern crate vnbase;
+///
+/// use vnbase::run_loop::{self, Signal};
+/// use std::cell::RefCell;
+/// use std::mem;
+/// use std::ptr;
+/// use std::rc::Rc;
+///
+/// # fn main() {
+/// // 注册前忽略这个信号，注销后应当恢复
+/// unsafe { libc::signal(libc::SIGUSR2, libc::SIG_IGN); }
+///
+/// let log = Rc::new(RefCell::new(Vec::new()));
+/// let handles: Vec<_> = ["a", "b"].iter().map(|&name| {
+///     let log = log.clone();
+///     run_loop::on_signal(Signal::USR2, move || {
+///         log.borrow_mut().push((name, run_loop::current_id()));
+///         if log.borrow().len() == 2 {
+///             run_loop::stop();
+///         }
+///     }).unwrap()
+/// }).collect();
+///
+/// unsafe { libc::raise(libc::SIGUSR2); }
+/// run_loop::run();
+/// let id = run_loop::current_id();
+/// assert_eq!(*log.borrow(), vec![("a", id), ("b", id)]);
+///
+/// drop(handles);
+/// let disposition = unsafe {
+///     let mut old: libc::sigaction = mem::zeroed();
+///     libc::sigaction(libc::SIGUSR2, ptr::null(), &mut old);
+///     old.sa_sigaction
+/// };
+/// assert_eq!(disposition, libc::SIG_IGN);
+/// # }
+/// ```
+#[cfg(all(unix, feature = "signal"))]
+pub fn on_signal<F>(signal: Signal, f: F) -> ::std::io::Result<SignalHandle> where F: FnMut() + 'static {
+    unix_signal::register(signal, Box::new(f))
+}
+
+/// 获得当前线程的循环句柄
+pub fn clone_handle() -> Handle {
+    RUN_LOOP.with(|rl| {
+        Handle {
+            core: rl.core.clone(),
+        }
+    })
+}
+
+/// 判断 handle 是否是当前线程的循环句柄
+///
+/// 当前线程的循环正在销毁或已经销毁时返回 false
+pub fn is_own_handle(handle: &Handle) -> bool {
+    RUN_LOOP.try_with(|rl| Arc::ptr_eq(&rl.core, &handle.core)).unwrap_or(false)
+}
+
+/// 当前线程是否正在循环中执行消息、定时器回调或者 `LoopPool` 取走的工作
+///
+/// `run` 开始之前、返回之后，或者不在回调中时返回 false，可以用来检查函数是否在循环的回调中调用。
+/// 当前线程的循环正在销毁或已经销毁时返回 false。
+///
+/// ```
+/// use vnbase::run_loop;
+/// use std::cell::Cell;
+/// use std::rc::Rc;
+/// use std::time::Duration;
+///
+/// assert!(!run_loop::in_loop());
+///
+/// let seen = Rc::new(Cell::new((false, false)));
+/// let s = seen.clone();
+/// run_loop::yield_now(move || {
+///     let s2 = s.clone();
+///     s.set((run_loop::in_loop(), false));
+///     run_loop::new_timer().with_callback_once(move || {
+///         s2.set((s2.get().0, run_loop::in_loop()));
+///         run_loop::stop();
+///     }).and_start(Duration::from_millis(1));
+/// });
+/// run_loop::run();
+/// assert_eq!(seen.get(), (true, true));
+/// assert!(!run_loop::in_loop());
+/// ```
+pub fn in_loop() -> bool {
+    RUN_LOOP.try_with(|rl| rl.dispatching.get() > 0).unwrap_or(false)
+}
+
+/// 正在执行的回调是在哪里投递或设置的，需要开启 `debug-origin` 特性
+///
+/// 记录 `Handle::post`、`ObjectHandle::post` 的调用位置，以及定时器、周期历程设置回调的位置，
+/// 其它方式投递的消息和不在回调中时为 `None`。在 panic hook 中可以据此报告 panic 的回调的来源；
+/// 跟踪回调收到 `MsgEnd`、`TimerEnd` 时仍然是刚结束的回调的位置，可以据此找出耗时的回调。
+///
+/// ```
+/// use vnbase::run_loop::{self, TraceEvent};
+/// use std::cell::RefCell;
+/// use std::panic;
+/// use std::rc::Rc;
+/// use std::sync::{Arc, Mutex};
+/// use std::time::Duration;
+///
+/// let seen = Rc::new(RefCell::new(Vec::new()));
+/// let s = seen.clone();
+/// run_loop::set_trace_hook(move |ev| if let TraceEvent::MsgEnd { .. } | TraceEvent::TimerEnd { .. } = ev {
+///     let loc = run_loop::current_origin().unwrap();
+///     s.borrow_mut().push((loc.file(), loc.line()));
+/// });
+/// let handle = run_loop::clone_handle();
+/// let posted = (file!(), line!()); handle.post(|| assert!(run_loop::current_origin().is_some()));
+/// let obj = run_loop::new_object(());
+/// let obj_posted = (file!(), line!()); obj.post(|_| {});
+/// let set = (file!(), line!()); let timer = run_loop::new_timer().with_callback_once(run_loop::stop);
+/// timer.start(Duration::from_millis(1));
+/// run_loop::run();
+/// run_loop::clear_trace_hook();
+/// assert_eq!(*seen.borrow(), vec![posted, obj_posted, set]);
+/// assert_eq!(run_loop::current_origin(), None);
+/// assert!(format!("{:?}", timer).contains(&format!("{}:{}", set.0, set.1)));
+///
+/// let line = line!(); let schedule = run_loop::new_schedule().with_callback(|_| {});
+/// assert!(format!("{:?}", schedule).contains(&format!("{}:{}", file!(), line)));
+///
+/// // panic hook 中报告 panic 的消息的来源
+/// let reported = Arc::new(Mutex::new(None));
+/// let r = reported.clone();
+/// let prev = panic::take_hook();
+/// panic::set_hook(Box::new(move |_| {
+///     *r.lock().unwrap() = run_loop::current_origin().map(|loc| loc.line());
+/// }));
+/// let line = line!(); handle.post(|| panic!("callback failed"));
+/// assert!(panic::catch_unwind(run_loop::run).is_err());
+/// panic::set_hook(prev);
+/// assert_eq!(*reported.lock().unwrap(), Some(line));
+/// ```
+#[cfg(feature = "debug-origin")]
+pub fn current_origin() -> Option<&'static ::std::panic::Location<'static>> {
+    origin::current()
+}
+
+/// 当前线程循环的标识
+///
+/// 当前线程的循环正在销毁或已经销毁时返回 0，不会和任何循环的标识相同
+pub fn current_id() -> u64 {
+    RUN_LOOP.try_with(|rl| rl.core.id).unwrap_or(0)
+}
+
+/// 当前线程下一个定时器或周期历程的到期时间，没有时返回 None
+pub fn next_timer_deadline() -> Option<Instant> {
+    RUN_LOOP.with(|rl| rl.timers.borrow().peek_time())
+}
+
+/// 当前线程距离下一个定时器到期还需等待的时间，已到期时返回零，没有时返回 None
+///
+/// 用于把循环嵌入其它事件系统时计算宿主的等待超时
+///
+/// ```
+/// use vnbase::run_loop;
+/// use std::time::Duration;
+///
+/// assert_eq!(run_loop::next_wait_duration(), None);
+///
+/// let _timer = run_loop::new_timer()
+///     .with_callback(|| {})
+///     .and_start(Duration::from_secs(10));
+/// assert!(run_loop::next_wait_duration().unwrap() <= Duration::from_secs(10));
+/// assert!(run_loop::next_timer_deadline().is_some());
+/// ```
+pub fn next_wait_duration() -> Option<Duration> {
+    RUN_LOOP.with(|rl| {
+        match rl.calculate_waiting_time() {
+            WaitingTime::Infinite => None,
+            WaitingTime::Zero => Some(Duration::from_secs(0)),
+            WaitingTime::Duration(dur) => Some(dur),
+        }
+    })
+}
+
+/// 当前线程循环中已经启动的定时器数，包括周期历程
+///
+/// 需要分别统计时使用 `metrics` 的 `active_timers` 和 `active_schedules`。
+///
+/// ```
+/// use vnbase::run_loop;
+/// use std::time::Duration;
+///
+/// let before = run_loop::active_timer_count();
+/// let timer = run_loop::new_timer().with_callback(|| {}).and_start(Duration::from_secs(10));
+/// let schedule = run_loop::new_schedule().with_callback(|_| {}).and_start();
+/// assert_eq!(run_loop::active_timer_count(), before + 2);
+///
+/// timer.cancel();
+/// assert_eq!(run_loop::active_timer_count(), before + 1);
+/// schedule.cancel();
+/// assert_eq!(run_loop::active_timer_count(), before);
+/// ```
+pub fn active_timer_count() -> usize {
+    RUN_LOOP.with(|rl| rl.timers.borrow().len())
+}
+
+/// 为当前线程的循环预留至少 additional 个定时器的空间
+///
+/// 将要同时启动大量定时器时预先调用，避免逐步扩容。循环空闲时，
+/// 定时器远少于容量的部分会被释放。使用时间轮时不做处理。
+///
+/// ```
+/// use vnbase::run_loop;
+/// use std::cell::RefCell;
+/// use std::rc::Rc;
+/// use std::time::Duration;
+///
+/// run_loop::reserve_timers(1000);
+/// let capacity = run_loop::timer_capacity();
+/// assert!(capacity >= 1000 + run_loop::active_timer_count());
+///
+/// let fired = Rc::new(RefCell::new(Vec::new()));
+/// let timers: Vec<_> = (0..1000).map(|i| {
+///     let fired = fired.clone();
+///     run_loop::new_timer()
+///         .with_callback_once(move || fired.borrow_mut().push(i))
+///         .and_start(Duration::from_millis(10 + (i * 7919 % 1000) as u64))
+/// }).collect();
+/// assert_eq!(run_loop::timer_capacity(), capacity);
+///
+/// // 只保留三个，空闲时收缩，剩余的定时器仍然按到期时间触发
+/// for (i, t) in timers.iter().enumerate() {
+///     if i % 400 != 0 {
+///         t.cancel();
+///     }
+/// }
+/// let _stop = run_loop::new_timer().with_callback_once(run_loop::stop).and_start(Duration::from_millis(1100));
+/// run_loop::run();
+/// assert!(run_loop::timer_capacity() < capacity);
+/// assert_eq!(*fired.borrow(), vec![0, 800, 400]);
+/// ```
+pub fn reserve_timers(additional: usize) {
+    RUN_LOOP.with(|rl| rl.timers.borrow_mut().reserve(additional))
+}
+
+/// 当前线程的循环不再分配内存能容纳的定时器数，使用时间轮时等于定时器数
+pub fn timer_capacity() -> usize {
+    RUN_LOOP.with(|rl| rl.timers.borrow().capacity())
+}
+
+/// 立即收缩当前线程定时器容器多余的空间，不必等到循环空闲
+///
+/// 定时器远少于容量时才收缩，定时器全部取消或者触发后也会自动收缩。使用时间轮时不做处理。
+///
+/// ```
+/// use vnbase::run_loop;
+/// use std::time::Duration;
+///
+/// let timers: Vec<_> = (0..1000).map(|i| {
+///     run_loop::new_timer().with_callback(|| {}).and_start(Duration::from_secs(10 + i))
+/// }).collect();
+/// let capacity = run_loop::timer_capacity();
+///
+/// // 留下一部分，收缩后容量仍然足够
+/// for t in &timers[10..] {
+///     t.cancel();
+/// }
+/// run_loop::compact_timers();
+/// assert!(run_loop::timer_capacity() < capacity);
+/// assert!(run_loop::timer_capacity() >= run_loop::active_timer_count());
+///
+/// // 全部取消时自动收缩
+/// run_loop::reserve_timers(1000);
+/// let capacity = run_loop::timer_capacity();
+/// for t in &timers[..10] {
+///     t.cancel();
+/// }
+/// assert_eq!(run_loop::active_timer_count(), 0);
+/// assert!(run_loop::timer_capacity() < capacity);
+/// ```
+pub fn compact_timers() {
+    RUN_LOOP.with(|rl| rl.timers.borrow_mut().compact())
+}
+
+/// 当前线程循环内对象的数量
+pub fn object_count() -> usize {
+    RUN_LOOP.with(|rl| rl.objects.borrow().len())
+}
+
+/// 当前线程循环内对象按类型统计的数量，按类型名排序，需要开启 `debug-introspection` 特性
+///
+/// ```
+/// use vnbase::run_loop;
+///
+/// let a = run_loop::new_object(1u32);
+/// let b = run_loop::new_object(2u32);
+/// let c = run_loop::new_object(String::new());
+/// drop(b);
+///
+/// let counts = run_loop::object_type_counts();
+/// assert!(counts.contains(&("u32", 1)));
+/// assert!(counts.contains(&("alloc::string::String", 1)));
+/// drop((a, c));
+/// assert!(run_loop::object_type_counts().iter().all(|&(name, _)| name != "u32"));
+/// ```
+#[cfg(feature = "debug-introspection")]
+pub fn object_type_counts() -> Vec<(&'static str, usize)> {
+    RUN_LOOP.with(|rl| rl.objects.borrow().type_counts())
+}
+
+/// 当前线程循环的统计快照
+///
+/// ```
+/// use vnbase::run_loop;
+/// use std::time::Duration;
+///
+/// let before = run_loop::metrics();
+/// assert_eq!(before.state, run_loop::LoopState::Stopped);
+///
+/// let _timer = run_loop::new_timer().with_callback(|| {}).and_start(Duration::from_secs(60));
+/// let _schedule = run_loop::new_schedule().with_callback(|_| {}).and_start();
+/// let _obj = run_loop::new_object(());
+/// let handle = run_loop::clone_handle();
+/// handle.post(|| {});
+/// handle.post(|| {
+///     assert_eq!(run_loop::metrics().state, run_loop::LoopState::Running);
+///     run_loop::stop();
+/// });
+///
+/// let m = run_loop::metrics();
+/// assert_eq!(m.pending_msgs, before.pending_msgs + 2);
+/// assert_eq!(m.active_timers, before.active_timers + 1);
+/// assert_eq!(m.active_schedules, before.active_schedules + 1);
+/// assert_eq!(m.objects, before.objects + 1);
+///
+/// run_loop::run();
+/// let m = run_loop::metrics();
+/// assert_eq!(m.pending_msgs, 0);
+/// assert_eq!(m.processed_msgs, before.processed_msgs + 2);
+/// ```
+pub fn metrics() -> LoopMetrics {
+    RUN_LOOP.with(|rl| {
+        let (pending_msgs, state) = {
+            let ctrl = rl.core.lock();
+            (rl.core.queue.len(), LoopState::from(&ctrl.state))
+        };
+        let timers = rl.timers.borrow();
+        let active_schedules = timers.count_periodic();
+        LoopMetrics {
+            pending_msgs: pending_msgs,
+            active_timers: timers.len() - active_schedules,
+            active_schedules: active_schedules,
+            objects: rl.objects.borrow().len(),
+            state: state,
+            processed_msgs: rl.processed.get(),
+            name: core::lock(&rl.core.name).clone(),
+        }
+    })
+}
+
+/// 当前线程循环的消息队列统计
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop;
+///
+/// let handle = run_loop::clone_handle();
+/// for _ in 0..100 {
+///     handle.post(|| {});
+/// }
+/// handle.post(run_loop::stop);
+/// let stats = run_loop::queue_stats();
+/// assert_eq!(stats.len, 101);
+/// assert_eq!(stats.high_watermark, 101);
+///
+/// run_loop::run();
+/// let stats = run_loop::queue_stats();
+/// assert_eq!(stats.len, 0);
+/// assert_eq!(stats.high_watermark, 101);
+/// assert_eq!(stats.total_pushed, 101);
+/// assert_eq!(stats.total_processed, 101);
+///
+/// run_loop::reset_queue_high_watermark();
+/// assert_eq!(run_loop::queue_stats().high_watermark, 0);
+/// ```
+pub fn queue_stats() -> QueueStats {
+    RUN_LOOP.with(|rl| {
+        let queue = &rl.core.queue;
+        let total_processed = queue.popped();
+        let total_pushed = queue.pushed();
+        QueueStats {
+            len: total_pushed - total_processed,
+            high_watermark: queue.high_watermark(),
+            total_pushed: total_pushed,
+            total_processed: total_processed,
+        }
+    })
+}
+
+/// 当前线程循环的消息队列的分片数，见 `RunLoopBuilder::producer_shards`
+pub fn producer_shards() -> usize {
+    RUN_LOOP.with(|rl| rl.core.queue.shard_count())
+}
+
+/// 以当前队列中的消息数重新开始记录队列的最高水位
+pub fn reset_queue_high_watermark() {
+    RUN_LOOP.with(|rl| rl.core.queue.reset_high_watermark())
+}
+
+/// 设置当前线程循环的等待方式，见 `WaitStrategy`
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop;
+/// use std::thread;
+/// use std::time::Duration;
+///
+/// run_loop::set_wait_strategy(run_loop::WaitStrategy::Park);
+/// assert_eq!(run_loop::get_wait_strategy(), run_loop::WaitStrategy::Park);
+///
+/// let handle = run_loop::clone_handle();
+/// let th = thread::spawn(move || {
+///     thread::sleep(Duration::from_millis(20));
+///     handle.post(run_loop::stop);
+/// });
+/// let _timer = run_loop::new_timer()
+///     .with_callback(|| {})
+///     .and_start(Duration::from_millis(5));
+/// run_loop::run();
+/// th.join().unwrap();
+///
+/// run_loop::set_wait_strategy(run_loop::WaitStrategy::Condvar);
+/// ```
+///
+/// 拥有窗口的线程使用 `MessagePump`，窗口消息和投递的函数在同一个线程交替处理：
+/// ```
+/// #[cfg(all(windows, feature = "windows-pump"))]
+/// fn main() {
+///     use vnbase::run_loop::{self, WaitStrategy};
+///     use std::cell::RefCell;
+///     use std::os::raw::c_void;
+///     use std::ptr;
+///     use std::thread;
+///     use std::time::Duration;
+///
+///     type Hwnd = *mut c_void;
+///
+///     #[repr(C)]
+///     struct WndClassW {
+///         style: u32,
+///         wnd_proc: extern "system" fn(Hwnd, u32, usize, isize) -> isize,
+///         cls_extra: i32,
+///         wnd_extra: i32,
+///         instance: *mut c_void,
+///         icon: *mut c_void,
+///         cursor: *mut c_void,
+///         background: *mut c_void,
+///         menu_name: *const u16,
+///         class_name: *const u16,
+///     }
+///
+///     #[link(name = "user32")]
+///     extern "system" {
+///         fn RegisterClassW(class: *const WndClassW) -> u16;
+///         fn CreateWindowExW(ex_style: u32, class_name: *const u16, window_name: *const u16, style: u32,
+///                            x: i32, y: i32, width: i32, height: i32,
+///                            parent: Hwnd, menu: *mut c_void, instance: *mut c_void, param: *mut c_void) -> Hwnd;
+///         fn DestroyWindow(hwnd: Hwnd) -> i32;
+///         fn DefWindowProcW(hwnd: Hwnd, msg: u32, wparam: usize, lparam: isize) -> isize;
+///         fn PostMessageW(hwnd: Hwnd, msg: u32, wparam: usize, lparam: isize) -> i32;
+///     }
+///
+///     const HWND_MESSAGE: isize = -3;
+///     const WM_APP: u32 = 0x8000;
+///
+///     thread_local! {
+///         static LOG: RefCell<Vec<String>> = RefCell::new(Vec::new());
+///     }
+///
+///     extern "system" fn wnd_proc(hwnd: Hwnd, msg: u32, wparam: usize, lparam: isize) -> isize {
+///         if msg == WM_APP {
+///             LOG.with(|log| log.borrow_mut().push(format!("win32 {}", wparam)));
+///             return 0;
+///         }
+///         unsafe { DefWindowProcW(hwnd, msg, wparam, lparam) }
+///     }
+///
+///     run_loop::set_wait_strategy(WaitStrategy::MessagePump);
+///     assert_eq!(run_loop::get_wait_strategy(), WaitStrategy::MessagePump);
+///
+///     // 只接收消息的窗口
+///     let class_name: Vec<u16> = "vnbase-pump-example\0".encode_utf16().collect();
+///     let class = WndClassW {
+///         style: 0,
+///         wnd_proc: wnd_proc,
+///         cls_extra: 0,
+///         wnd_extra: 0,
+///         instance: ptr::null_mut(),
+///         icon: ptr::null_mut(),
+///         cursor: ptr::null_mut(),
+///         background: ptr::null_mut(),
+///         menu_name: ptr::null(),
+///         class_name: class_name.as_ptr(),
+///     };
+///     let hwnd = unsafe {
+///         assert!(RegisterClassW(&class) != 0);
+///         CreateWindowExW(0, class_name.as_ptr(), ptr::null(), 0, 0, 0, 0, 0,
+///                         HWND_MESSAGE as Hwnd, ptr::null_mut(), ptr::null_mut(), ptr::null_mut())
+///     };
+///     assert!(!hwnd.is_null());
+///
+///     // 其它线程交替发送窗口消息和投递函数
+///     let handle = run_loop::clone_handle();
+///     let target = hwnd as usize;
+///     let th = thread::spawn(move || {
+///         for i in 0..3 {
+///             unsafe { PostMessageW(target as Hwnd, WM_APP, i, 0); }
+///             thread::sleep(Duration::from_millis(20));
+///             handle.post(move || LOG.with(|log| log.borrow_mut().push(format!("post {}", i))));
+///             thread::sleep(Duration::from_millis(20));
+///         }
+///         handle.post(run_loop::stop);
+///     });
+///     run_loop::run();
+///     th.join().unwrap();
+///
+///     LOG.with(|log| {
+///         assert_eq!(*log.borrow(), vec!["win32 0", "post 0", "win32 1", "post 1", "win32 2", "post 2"]);
+///     });
+///     unsafe { DestroyWindow(hwnd); }
+///     run_loop::set_wait_strategy(WaitStrategy::Condvar);
+/// }
+/// #[cfg(not(all(windows, feature = "windows-pump")))]
+/// fn main() {}
+/// ```
+pub fn set_wait_strategy(strategy: WaitStrategy) {
+    RUN_LOOP.with(|rl| {
+        let mut ctrl = rl.core.lock();
+        ctrl.park = match strategy {
+            WaitStrategy::Park => Some(thread::current()),
+            _ => None,
+        };
+        #[cfg(all(windows, feature = "windows-pump"))]
+        {
+            ctrl.pump = match strategy {
+                WaitStrategy::MessagePump => Some(Arc::new(win_pump::PumpEvent::new().expect("failed to create run loop event"))),
+                _ => None,
+            };
+        }
+    })
+}
+
+pub fn get_wait_strategy() -> WaitStrategy {
+    RUN_LOOP.with(|rl| {
+        let ctrl = rl.core.lock();
+        #[cfg(all(windows, feature = "windows-pump"))]
+        {
+            if ctrl.pump.is_some() {
+                return WaitStrategy::MessagePump;
+            }
+        }
+        match ctrl.park {
+            Some(_) => WaitStrategy::Park,
+            None => WaitStrategy::Condvar,
+        }
+    })
+}
+
+/// 设置当前线程定时器的合并窗口，为零时关闭
+///
+/// 开启后定时器和周期历程的到期时间会向后对齐到窗口的整数倍，
+/// 相近的定时器一起触发以减少唤醒次数。立即到期的定时器不受影响。
+///
+/// ```
+/// use vnbase::run_loop;
+/// use std::time::{Duration, Instant};
+///
+/// run_loop::set_timer_coalescing(Duration::from_secs(3600));
+///
+/// let timer = run_loop::new_timer().with_callback(|| {});
+/// timer.start(Duration::from_millis(1));
+/// let first = run_loop::next_timer_deadline().unwrap();
+/// timer.start(Duration::from_millis(20));
+/// assert_eq!(run_loop::next_timer_deadline().unwrap(), first);
+///
+/// timer.start(Duration::from_secs(0));
+/// assert!(run_loop::next_timer_deadline().unwrap() <= Instant::now());
+///
+/// run_loop::set_timer_coalescing(Duration::from_secs(0));
+/// ```
+pub fn set_timer_coalescing(window: Duration) {
+    RUN_LOOP.with(|rl| {
+        if window == Duration::from_secs(0) {
+            rl.coalescing.set(None);
+        }
+        else {
+            rl.coalescing.set(Some(window));
+        }
+    })
+}
+
+/// 当前线程定时器的合并窗口
+pub fn get_timer_coalescing() -> Option<Duration> {
+    RUN_LOOP.with(|rl| rl.coalescing.get())
+}
+
+/// 设置当前线程循环管理定时器的方式，见 `TimerStrategy`
+///
+/// 可以随时切换，已经启动的定时器和周期历程按原来的到期时间移入。
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop::{self, TimerStrategy};
+/// use std::rc::Rc;
+/// use std::cell::RefCell;
+/// use std::time::{Duration, Instant};
+///
+/// // 精度很小，定时器会经过多层
+/// run_loop::set_timer_strategy(TimerStrategy::Wheel(Duration::from_micros(10)));
+///
+/// let start = Instant::now();
+/// let late = Rc::new(RefCell::new(Vec::new()));
+/// let mut timers = Vec::new();
+/// for i in 0..2000u64 {
+///     let target = Duration::from_micros(i * 7919 % 60000);
+///     let l = late.clone();
+///     let timer = run_loop::new_timer()
+///         .with_callback_once(move || l.borrow_mut().push((i, start.elapsed().checked_sub(target))))
+///         .and_start(target);
+///     timers.push(timer);
+/// }
+/// // 取消和重新启动一部分
+/// for (i, t) in timers.iter().enumerate() {
+///     match i % 4 {
+///         0 => t.cancel(),
+///         1 => t.start(Duration::from_millis(30)),
+///         _ => {},
+///     }
+/// }
+/// // 切换时已经启动的定时器一同移入
+/// run_loop::set_timer_strategy(TimerStrategy::Heap);
+/// run_loop::set_timer_strategy(TimerStrategy::Wheel(Duration::from_micros(10)));
+/// assert_eq!(run_loop::active_timer_count(), 1500);
+///
+/// let _stop = run_loop::new_timer().with_callback_once(run_loop::stop).and_start(Duration::from_millis(200));
+/// run_loop::run();
+///
+/// let late = late.borrow();
+/// assert_eq!(late.len(), 1500);
+/// assert!(late.iter().all(|&(i, _)| i % 4 != 0));
+/// // 重新启动的定时器按新的时间计算，其余的不会提前
+/// assert!(late.iter().all(|&(i, l)| i % 4 == 1 || l.is_some()));
+/// assert_eq!(run_loop::active_timer_count(), 0);
+/// run_loop::set_timer_strategy(TimerStrategy::Heap);
+/// ```
+pub fn set_timer_strategy(strategy: TimerStrategy) {
+    RUN_LOOP.with(|rl| rl.timers.borrow_mut().set_strategy(strategy))
+}
+
+pub fn get_timer_strategy() -> TimerStrategy {
+    RUN_LOOP.with(|rl| rl.timers.borrow().strategy())
+}
+
+/// 设置当前线程的循环每轮最多处理的消息数，为零时不限制，默认为 1024
+///
+/// 处理了这么多消息后，先处理到期的定时器、检查退出请求，再继续处理剩余的消息，
+/// 大量消息排队时定时器不会被推迟到全部处理完。消息的顺序不受影响。
+///
+/// ```
+/// use vnbase::run_loop;
+/// use std::rc::Rc;
+/// use std::cell::Cell;
+/// use std::time::{Duration, Instant};
+///
+/// assert_eq!(run_loop::get_message_batch_limit(), Some(1024));
+///
+/// let handle = run_loop::clone_handle();
+/// for _ in 0..100000 {
+///     handle.post(|| {
+///         let t = Instant::now();
+///         while t.elapsed() < Duration::from_micros(2) {}
+///     });
+/// }
+///
+/// let start = Instant::now();
+/// let fired = Rc::new(Cell::new(None));
+/// let (f, m) = (fired.clone(), run_loop::metrics().processed_msgs);
+/// let _timer = run_loop::new_timer()
+///     .with_callback_once(move || f.set(Some((start.elapsed(), run_loop::metrics().processed_msgs - m))))
+///     .and_start(Duration::from_millis(10));
+/// handle.post(run_loop::stop);
+/// run_loop::run();
+///
+/// let (at, msgs) = fired.get().unwrap();
+/// assert!(at >= Duration::from_millis(10) && at < Duration::from_millis(150), "{:?}", at);
+/// assert!(msgs < 100000);
+/// ```
+pub fn set_message_batch_limit(limit: usize) {
+    RUN_LOOP.with(|rl| {
+        if limit == 0 {
+            rl.msg_batch.set(None);
+        }
+        else {
+            rl.msg_batch.set(Some(limit));
+        }
+    })
+}
+
+/// 当前线程的循环每轮最多处理的消息数
+pub fn get_message_batch_limit() -> Option<usize> {
+    RUN_LOOP.with(|rl| rl.msg_batch.get())
+}
+
+/// 设置当前线程的循环每轮最多处理的消息数和到期定时器数，为零时不限制
+///
+/// 循环轮流处理消息和到期的定时器，各自达到上限后转向另一方，剩余的留到下一轮，
+/// 持续的消息和大量同时到期的定时器都不会让另一方等待太久。
+/// 消息的上限和 `set_message_batch_limit` 相同，默认为 1024；定时器默认不限制。
+///
+/// ```
+/// use vnbase::run_loop;
+/// use std::sync::{Arc, Mutex};
+/// use std::time::Duration;
+///
+/// run_loop::set_fairness(1, 1);
+/// assert_eq!(run_loop::get_fairness(), (Some(1), Some(1)));
+///
+/// let log = Arc::new(Mutex::new(Vec::new()));
+/// let handle = run_loop::clone_handle();
+/// let timers: Vec<_> = (0..3).map(|i| {
+///     let l = log.clone();
+///     handle.post(move || l.lock().unwrap().push(format!("msg {}", i)));
+///     let l = log.clone();
+///     run_loop::new_timer()
+///         .with_callback_once(move || l.lock().unwrap().push(format!("timer {}", i)))
+///         .and_start(Duration::from_secs(0))
+/// }).collect();
+/// let _stop = run_loop::new_timer().with_callback_once(run_loop::stop).and_start(Duration::from_millis(20));
+/// run_loop::run();
+///
+/// assert_eq!(*log.lock().unwrap(), vec!["msg 0", "timer 0", "msg 1", "timer 1", "msg 2", "timer 2"]);
+/// run_loop::set_fairness(1024, 0);
+/// # drop(timers);
+/// ```
+pub fn set_fairness(msgs_per_round: usize, timers_per_round: usize) {
+    set_message_batch_limit(msgs_per_round);
+    RUN_LOOP.with(|rl| {
+        if timers_per_round == 0 {
+            rl.timer_batch.set(None);
+        }
+        else {
+            rl.timer_batch.set(Some(timers_per_round));
+        }
+    })
+}
+
+/// 当前线程的循环每轮最多处理的消息数和到期定时器数
+pub fn get_fairness() -> (Option<usize>, Option<usize>) {
+    RUN_LOOP.with(|rl| (rl.msg_batch.get(), rl.timer_batch.get()))
+}
+
+/// 设置当前线程循环的跟踪回调，在处理每条消息和每个到期的定时器前后调用，替换之前的回调
+///
+/// 结束事件带有处理的耗时。没有设置时不计时，不影响循环的性能。
+/// 跟踪回调中不能再设置或移除跟踪回调。
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop::{self, TraceEvent};
+/// use std::rc::Rc;
+/// use std::cell::RefCell;
+/// use std::time::Duration;
+///
+/// let events = Rc::new(RefCell::new(Vec::new()));
+/// let e = events.clone();
+/// run_loop::set_trace_hook(move |ev| e.borrow_mut().push(ev));
+///
+/// run_loop::clone_handle().post(|| {});
+/// let _timer = run_loop::new_timer()
+///     .with_callback_once(run_loop::stop)
+///     .and_start(Duration::from_millis(1));
+/// run_loop::run();
+/// run_loop::clear_trace_hook();
+///
+/// let kinds: Vec<_> = events.borrow().iter().map(|ev| match *ev {
+///     TraceEvent::MsgStart => "msg",
+///     TraceEvent::MsgEnd { .. } => "/msg",
+///     TraceEvent::TimerStart => "timer",
+///     TraceEvent::TimerEnd { .. } => "/timer",
+/// }).collect();
+/// assert_eq!(kinds, vec!["msg", "/msg", "timer", "/timer"]);
+/// ```
+pub fn set_trace_hook<F>(f: F) where F: FnMut(TraceEvent) + 'static {
+    RUN_LOOP.with(|rl| *rl.trace.borrow_mut() = Some(Box::new(f)))
+}
+
+/// 移除当前线程循环的跟踪回调
+pub fn clear_trace_hook() {
+    RUN_LOOP.with(|rl| {
+        let hook = rl.trace.borrow_mut().take();
+        drop(hook);
+    })
+}
+
+/// 设置当前线程循环的看门狗，每隔 period 检查一次，发现循环停滞时以停滞的情况调用 f
+///
+/// 相邻两次检查时都有消息等待，而期间没有处理任何消息，视为停滞；之后每次检查仍然停滞时再次调用 f，
+/// `stalled_for` 随之增长，直到消息重新得到处理。用于排查唤醒丢失之类导致消息得不到处理的问题。
+/// 检查由循环上的周期历程执行，回调阻塞循环时检查同样无法进行。再次设置时替换之前的看门狗。
+///
+/// ```
+/// use vnbase::run_loop;
+/// use std::cell::Cell;
+/// use std::rc::Rc;
+/// use std::time::Duration;
+///
+/// let before = run_loop::metrics().active_schedules;
+/// let reports = Rc::new(Cell::new(0));
+/// let r = reports.clone();
+/// run_loop::set_watchdog(Duration::from_millis(5), move |info| {
+///     println!("loop stalled: {:?}", info);
+///     r.set(r.get() + 1);
+/// });
+/// assert_eq!(run_loop::metrics().active_schedules, before + 1);
+///
+/// // 消息持续得到处理，空闲时队列为空，都不算停滞
+/// let busy = run_loop::repeat_while(Duration::from_millis(1), || {
+///     for _ in 0..10 {
+///         run_loop::yield_now(|| {});
+///     }
+///     true
+/// });
+/// let _stop = run_loop::new_timer().with_callback_once(run_loop::stop).and_start(Duration::from_millis(50));
+/// run_loop::run();
+/// busy.cancel();
+/// let _stop = run_loop::new_timer().with_callback_once(run_loop::stop).and_start(Duration::from_millis(30));
+/// run_loop::run();
+/// assert_eq!(reports.get(), 0);
+///
+/// run_loop::clear_watchdog();
+/// assert_eq!(run_loop::metrics().active_schedules, before);
+/// ```
+pub fn set_watchdog<F>(period: Duration, f: F) where F: Fn(WatchdogInfo) + 'static {
+    // 上一次检查时等待的消息数和已经处理的消息总数
+    let mut last = (0, RUN_LOOP.with(|rl| rl.processed.get()));
+    let mut stalled = Duration::from_secs(0);
+    let schedule = new_schedule()
+        .with_period(period)
+        .with_callback(move |dt| {
+            let (pending, processed) = RUN_LOOP.with(|rl| (rl.core.queue.len(), rl.processed.get()));
+            if pending > 0 && last.0 > 0 && processed == last.1 {
+                stalled += dt;
+                f(WatchdogInfo {
+                    pending_msgs: pending,
+                    processed_msgs: processed,
+                    stalled_for: stalled,
+                });
+            }
+            else {
+                stalled = Duration::from_secs(0);
+            }
+            last = (pending, processed);
+        })
+        .and_start();
+    let old = RUN_LOOP.with(|rl| rl.watchdog.borrow_mut().replace(schedule));
+    if let Some(old) = old {
+        old.cancel();
+    }
+}
+
+/// 移除当前线程循环的看门狗
+pub fn clear_watchdog() {
+    let old = RUN_LOOP.with(|rl| rl.watchdog.borrow_mut().take());
+    if let Some(old) = old {
+        old.cancel();
+    }
+}
+
+/// 阻塞当前线程，直到其中一个弱引用指向的对象被释放，返回它在 weaks 中的序号
+///
+/// 调用时已经释放的对象立即返回，weaks 不能为空。不能在对象所在的线程调用，
+/// 否则循环无法运行，对象永远不会被释放。
+///
+/// ```
+/// use vnbase::run_loop;
+/// use std::thread;
+///
+/// let a = run_loop::new_object(1);
+/// let b = run_loop::new_object(2);
+/// let weaks = vec![a.downgrade(), b.downgrade()];
+///
+/// let th = thread::spawn(move || run_loop::wait_any_dropped(&weaks));
+/// drop(b);
+/// assert_eq!(th.join().unwrap(), 1);
+///
+/// assert_eq!(run_loop::wait_any_dropped(&[a.downgrade(), run_loop::ObjectWeak::new()]), 1);
+/// ```
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+pub fn wait_any_dropped<T: ?Sized>(weaks: &[ObjectWeak<T>]) -> usize {
+    object::wait_any_dropped(weaks)
+}
+
+/// 每隔 period 执行一次 f，直到 f 返回 false
+///
+/// 返回的周期历程可以保存下来提前取消，直接丢弃不会停止执行。
+///
+/// ```
+/// use vnbase::run_loop;
+/// use std::rc::Rc;
+/// use std::cell::Cell;
+/// use std::time::Duration;
+///
+/// let count = Rc::new(Cell::new(0));
+/// let c = count.clone();
+/// run_loop::repeat_while(Duration::from_millis(5), move || {
+///     c.set(c.get() + 1);
+///     c.get() < 3
+/// });
+///
+/// let _timer = run_loop::new_timer()
+///     .with_callback_once(run_loop::stop)
+///     .and_start(Duration::from_millis(100));
+/// run_loop::run();
+///
+/// assert_eq!(count.get(), 3);
+/// assert_eq!(run_loop::metrics().active_schedules, 0);
+/// ```
+pub fn repeat_while<F>(period: Duration, mut f: F) -> Schedule where F: FnMut() -> bool + 'static {
+    Schedule::new()
+        .with_period(period)
+        .with_callback_while(move |_| f())
+        .and_start()
+}
+
+/// 在当前线程创建每隔 period 触发一次的异步流
+///
+/// 每次触发产生触发的时刻，在异步代码中 `while let Some(t) = stream.next().await` 即可周期执行。
+/// 释放流时停止。
+///
+/// ```
+/// use vnbase::run_loop;
+/// use std::time::{Duration, Instant};
+///
+/// let start = Instant::now();
+/// let mut stream = run_loop::interval_stream(Duration::from_millis(10));
+/// let ticks: Vec<Instant> = (0..3).map(|_| run_loop::block_on(stream.next()).unwrap()).collect();
+/// assert!(ticks.windows(2).all(|w| w[0] < w[1]));
+/// assert!(ticks[0] - start >= Duration::from_millis(10));
+///
+/// assert_eq!(run_loop::metrics().active_schedules, 1);
+/// drop(stream);
+/// assert_eq!(run_loop::metrics().active_schedules, 0);
+/// ```
+pub fn interval_stream(period: Duration) -> IntervalStream {
+    IntervalStream::new(period)
+}
+
+/// 在当前线程创建定时器
+pub fn new_timer() -> Timer {
+    Timer::new()
+}
+
+/// 在当前线程创建周期历程
+pub fn new_schedule() -> Schedule {
+    Schedule::new()
+}
+
+/// 在当前线程创建循环内对象
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop;
+/// use std::thread;
+/// 
+/// trait MyObj {
+///     fn say_hello(&self);
+/// }
+/// 
+/// struct MOA (i32);
+/// impl MyObj for MOA {
+///     fn say_hello(&self) {
+///         println!("MOA: {}", self.0);
+///     }
+/// }
+/// struct MOB (String);
+/// impl MyObj for MOB {
+///     fn say_hello(&self) {
+///         println!("MOB: {}", self.0);
+///     }
+/// }
+/// 
+/// let mut objs: Vec<run_loop::ObjectHandle<Box<MyObj>>> = Vec::new();
+/// objs.push(run_loop::new_object(Box::new(MOA(100))));
+/// objs.push(run_loop::new_object(Box::new(MOB("Hello".to_string()))));
+/// 
+/// let handle = run_loop::clone_handle();
+/// let th = thread::spawn(move || {
+///     for obj in objs.iter() {
+///         obj.post(|obj| obj.say_hello());
+///     }
+///     handle.post(run_loop::stop);
+/// });
+/// run_loop::run();
+/// th.join().unwrap();
+/// ```
+///
+/// 每个对象都只会被释放一次，循环退出时剩余的对象随之释放：
+/// ```
+/// use vnbase::run_loop;
+/// use std::sync::Arc;
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::thread;
+/// use std::mem;
+///
+/// struct Counted (Arc<AtomicUsize>);
+/// impl Drop for Counted {
+///     fn drop(&mut self) {
+///         self.0.fetch_add(1, Ordering::SeqCst);
+///     }
+/// }
+///
+/// let drops = Arc::new(AtomicUsize::new(0));
+/// let new = || run_loop::new_object(Counted(drops.clone()));
+///
+/// drop(new());
+/// assert_eq!(drops.load(Ordering::SeqCst), 1);
+///
+/// let (a, b) = (new(), new());
+/// drop(a);
+/// drop(b);
+/// assert_eq!(drops.load(Ordering::SeqCst), 3);
+///
+/// let (a, b, c) = (new(), new(), new());
+/// drop(b);
+/// drop(c);
+/// drop(a);
+/// assert_eq!(drops.load(Ordering::SeqCst), 6);
+///
+/// let d = drops.clone();
+/// thread::spawn(move || {
+///     for _ in 0..3 {
+///         mem::forget(run_loop::new_object(Counted(d.clone())));
+///     }
+///     drop(run_loop::new_object(Counted(d.clone())));
+/// }).join().unwrap();
+/// assert_eq!(drops.load(Ordering::SeqCst), 10);
+/// ```
+pub fn new_object<T>(obj: T) -> ObjectHandle<T> where T: 'static {
+    RUN_LOOP.with(move |rl| {
+        rl.objects.borrow_mut().create(obj)
+    })
+}
+
+/// 在当前线程创建循环内对象，对象在构造时即可保存自己的弱引用，和 `Arc::new_cyclic` 类似
+///
+/// 构造期间弱引用无法 `upgrade`。f 发生 panic 时不会泄漏，之前取得的弱引用都视为对象已经释放。
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop;
+/// use std::cell::Cell;
+/// use std::panic;
+///
+/// struct Counter {
+///     n: Cell<u32>,
+///     me: run_loop::ObjectWeak<Counter>,
+/// }
+///
+/// impl Counter {
+///     fn step(&self) {
+///         self.n.set(self.n.get() + 1);
+///         if self.n.get() < 5 {
+///             self.me.post(Counter::step);
+///         }
+///         else {
+///             run_loop::stop();
+///         }
+///     }
+/// }
+///
+/// let obj = run_loop::new_object_cyclic(|me| {
+///     assert!(me.upgrade().is_none());
+///     Counter { n: Cell::new(0), me: me.clone() }
+/// });
+/// obj.post(Counter::step);
+/// run_loop::run();
+/// assert_eq!(obj.get_ref().unwrap().n.get(), 5);
+///
+/// let before = run_loop::object_count();
+/// let mut escaped = None;
+/// let r = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+///     run_loop::new_object_cyclic::<u32, _>(|me| {
+///         escaped = Some(me.clone());
+///         panic!("failed");
+///     })
+/// }));
+/// assert!(r.is_err());
+/// let escaped = escaped.unwrap();
+/// assert!(escaped.upgrade().is_none());
+/// assert!(!escaped.post(|_| unreachable!()));
+/// assert_eq!(run_loop::object_count(), before);
+/// ```
+pub fn new_object_cyclic<T, F>(f: F) -> ObjectHandle<T> where T: 'static, F: FnOnce(&ObjectWeak<T>) -> T {
+    RUN_LOOP.with(move |rl| object::create_cyclic(&rl.objects, f))
+}
+
+/// 在当前线程创建循环内对象，只在 f 中使用，f 返回后立即释放
+///
+/// 创建和释放都在当前线程，释放是同步的。f 中复制出的句柄仍然持有对象，全部释放后对象才释放。
+///
+/// ```
+/// use vnbase::run_loop;
+/// use std::cell::Cell;
+/// use std::rc::Rc;
+///
+/// struct Scoped (Rc<Cell<bool>>);
+/// impl Drop for Scoped {
+///     fn drop(&mut self) {
+///         self.0.set(true);
+///     }
+/// }
+///
+/// let dropped = Rc::new(Cell::new(false));
+/// let weak = run_loop::with_object(Scoped(dropped.clone()), |obj| {
+///     assert!(!obj.get_ref().unwrap().0.get());
+///     obj.downgrade()
+/// });
+/// assert!(dropped.get());
+/// assert!(weak.upgrade().is_none());
+/// ```
+pub fn with_object<T, R, F>(value: T, f: F) -> R where T: 'static, F: FnOnce(&ObjectHandle<T>) -> R {
+    let obj = new_object(value);
+    f(&obj)
+}
+
+/// 在 target 所在的循环执行 f，把结果交给投递到 reply_to 的 on_reply
+///
+/// target 在 f 执行前保持存在，回复时不再持有 target。任意一个循环在中途结束时，
+/// on_reply 不会执行，随丢弃的消息一起释放。
+///
+/// ```
+/// use vnbase::run_loop;
+/// use std::sync::Arc;
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::thread;
+///
+/// struct Counted (Arc<AtomicUsize>);
+/// impl Drop for Counted {
+///     fn drop(&mut self) {
+///         self.0.fetch_add(1, Ordering::SeqCst);
+///     }
+/// }
+///
+/// let obj = run_loop::new_object(String::from("hello"));
+/// let handle = run_loop::clone_handle();
+/// let th = thread::spawn(move || {
+///     run_loop::request(&obj, |s| s.len(), &run_loop::clone_handle(), move |len| {
+///         assert_eq!(len, 5);
+///         handle.post(run_loop::stop);
+///         run_loop::stop();
+///     });
+///     run_loop::run();
+/// });
+/// run_loop::run();
+/// th.join().unwrap();
+///
+/// // 目标所在的循环已经结束
+/// let obj = thread::spawn(|| run_loop::new_object(String::from("hello"))).join().unwrap();
+/// let drops = Arc::new(AtomicUsize::new(0));
+/// let c = Counted(drops.clone());
+/// run_loop::request(&obj, |s| s.len(), &run_loop::clone_handle(), move |_| {
+///     let _ = &c;
+///     unreachable!();
+/// });
+/// assert_eq!(drops.load(Ordering::SeqCst), 1);
+///
+/// // 回复的循环已经结束
+/// let reply_to = thread::spawn(run_loop::clone_handle).join().unwrap();
+/// let target = run_loop::new_object(1);
+/// let c = Counted(drops.clone());
+/// run_loop::request(&target, |n| *n, &reply_to, move |_| {
+///     let _ = &c;
+///     unreachable!();
+/// });
+/// run_loop::clone_handle().post(run_loop::stop);
+/// run_loop::run();
+/// assert_eq!(drops.load(Ordering::SeqCst), 2);
+/// ```
+pub fn request<T, Resp, F, R>(target: &ObjectHandle<T>, f: F, reply_to: &Handle, on_reply: R)
+    where T: ?Sized + 'static, Resp: Send + 'static, F: FnOnce(&T) -> Resp + Send + 'static, R: FnOnce(Resp) + Send + 'static {
+    let reply_to = reply_to.clone();
+    target.post(move |t| {
+        let resp = f(t);
+        reply_to.post(move || on_reply(resp));
+    });
+}
+
+/// 同 `request`，on_reply 在 reply_to 对象所在的循环执行并获得该对象
+///
+/// 请求发出后 reply_to 保持存在，直到 on_reply 执行或者被丢弃。
+///
+/// 两个循环之间来回传递计数：
+/// ```
+/// use vnbase::run_loop::{self, ObjectHandle};
+/// use std::cell::Cell;
+/// use std::sync::mpsc;
+/// use std::thread;
+///
+/// fn ping(a: ObjectHandle<Cell<u32>>, b: ObjectHandle<Cell<u32>>) {
+///     let (a2, b2) = (a.clone(), b.clone());
+///     run_loop::request_object(&b, |b| {
+///         b.set(b.get() + 1);
+///         b.get()
+///     }, &a, move |a, n| {
+///         a.set(n);
+///         if n < 3000 {
+///             ping(a2, b2);
+///         }
+///         else {
+///             run_loop::stop();
+///         }
+///     });
+/// }
+///
+/// let (tx, rx) = mpsc::channel();
+/// let th = thread::spawn(move || {
+///     tx.send((run_loop::clone_handle(), run_loop::new_object(Cell::new(0)))).unwrap();
+///     run_loop::run();
+/// });
+/// let (handle, b) = rx.recv().unwrap();
+/// let a = run_loop::new_object(Cell::new(0));
+/// ping(a.clone(), b);
+/// run_loop::run();
+/// assert_eq!(a.get_ref().unwrap().get(), 3000);
+///
+/// handle.stop();
+/// th.join().unwrap();
+/// ```
+pub fn request_object<T, U, Resp, F, R>(target: &ObjectHandle<T>, f: F, reply_to: &ObjectHandle<U>, on_reply: R)
+    where T: ?Sized + 'static, U: ?Sized + 'static, Resp: Send + 'static,
+          F: FnOnce(&T) -> Resp + Send + 'static, R: FnOnce(&U, Resp) + Send + 'static {
+    let reply_to = reply_to.clone();
+    target.post(move |t| {
+        let resp = f(t);
+        reply_to.post(move |u| on_reply(u, resp));
+    });
+}
+
+/// 在当前线程创建带生命周期回调的循环内对象，见 `LoopObject`
+///
+/// 循环退出时仍然存在的对象同样会调用 `detaching`：
+/// ```
+/// use vnbase::run_loop;
+/// use std::sync::Arc;
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::thread;
+/// use std::mem;
+///
+/// struct Obj (Arc<AtomicUsize>);
+/// impl run_loop::LoopObject for Obj {
+///     fn detaching(&mut self) {
+///         self.0.fetch_add(1, Ordering::SeqCst);
+///     }
+/// }
+///
+/// let detached = Arc::new(AtomicUsize::new(0));
+/// let d = detached.clone();
+/// thread::spawn(move || {
+///     mem::forget(run_loop::new_loop_object(Obj(d.clone())));
+///     mem::forget(run_loop::new_loop_object(Obj(d.clone())));
+/// }).join().unwrap();
+/// assert_eq!(detached.load(Ordering::SeqCst), 2);
+/// ```
+pub fn new_loop_object<T>(mut obj: T) -> ObjectHandle<T> where T: LoopObject + 'static {
+    // 对象还没有句柄，只有这里可以访问它，`attached` 可以取得 &mut
+    obj.attached();
+    RUN_LOOP.with(move |rl| {
+        rl.objects.borrow_mut().create_loop_object(obj)
+    })
+}
+
+/// 配置当前线程或新线程的循环，见 `RunLoopBuilder`
+///
+/// `install` 必须在当前线程第一次使用循环之前调用，此后返回错误，已经生效的配置不受影响。
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop::{self, InstallError, PanicPolicy, StopPolicy, TimerStrategy, WaitStrategy};
+/// use std::sync::Arc;
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::time::Duration;
+///
+/// run_loop::builder()
+///     .name("main")
+///     .producer_shards(4)
+///     .timer_capacity(500)
+///     .message_batch_limit(16)
+///     .wait_strategy(WaitStrategy::Park)
+///     .timer_coalescing(Duration::from_millis(5))
+///     .panic_policy(PanicPolicy::Restart)
+///     .stop_policy(StopPolicy::Drain)
+///     .install()
+///     .unwrap();
+///
+/// assert_eq!(run_loop::name(), Some(String::from("main")));
+/// assert_eq!(run_loop::producer_shards(), 4);
+/// assert!(run_loop::timer_capacity() >= 500);
+/// assert_eq!(run_loop::get_message_batch_limit(), Some(16));
+/// assert_eq!(run_loop::get_wait_strategy(), WaitStrategy::Park);
+/// assert_eq!(run_loop::get_timer_strategy(), TimerStrategy::Heap);
+/// assert_eq!(run_loop::get_timer_coalescing(), Some(Duration::from_millis(5)));
+///
+/// // 已经创建，不能再配置
+/// let late = run_loop::builder().timer_strategy(TimerStrategy::Wheel(Duration::from_millis(1))).install();
+/// assert_eq!(late, Err(InstallError));
+/// assert_eq!(run_loop::get_timer_strategy(), TimerStrategy::Heap);
+///
+/// let handle = run_loop::clone_handle();
+/// let count = Arc::new(AtomicUsize::new(0));
+/// // 回调 panic 后继续运行
+/// handle.post(|| panic!("callback failed"));
+/// handle.post(run_loop::stop);
+/// // 退出请求之后、超过每轮上限的消息同样在返回前处理
+/// for _ in 0..20 {
+///     let c = count.clone();
+///     handle.post(move || { c.fetch_add(1, Ordering::SeqCst); });
+/// }
+/// run_loop::run();
+/// assert_eq!(count.load(Ordering::SeqCst), 20);
+/// ```
+pub fn builder() -> RunLoopBuilder {
+    RunLoopBuilder::new()
+}
+
+/// 在新线程上按 builder 的配置创建循环并运行，返回循环的句柄和线程
+///
+/// 设置了名字时同时用作线程的名字。
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop::{self, PanicPolicy, StopPolicy, TimerStrategy, WaitStrategy};
+/// use std::sync::mpsc;
+/// use std::thread;
+/// use std::time::Duration;
+///
+/// let wheel = TimerStrategy::Wheel(Duration::from_millis(1));
+/// let (handle, th) = run_loop::spawn_with(run_loop::builder().name("worker").timer_strategy(wheel).panic_policy(PanicPolicy::Restart));
+/// assert_eq!(handle.name(), Some(String::from("worker")));
+///
+/// let (tx, rx) = mpsc::channel();
+/// handle.post(|| panic!("callback failed"));
+/// let t = tx.clone();
+/// handle.post(move || t.send((thread::current().name().map(String::from), run_loop::get_timer_strategy())).unwrap());
+/// assert_eq!(rx.recv().unwrap(), (Some(String::from("worker")), wheel));
+/// handle.stop();
+/// th.join().unwrap();
+///
+/// // 没有设置的项和直接使用循环时相同
+/// let (handle, th) = run_loop::spawn_with(run_loop::builder());
+/// let (tx, rx) = mpsc::channel();
+/// handle.post(move || tx.send((
+///     run_loop::name(),
+///     run_loop::producer_shards(),
+///     run_loop::get_message_batch_limit(),
+///     run_loop::get_wait_strategy(),
+///     run_loop::get_timer_strategy(),
+///     run_loop::get_timer_coalescing(),
+/// )).unwrap());
+/// assert_eq!(rx.recv().unwrap(), (None, 1, Some(1024), WaitStrategy::Condvar, TimerStrategy::Heap, None));
+///
+/// // 默认的 StopPolicy::Immediate 收到退出请求后不再处理排队的消息
+/// let (release, blocked) = mpsc::channel::<()>();
+/// handle.post(move || blocked.recv().unwrap());
+/// let (done, finished) = mpsc::channel();
+/// handle.post(move || done.send(()).unwrap());
+/// handle.stop();
+/// // 循环可能在处理前就已经退出，线程结束时丢弃
+/// let _ = release.send(());
+/// th.join().unwrap();
+/// assert!(finished.try_recv().is_err());
+///
+/// let (handle, th) = run_loop::spawn_with(run_loop::builder().stop_policy(StopPolicy::Drain));
+/// let (release, blocked) = mpsc::channel::<()>();
+/// handle.post(move || blocked.recv().unwrap());
+/// let (done, finished) = mpsc::channel();
+/// handle.post(move || done.send(()).unwrap());
+/// handle.stop();
+/// release.send(()).unwrap();
+/// th.join().unwrap();
+/// assert_eq!(finished.try_recv(), Ok(()));
+/// ```
+pub fn spawn_with(builder: RunLoopBuilder) -> (Handle, thread::JoinHandle<()>) {
+    builder.spawn()
+}
+
+/// 启动 n 个循环线程组成的池，循环 panic 时使用 `PanicPolicy::Report`
+///
+/// 同一个键的函数由 `post_keyed` 投递到同一个循环，按投递的顺序执行；`post` 轮流投递。
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop;
+/// use std::collections::HashMap;
+/// use std::sync::{Arc, Mutex};
+/// use std::thread;
+///
+/// let pool = Arc::new(run_loop::pool(4));
+/// assert_eq!(pool.handles().len(), 4);
+///
+/// // 多个线程按键投递，每个键的函数在同一个循环上按顺序执行
+/// let seen = Arc::new(Mutex::new(HashMap::new()));
+/// let producers: Vec<_> = (0..4).map(|p| {
+///     let pool = pool.clone();
+///     let seen = seen.clone();
+///     thread::spawn(move || for i in 0..2000 {
+///         let key = (p, i % 8);
+///         let seen = seen.clone();
+///         pool.post_keyed(&key, move || {
+///             let name = thread::current().name().unwrap().to_string();
+///             let mut seen = seen.lock().unwrap();
+///             let last = seen.entry(key).or_insert((name.clone(), None));
+///             assert_eq!(last.0, name);
+///             assert!(last.1 < Some(i));
+///             last.1 = Some(i);
+///         });
+///     })
+/// }).collect();
+/// for p in producers {
+///     p.join().unwrap();
+/// }
+///
+/// // 池中的循环可以创建对象
+/// let obj = run_loop::new_object_on_blocking(&pool.handles()[1], || 7).unwrap();
+/// let (tx, rx) = run_loop::oneshot();
+/// obj.post_reply(|n| *n * 6, tx);
+/// assert_eq!(rx.recv(), Ok(42));
+/// drop(obj);
+///
+/// // 关闭时先执行完已经投递的函数
+/// let handles = pool.handles().to_vec();
+/// let pool = Arc::try_unwrap(pool).ok().unwrap();
+/// pool.shutdown().unwrap();
+/// assert_eq!(seen.lock().unwrap().len(), 32);
+/// // 每个键最后执行的是它的最后一个函数
+/// assert!(seen.lock().unwrap().iter().all(|(&(_, k), v)| v.1 == Some(1992 + k)));
+/// assert!(handles.iter().all(|h| !h.is_alive()));
+/// ```
+pub fn pool(n: usize) -> LoopPool {
+    LoopPool::new(n, PanicPolicy::Report)
+}
+
+/// 创建单次传值通道，见 `oneshot` 模块
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop;
+/// use std::thread;
+///
+/// let (tx, rx) = run_loop::oneshot();
+/// thread::spawn(move || tx.send(1).unwrap());
+/// assert_eq!(rx.recv(), Ok(1));
+///
+/// let (tx, rx) = run_loop::oneshot::<u32>();
+/// thread::spawn(move || drop(tx));
+/// assert_eq!(rx.recv(), Err(run_loop::oneshot::Canceled));
+/// ```
+pub fn oneshot<T: Send>() -> (oneshot::Sender<T>, oneshot::Receiver<T>) {
+    oneshot::channel()
+}
+
+/// 创建把值送入循环的多生产者通道，capacity 为 `None` 时不限容量
+///
+/// 接收端用 `LoopReceiver::for_each` 绑定到当前线程的循环。和每个值单独投递相比，
+/// 发送只在队列没有等待执行的取出消息时唤醒循环，循环每次把已经到达的值全部交给回调。
+/// 有界通道已满时 `send` 阻塞，`try_send` 返回 `TrySendError::Full`。
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop;
+/// use std::cell::RefCell;
+/// use std::rc::Rc;
+/// use std::sync::Arc;
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::sync::mpsc::TrySendError;
+/// use std::thread;
+///
+/// let (tx, rx) = run_loop::channel(Some(4));
+/// let producers: Vec<_> = (0..2).map(|p| {
+///     let tx = tx.clone();
+///     thread::spawn(move || for i in 0..1000 {
+///         tx.send((p, i)).unwrap();
+///     })
+/// }).collect();
+/// drop(tx);
+///
+/// // 所有发送端释放、值都交给回调后回调随之释放
+/// struct StopOnDrop;
+/// impl Drop for StopOnDrop {
+///     fn drop(&mut self) {
+///         run_loop::stop();
+///     }
+/// }
+/// let next = Rc::new(RefCell::new([0; 2]));
+/// let n = next.clone();
+/// let guard = StopOnDrop;
+/// rx.for_each(move |(p, i)| {
+///     let _ = &guard;
+///     let mut next = n.borrow_mut();
+///     assert_eq!(next[p], i);
+///     next[p] += 1;
+/// });
+/// run_loop::run();
+/// assert_eq!(*next.borrow(), [1000, 1000]);
+/// for p in producers {
+///     p.join().unwrap();
+/// }
+///
+/// // 已满时 try_send 失败，接收端释放时通道中剩余的值随之释放
+/// struct Counted (Arc<AtomicUsize>);
+/// impl Drop for Counted {
+///     fn drop(&mut self) {
+///         self.0.fetch_add(1, Ordering::SeqCst);
+///     }
+/// }
+/// let drops = Arc::new(AtomicUsize::new(0));
+/// let (tx, rx) = run_loop::channel(Some(2));
+/// tx.try_send(Counted(drops.clone())).ok().unwrap();
+/// tx.try_send(Counted(drops.clone())).ok().unwrap();
+/// match tx.try_send(Counted(drops.clone())) {
+///     Err(TrySendError::Full(_)) => {},
+///     _ => panic!(),
+/// }
+/// assert_eq!(drops.load(Ordering::SeqCst), 1);
+/// drop(rx);
+/// assert_eq!(drops.load(Ordering::SeqCst), 3);
+/// assert!(tx.send(Counted(drops.clone())).is_err());
+/// assert_eq!(drops.load(Ordering::SeqCst), 4);
+///
+/// // 绑定的循环结束时剩余的值随之释放，之后发送失败
+/// let (tx, rx) = run_loop::channel(None);
+/// tx.send(Counted(drops.clone())).ok().unwrap();
+/// thread::spawn(move || rx.for_each(|_| unreachable!())).join().unwrap();
+/// assert!(tx.send(Counted(drops.clone())).is_err());
+/// assert_eq!(drops.load(Ordering::SeqCst), 6);
+/// ```
+pub fn channel<T: Send + 'static>(capacity: Option<usize>) -> (LoopSender<T>, LoopReceiver<T>) {
+    channel::channel(capacity)
+}
+
+/// 把 `std::sync::mpsc::Receiver` 收到的值按顺序交给当前线程循环上的 f
+///
+/// 转发线程在 `recv` 上等待，收到的值经 `run_loop::channel` 成批送入循环。
+/// 发送端全部释放、`ForwardHandle` 释放或者循环结束时转发停止，转发线程随之退出并释放 rx。
+/// 释放 `ForwardHandle` 时等待转发线程退出，之后 f 不再被调用。
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop;
+/// use std::cell::RefCell;
+/// use std::rc::Rc;
+/// use std::sync::mpsc;
+/// use std::thread;
+///
+/// // 按发送的顺序交给回调，发送端全部释放后转发线程退出
+/// let (tx, rx) = mpsc::channel();
+/// let got = Rc::new(RefCell::new(Vec::new()));
+/// let g = got.clone();
+/// let fwd = run_loop::forward_receiver(rx, move |i: u32| {
+///     g.borrow_mut().push(i);
+///     if i == 999 {
+///         run_loop::stop();
+///     }
+/// });
+/// let producer = thread::spawn(move || for i in 0..1000 {
+///     tx.send(i).unwrap();
+/// });
+/// run_loop::run();
+/// producer.join().unwrap();
+/// assert_eq!(*got.borrow(), (0..1000).collect::<Vec<_>>());
+/// while !fwd.is_finished() {
+///     thread::yield_now();
+/// }
+/// fwd.stop();
+///
+/// // 停止后回调不再被调用，接收端随转发线程释放
+/// let (tx, rx) = mpsc::channel::<u32>();
+/// let fwd = run_loop::forward_receiver(rx, |_| unreachable!());
+/// assert!(!fwd.is_finished());
+/// fwd.stop();
+/// assert!(tx.send(1).is_err());
+///
+/// // 释放句柄时转发线程已经退出，已经送入循环的值也被丢弃
+/// let (tx, rx) = mpsc::channel::<u32>();
+/// let fwd = run_loop::forward_receiver(rx, |_| unreachable!());
+/// tx.send(1).unwrap();
+/// thread::sleep(std::time::Duration::from_millis(20));
+/// drop(fwd);
+/// assert!(tx.send(2).is_err());
+/// run_loop::run_pending();
+/// ```
+pub fn forward_receiver<T, F>(rx: ::std::sync::mpsc::Receiver<T>, f: F) -> ForwardHandle
+    where T: Send + 'static, F: FnMut(T) + 'static {
+    forward::forward(rx, f)
+}
+
+/// 在 handle 对应的循环创建循环内对象
+///
+/// ctor 在目标循环所在的线程执行，对象本身不会跨越线程，只有句柄被送回。
+/// 返回的接收端可以作为 `Future` 等待，目标循环在创建前退出时得到 `Canceled`。
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop;
+/// use vnbase::run_loop::oneshot::Canceled;
+/// use std::cell::RefCell;
+/// use std::sync::atomic::{AtomicBool, Ordering};
+/// use std::sync::mpsc;
+/// use std::thread;
+///
+/// let (tx, rx) = mpsc::channel();
+/// let th = thread::spawn(move || {
+///     tx.send((run_loop::clone_handle(), thread::current().id())).unwrap();
+///     run_loop::run();
+/// });
+/// let (handle, loop_thread) = rx.recv().unwrap();
+///
+/// // 对象在目标线程构造，句柄送回调用者
+/// let obj = run_loop::new_object_on(&handle, || RefCell::new(thread::current().id())).recv().unwrap();
+/// let (tx, rx) = mpsc::channel();
+/// obj.post(move |id| tx.send((*id.borrow(), thread::current().id())).unwrap());
+/// assert_eq!(rx.recv().unwrap(), (loop_thread, loop_thread));
+///
+/// drop(obj);
+/// handle.stop();
+/// th.join().unwrap();
+///
+/// // 目标循环已经结束，ctor 不会执行
+/// static CALLED: AtomicBool = AtomicBool::new(false);
+/// let rx = run_loop::new_object_on(&handle, || CALLED.store(true, Ordering::SeqCst));
+/// assert_eq!(rx.recv().err(), Some(Canceled));
+/// assert!(!CALLED.load(Ordering::SeqCst));
+/// ```
+pub fn new_object_on<T, F>(handle: &Handle, ctor: F) -> oneshot::Receiver<ObjectHandle<T>>
+    where T: 'static, F: FnOnce() -> T + Send + 'static {
+    let (tx, rx) = oneshot::channel();
+    handle.post(move || {
+        let _ = tx.send(new_object(ctor()));
+    });
+    rx
+}
+
+/// `new_object_on` 的阻塞版本，在目标循环所在的线程调用时直接创建
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop;
+/// use std::rc::Rc;
+/// use std::cell::Cell;
+/// use std::thread;
+/// use std::sync::mpsc;
+///
+/// let (tx, rx) = mpsc::channel();
+/// let th = thread::spawn(move || {
+///     tx.send(run_loop::clone_handle()).unwrap();
+///     run_loop::run();
+/// });
+/// let handle = rx.recv().unwrap();
+///
+/// // Rc 不能跨越线程，但对象在目标线程构造
+/// let obj = run_loop::new_object_on_blocking(&handle, || Rc::new(Cell::new(0))).unwrap();
+/// let (tx, rx) = mpsc::channel();
+/// obj.post(move |rc| {
+///     rc.set(rc.get() + 1);
+///     tx.send(rc.get()).unwrap();
+/// });
+/// assert_eq!(rx.recv().unwrap(), 1);
+///
+/// drop(obj);
+/// handle.stop();
+/// th.join().unwrap();
+/// ```
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+pub fn new_object_on_blocking<T, F>(handle: &Handle, ctor: F) -> Result<ObjectHandle<T>, oneshot::Canceled>
+    where T: 'static, F: FnOnce() -> T + Send + 'static {
+    if is_own_handle(handle) {
+        Ok(new_object(ctor()))
+    }
+    else {
+        new_object_on(handle, ctor).recv()
+    }
+}
+
+/// 每轮默认最多处理的消息数
+const DEFAULT_MSG_BATCH: usize = 1024;
+
+/// 处理一轮消息，返回执行的个数
+fn process_msgs(rl: &RunLoop) -> usize {
+    // 超过每轮的上限时剩余的消息留在队列中，先检查定时器和退出请求
+    process_msgs_upto(rl, rl.msg_batch.get().unwrap_or(usize::MAX))
+}
+
+/// 处理开始时已经投递的消息，最多 limit 条
+fn process_msgs_upto(rl: &RunLoop, limit: usize) -> usize {
+    // 回调中嵌套处理消息时从同一个队列继续取出，保持投递的顺序
+    let _dispatching = Dispatching::new(rl);
+    let mut end = rl.core.queue.end();
+    let mut n = 0;
+    while n < limit {
+        match unsafe { rl.core.queue.pop(&mut end) } {
+            Some(msg) => {
+                let _origin = origin::Scope::new();
+                rl.traced(TraceEvent::MsgStart, || unsafe { queue::run(msg) }, |elapsed| TraceEvent::MsgEnd { elapsed: elapsed });
+                rl.run_deferred();
+                rl.processed.set(rl.processed.get() + 1);
+                n += 1;
+            },
+            None => break,
         }
-        None
-    }
-    else {
-        Some(msgs)
     }
+    n
+}
+
+#[cfg(feature = "io")]
+fn with_reactor<F, R>(f: F) -> ::std::io::Result<R> where F: FnOnce(&mut io::Reactor) -> ::std::io::Result<R> {
+    // 线程结束、循环销毁后（如释放 `io::Registration` 时）返回错误
+    RUN_LOOP.try_with(|rl| {
+        let mut reactor = rl.io.borrow_mut();
+        if reactor.is_none() {
+            let (r, waker) = io::Reactor::new()?;
+            *reactor = Some(r);
+            rl.core.lock().waker = Some(waker);
+        }
+        f(reactor.as_mut().unwrap())
+    }).unwrap_or_else(|_| Err(::std::io::Error::other("run loop is destroyed")))
+}
+
+#[cfg(feature = "io")]
+fn take_io_error() -> Option<::std::io::Error> {
+    RUN_LOOP.try_with(|rl| {
+        rl.io.borrow_mut().as_mut().and_then(|r| r.take_error())
+    }).unwrap_or(None)
 }
 
 fn push_timed_action(ta: Rc<core::TimedAction>, time: Instant) {
     RUN_LOOP.with(|rl| {
+        let time = rl.coalesce(time);
         rl.timers.borrow_mut().push(ta, time);
     })
 }
 
 fn adjust_timed_action(node: &core::TimedActionNode, time: Instant) {
     RUN_LOOP.with(|rl| {
+        let time = rl.coalesce(time);
         rl.timers.borrow_mut().adjust(node, time);
     })
 }
@@ -305,8 +3594,35 @@ fn remove_timed_action(node: &core::TimedActionNode) {
     })
 }
 
+/// 向当前线程的循环投递不要求 `Send` 的函数
+///
+/// 循环正在销毁时直接丢弃函数。
+fn post_local<T>(f: T) where T: FnOnce() + 'static {
+    struct Local<T> (T);
+    // 只投递到当前线程的循环，队列中的消息在循环销毁时由本线程释放，
+    // 循环销毁后不会再有本地投递，因此函数不会在其它线程执行或释放
+    unsafe impl<T> Send for Local<T> {}
+
+    let msg = Local(f);
+    let _ = RUN_LOOP.try_with(move |rl| {
+        rl.core.post(move || {
+            let msg = msg;
+            (msg.0)()
+        });
+    });
+}
+
+/// 从链表中取出节点，由调用者负责销毁
+unsafe fn unlink_object(ptr: *mut object::Object) -> Box<object::Object> {
+    RUN_LOOP.with(|rl| rl.objects.borrow_mut().remove(ptr))
+}
+
+/// 循环已经销毁时什么都不做，节点由 `ObjectList` 在销毁时统一释放
 unsafe fn drop_object(ptr: *mut object::Object) {
-    RUN_LOOP.with(|rl| {
+    let node = RUN_LOOP.try_with(|rl| {
         rl.objects.borrow_mut().remove(ptr)
-    })
+    });
+    if let Ok(node) = node {
+        object::destroy(node);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,374 @@
+//! 套接字就绪事件，需要开启 `net` 特性
+//!
+//! `register` 把 mio 的 IO 源注册到当前线程的循环，就绪时在循环所在的线程调用回调，
+//! 回调中可以访问循环内的对象。事件有两种等待方式，见 `Poller`：
+//! 默认和 `io` 模块一样由循环直接等待 `mio::Poll`；循环不由 `run` 等待时（例如由外部事件循环通过
+//! `run_pending` 驱动，或者使用 `WaitStrategy::MessagePump`）改用辅助线程等待，事件投递到循环。
+//!
+//! # Examples
+//! ```
+//! use vnbase::run_loop;
+//! use vnbase::run_loop::net::{self, Interest, Poller};
+//! use std::net::TcpStream;
+//! use std::thread;
+//!
+//! // 循环照常在条件变量上等待，由辅助线程等待 IO
+//! net::set_poller(Poller::Thread);
+//! let listener = mio::net::TcpListener::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+//! let addr = listener.local_addr().unwrap();
+//! let _reg = net::register(listener, Interest::READABLE, |_| run_loop::stop()).unwrap();
+//!
+//! let th = thread::spawn(move || TcpStream::connect(addr).unwrap());
+//! run_loop::run();
+//! th.join().unwrap();
+//! ```
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::io;
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+
+use mio::{Events, Poll, Registry, Waker};
+
+use super::io as loop_io;
+
+pub use mio::{Interest, Token};
+pub use mio::event::{Event, Source};
+
+/// 等待 IO 事件的方式，见 `set_poller`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Poller {
+    /// 循环等待时直接等待 `mio::Poll`，代替条件变量等其它等待方式，同 `io` 模块，默认方式
+    Inline,
+    /// 辅助线程等待 `mio::Poll`，把事件投递到循环，循环的等待方式不变
+    ///
+    /// 事件多经过一次投递，适合不由 `run` 等待的循环。
+    Thread,
+}
+
+/// 设置当前线程之后的 `register` 使用的等待方式，已经注册的 IO 源不受影响
+pub fn set_poller(poller: Poller) {
+    POLLER.with(|p| p.set(poller));
+}
+
+/// 在当前线程的循环上注册 source，就绪时在循环中调用 callback，返回的 `Registration` 释放时注销
+///
+/// 标记自动分配。`Poller::Inline` 时和 `io::Registration::new` 相同。
+pub fn register<S, F>(source: S, interest: Interest, callback: F) -> io::Result<Registration<S>>
+    where S: Source, F: FnMut(&Event) + 'static {
+    let inner = match POLLER.with(|p| p.get()) {
+        Poller::Inline => Inner::Inline(loop_io::Registration::new(source, interest, callback)?),
+        Poller::Thread => {
+            let mut source = source;
+            let token = with_helper(|helper| {
+                helper.next_token += 1;
+                let token = Token(helper.next_token);
+                helper.registry.register(&mut source, token, interest)?;
+                helper.callbacks.insert(token, Some(Box::new(callback)));
+                Ok(token)
+            })?;
+            Inner::Thread(source, token)
+        },
+    };
+    Ok(Registration {
+        inner: inner,
+        phantom: PhantomData,
+    })
+}
+
+/// 取出使当前线程循环退出的等待错误，包括辅助线程的等待错误，见 `io::take_error`
+pub fn take_error() -> Option<io::Error> {
+    HELPER.try_with(|h| h.borrow_mut().as_mut().and_then(|h| h.error.take()))
+        .ok()
+        .and_then(|e| e)
+        .or_else(loop_io::take_error)
+}
+
+/// `register` 注册的 IO 源，释放时注销
+///
+/// 只能在注册的线程使用，因此不是 `Send`。
+///
+/// # Examples
+/// 两个线程各运行一个循环，服务端由辅助线程等待，客户端由循环直接等待：
+/// ```
+/// extern crate mio;
+/// extern crate vnbase;
+///
+/// use vnbase::run_loop;
+/// use vnbase::run_loop::net::{self, Interest, Poller, Registration};
+/// use mio::net::{TcpListener, TcpStream};
+/// use std::cell::RefCell;
+/// use std::io::{ErrorKind, Read, Write};
+/// use std::rc::Rc;
+/// use std::sync::mpsc;
+/// use std::thread;
+///
+/// // 读出所有可读的数据，返回对端是否已经关闭
+/// fn drain(stream: &mut TcpStream, buf: &mut Vec<u8>) -> bool {
+///     let mut chunk = [0; 64];
+///     loop {
+///         match stream.read(&mut chunk) {
+///             Ok(0) => return true,
+///             Ok(n) => buf.extend_from_slice(&chunk[..n]),
+///             Err(ref e) if e.kind() == ErrorKind::WouldBlock => return false,
+///             Err(e) => panic!("{}", e),
+///         }
+///     }
+/// }
+///
+/// # fn main() {
+/// let (tx, rx) = mpsc::channel();
+/// let server = thread::spawn(move || {
+///     net::set_poller(Poller::Thread);
+///     let listener = TcpListener::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+///     tx.send(listener.local_addr().unwrap()).unwrap();
+///
+///     let acceptor: Rc<RefCell<Option<Registration<TcpListener>>>> = Rc::new(RefCell::new(None));
+///     let conn: Rc<RefCell<Option<Registration<TcpStream>>>> = Rc::new(RefCell::new(None));
+///     let (a, c) = (acceptor.clone(), conn.clone());
+///     *acceptor.borrow_mut() = Some(net::register(listener, Interest::READABLE, move |_| {
+///         let (stream, _) = match a.borrow_mut().as_mut().unwrap().get_mut().accept() {
+///             Ok(s) => s,
+///             Err(ref e) if e.kind() == ErrorKind::WouldBlock => return,
+///             Err(e) => panic!("{}", e),
+///         };
+///         let c2 = c.clone();
+///         let mut buf = Vec::new();
+///         *c.borrow_mut() = Some(net::register(stream, Interest::READABLE, move |_| {
+///             let mut conn = c2.borrow_mut();
+///             let closed = {
+///                 let stream = conn.as_mut().unwrap().get_mut();
+///                 let closed = drain(stream, &mut buf);
+///                 stream.write_all(&buf).unwrap();
+///                 closed
+///             };
+///             buf.clear();
+///             if closed {
+///                 *conn = None;
+///                 run_loop::stop();
+///             }
+///         }).unwrap());
+///     }).unwrap());
+///     run_loop::run();
+///     acceptor.borrow_mut().take();
+/// });
+///
+/// let reply = Rc::new(RefCell::new(Vec::new()));
+/// let client: Rc<RefCell<Option<Registration<TcpStream>>>> = Rc::new(RefCell::new(None));
+/// let (c, r) = (client.clone(), reply.clone());
+/// let stream = TcpStream::connect(rx.recv().unwrap()).unwrap();
+/// *client.borrow_mut() = Some(net::register(stream, Interest::WRITABLE, move |event| {
+///     let mut client = c.borrow_mut();
+///     let reg = client.as_mut().unwrap();
+///     if event.is_writable() {
+///         reg.get_mut().write_all(b"hello").unwrap();
+///         reg.reregister(Interest::READABLE).unwrap();
+///     }
+///     else {
+///         drain(reg.get_mut(), &mut r.borrow_mut());
+///         if r.borrow().len() == 5 {
+///             run_loop::stop();
+///         }
+///     }
+/// }).unwrap());
+/// run_loop::run();
+/// assert_eq!(&reply.borrow()[..], b"hello");
+///
+/// // 关闭连接，服务端随之退出
+/// client.borrow_mut().take();
+/// server.join().unwrap();
+/// # }
+/// ```
+pub struct Registration<S> where S: Source {
+    inner: Inner<S>,
+    phantom: PhantomData<Rc<()>>,
+}
+
+enum Inner<S> where S: Source {
+    Inline(loop_io::Registration<S>),
+    Thread(S, Token),
+}
+
+impl<S> Registration<S> where S: Source {
+    /// 修改关注的事件，回调不变
+    pub fn reregister(&mut self, interest: Interest) -> io::Result<()> {
+        match self.inner {
+            Inner::Inline(ref mut reg) => reg.reregister(interest),
+            Inner::Thread(ref mut source, token) => with_helper(|helper| helper.registry.reregister(source, token, interest)),
+        }
+    }
+
+    /// 分配的标记
+    pub fn token(&self) -> Token {
+        match self.inner {
+            Inner::Inline(ref reg) => reg.token(),
+            Inner::Thread(_, token) => token,
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        match self.inner {
+            Inner::Inline(ref reg) => reg.get_ref(),
+            Inner::Thread(ref source, _) => source,
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        match self.inner {
+            Inner::Inline(ref mut reg) => reg.get_mut(),
+            Inner::Thread(ref mut source, _) => source,
+        }
+    }
+
+    /// 注销并取回 IO 源
+    pub fn into_inner(mut self) -> S {
+        let source = match self.inner {
+            Inner::Inline(ref mut reg) => unsafe { ptr::read(reg) }.into_inner(),
+            Inner::Thread(ref mut source, token) => {
+                deregister(source, token);
+                unsafe { ptr::read(source) }
+            },
+        };
+        mem::forget(self);
+        source
+    }
+}
+
+impl<S> Drop for Registration<S> where S: Source {
+    fn drop(&mut self) {
+        if let Inner::Thread(ref mut source, token) = self.inner {
+            deregister(source, token);
+        }
+    }
+}
+
+/// 从辅助线程注销，循环已经销毁时辅助线程也已经退出
+fn deregister<S>(source: &mut S, token: Token) where S: Source {
+    let _ = HELPER.try_with(|h| {
+        if let Some(ref mut helper) = *h.borrow_mut() {
+            helper.callbacks.remove(&token);
+            let _ = helper.registry.deregister(source);
+        }
+    });
+}
+
+type Callback = Box<FnMut(&Event)>;
+
+/// 辅助线程唤醒自己的保留标记
+const WAKE_TOKEN: Token = Token(usize::MAX);
+
+thread_local! {
+    static POLLER: Cell<Poller> = const { Cell::new(Poller::Inline) };
+    /// 当前线程循环的辅助线程，第一次以 `Poller::Thread` 注册时启动
+    static HELPER: RefCell<Option<Helper>> = const { RefCell::new(None) };
+}
+
+struct Helper {
+    registry: Registry,
+    waker: Arc<Waker>,
+    /// 要求辅助线程退出
+    closed: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    callbacks: HashMap<Token, Option<Callback>>,
+    /// 上一次分配的标记，只增不减，已经投递的旧事件不会交给新注册的回调
+    next_token: usize,
+    /// 使辅助线程退出的等待错误，见 `take_error`
+    error: Option<io::Error>,
+}
+
+impl Helper {
+    fn start() -> io::Result<Helper> {
+        let mut poll = Poll::new()?;
+        let registry = poll.registry().try_clone()?;
+        let waker = Arc::new(Waker::new(poll.registry(), WAKE_TOKEN)?);
+        let closed = Arc::new(AtomicBool::new(false));
+        let handle = super::clone_handle();
+        let c = closed.clone();
+        let thread = thread::Builder::new().name(String::from("vnbase-net-poller")).spawn(move || {
+            let mut events = Events::with_capacity(256);
+            loop {
+                match poll.poll(&mut events, None) {
+                    Ok(()) => {},
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => {
+                        // 无法继续等待，保存错误后使循环退出，同 `io` 模块
+                        handle.post(move || {
+                            let _ = HELPER.try_with(|h| {
+                                if let Some(ref mut helper) = *h.borrow_mut() {
+                                    helper.error = Some(e);
+                                }
+                            });
+                            super::stop();
+                        });
+                        return;
+                    },
+                }
+                if c.load(Ordering::SeqCst) {
+                    return;
+                }
+                let ready: Vec<Event> = events.iter().filter(|e| e.token() != WAKE_TOKEN).cloned().collect();
+                if !ready.is_empty() && handle.try_post(move || dispatch(ready)).is_err() {
+                    return;
+                }
+            }
+        })?;
+        Ok(Helper {
+            registry: registry,
+            waker: waker,
+            closed: closed,
+            thread: Some(thread),
+            callbacks: HashMap::new(),
+            next_token: 0,
+            error: None,
+        })
+    }
+}
+
+impl Drop for Helper {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::SeqCst);
+        let _ = self.waker.wake();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn with_helper<F, R>(f: F) -> io::Result<R> where F: FnOnce(&mut Helper) -> io::Result<R> {
+    // 线程结束、循环销毁后返回错误
+    HELPER.try_with(|h| {
+        let mut helper = h.borrow_mut();
+        if helper.is_none() {
+            *helper = Some(Helper::start()?);
+        }
+        f(helper.as_mut().unwrap())
+    }).unwrap_or_else(|_| Err(io::Error::other("run loop is destroyed")))
+}
+
+/// 在循环所在的线程分发辅助线程取得的事件
+fn dispatch(events: Vec<Event>) {
+    for event in events.iter() {
+        let token = event.token();
+        let cb = HELPER.try_with(|h| {
+            h.borrow_mut().as_mut().and_then(|helper| helper.callbacks.get_mut(&token).and_then(|cb| cb.take()))
+        });
+        if let Ok(Some(mut cb)) = cb {
+            cb(event);
+            // 回调中注销时不放回
+            let _ = HELPER.try_with(|h| {
+                if let Some(ref mut helper) = *h.borrow_mut() {
+                    if let Some(slot) = helper.callbacks.get_mut(&token) {
+                        if slot.is_none() {
+                            *slot = Some(cb);
+                        }
+                    }
+                }
+            });
+        }
+    }
+}
@@ -1,22 +1,42 @@
 use std::marker::PhantomData;
-use std::mem;
 use std::sync::atomic;
 use std::sync::atomic::AtomicUsize;
-use std::isize;
+use std::sync::{Mutex, Condvar};
 use std::process::abort;
 use std::ptr;
+use std::fmt;
+use std::error;
+use std::hash::{Hash, Hasher};
+use std::any::{Any, TypeId};
+use std::mem;
+use std::mem::MaybeUninit;
+use std::cell::RefCell;
+use std::time::Duration;
+
+use super::Instant;
+use super::oneshot;
+#[cfg(feature = "nightly")]
+use std::ops::CoerceUnsized;
+#[cfg(feature = "nightly")]
+use std::marker::Unsize;
 
 pub trait Object {
     fn set_next(&mut self, obj: Option<*mut Object>);
     fn get_next(&self) -> Option<*mut Object>;
     fn set_prev(&mut self, obj: Option<*mut Object>);
     fn get_prev(&self) -> Option<*mut Object>;
+    fn detach(&mut self);
+    fn handle(&self) -> *mut ObjH;
+    fn obj_type_id(&self) -> TypeId;
+    #[cfg(feature = "debug-introspection")]
+    fn type_name(&self) -> &'static str;
 }
 
 struct ObjectNode<T: 'static> {
-    handle: *mut ObjH<T>,
+    handle: *mut ObjH,
     next: Option<*mut Object>,
     prev: Option<*mut Object>,
+    detach: Option<fn(&mut T)>,
     obj: T,
 }
 
@@ -36,10 +56,69 @@ impl<T> Object for ObjectNode<T> {
     fn get_prev(&self) -> Option<*mut Object> {
         self.prev
     }
+
+    fn detach(&mut self) {
+        if let Some(f) = self.detach.take() {
+            f(&mut self.obj);
+        }
+    }
+
+    fn handle(&self) -> *mut ObjH {
+        self.handle
+    }
+
+    fn obj_type_id(&self) -> TypeId {
+        TypeId::of::<T>()
+    }
+
+    #[cfg(feature = "debug-introspection")]
+    fn type_name(&self) -> &'static str {
+        ::std::any::type_name::<T>()
+    }
+}
+
+/// 循环内对象的生命周期回调
+///
+/// 通过 `run_loop::new_loop_object` 创建的对象，在加入循环前调用 `attached`，
+/// 在被释放前（包括循环退出时）调用 `detaching`，两者都只调用一次，且都在对象所在的线程。
+/// `attached` panic 时对象不会加入循环，也不会调用 `detaching`。默认实现什么都不做。
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop;
+/// use std::rc::Rc;
+/// use std::cell::Cell;
+///
+/// struct Obj (Rc<Cell<(u32, u32)>>);
+/// impl run_loop::LoopObject for Obj {
+///     fn attached(&mut self) {
+///         let (a, d) = self.0.get();
+///         self.0.set((a + 1, d));
+///     }
+///     fn detaching(&mut self) {
+///         let (a, d) = self.0.get();
+///         self.0.set((a, d + 1));
+///     }
+/// }
+///
+/// let counter = Rc::new(Cell::new((0, 0)));
+/// let obj = run_loop::new_loop_object(Obj(counter.clone()));
+/// assert_eq!(counter.get(), (1, 0));
+///
+/// let other = obj.clone();
+/// drop(obj);
+/// assert_eq!(counter.get(), (1, 0));
+/// drop(other);
+/// assert_eq!(counter.get(), (1, 1));
+/// ```
+pub trait LoopObject {
+    fn attached(&mut self) {}
+    fn detaching(&mut self) {}
 }
 
 pub struct ObjectList {
     head: Option<*mut Object>,
+    count: usize,
     phantom: PhantomData<Box<Object>>,
 }
 
@@ -50,6 +129,7 @@ impl Drop for ObjectList {
             while let Some(node) = head {
                 let node = Box::from_raw(node);
                 head = node.get_next();
+                destroy(node);
             }
         }
     }
@@ -59,42 +139,87 @@ impl ObjectList {
     pub fn new() -> Self {
         ObjectList {
             head: None,
+            count: 0,
             phantom: PhantomData,
         }
     }
 
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
     pub fn create<T>(&mut self, obj: T) -> ObjectHandle<T>
         where T: 'static {
-        unsafe {
-            let node = Box::new(ObjectNode {
-                handle: mem::uninitialized(),
-                next: self.head,
-                prev: None,
-                obj: obj,
-            });
+        self.insert(obj, None)
+    }
 
-            let node = Box::into_raw(node);
+    pub fn create_loop_object<T>(&mut self, obj: T) -> ObjectHandle<T>
+        where T: LoopObject + 'static {
+        self.insert(obj, Some(T::detaching))
+    }
 
-            (*node).handle = Box::into_raw(Box::new(ObjH {
-                ptr: node,
+    fn insert<T>(&mut self, obj: T, detach: Option<fn(&mut T)>) -> ObjectHandle<T>
+        where T: 'static {
+        unsafe {
+            // 先分配控制块，节点创建时即可持有有效的指针，创建节点后再回填 ptr
+            let handle = Box::into_raw(Box::new(ObjH {
+                ptr: None,
+                obj: ptr::null(),
+                core: super::clone_handle(),
                 strong: AtomicUsize::new(1),
-                weak: AtomicUsize::new(1),
+                // 一个由所有强引用共同持有，一个由节点持有
+                weak: AtomicUsize::new(2),
+                watched: AtomicUsize::new(0),
+                on_drop: Mutex::new(Some(Vec::new())),
             }));
 
-            if let Some(ref mut head) = self.head {
-                (**head).set_prev(Some(node));
-                *head = node;
-            }
+            let node = Box::into_raw(Box::new(ObjectNode {
+                handle: handle,
+                next: None,
+                prev: None,
+                detach: detach,
+                obj: obj,
+            }));
+
+            (*handle).ptr = Some(node);
+            (*handle).obj = &(*node).obj as *const T as *const ();
+            self.link(node);
 
             ObjectHandle {
-                core: super::clone_handle(),
-                handle: (*node).handle,
+                core: (*handle).core.clone(),
+                handle: handle,
+                obj: &(*node).obj,
                 phantom: PhantomData,
             }
         }
     }
 
-    pub unsafe fn remove(&mut self, node: *mut Object) {
+    /// 把节点加入链表头部
+    unsafe fn link(&mut self, node: *mut Object) {
+        (*node).set_next(self.head);
+        (*node).set_prev(None);
+        if let Some(head) = self.head {
+            (*head).set_prev(Some(node));
+        }
+        self.head = Some(node);
+        self.count += 1;
+    }
+
+    #[cfg(feature = "debug-introspection")]
+    pub fn type_counts(&self) -> Vec<(&'static str, usize)> {
+        let mut counts = ::std::collections::BTreeMap::new();
+        let mut node = self.head;
+        while let Some(n) = node {
+            unsafe {
+                *counts.entry((*n).type_name()).or_insert(0) += 1;
+                node = (*n).get_next();
+            }
+        }
+        counts.into_iter().collect()
+    }
+
+    /// 从链表中取出节点，由调用者在释放借用后销毁
+    pub unsafe fn remove(&mut self, node: *mut Object) -> Box<Object> {
         let node = Box::from_raw(node);
         let next = node.get_next();
         let prev = node.get_prev();
@@ -107,6 +232,8 @@ impl ObjectList {
         if let Some(next) = next {
             (*next).set_prev(prev);
         }
+        self.count -= 1;
+        node
     }
 }
 
@@ -135,16 +262,66 @@ impl ObjectList {
 /// run_loop::run();
 /// th.join().unwrap();
 /// ```
-pub struct ObjectHandle<T: 'static> {
+///
+/// 对象不要求 `Send`，即使最后一个句柄在其它线程释放，对象也在所在的线程释放。
+/// 线程退出时仍然存在的对象随循环一起释放，此时不能再访问其它对象：
+/// ```
+/// use vnbase::run_loop;
+/// use std::rc::Rc;
+/// use std::thread;
+///
+/// struct Holder (run_loop::ObjectHandle<Rc<u32>>);
+/// impl Drop for Holder {
+///     fn drop(&mut self) {
+///         // 循环已经在销毁，另一个对象可能已经释放
+///         assert!(self.0.get_ref().is_none());
+///     }
+/// }
+///
+/// let obj = thread::spawn(|| {
+///     let a = run_loop::new_object(Rc::new(1));
+///     let b = run_loop::new_object(Holder(a.clone()));
+///     run_loop::clone_handle().post(move || drop(b));
+///     a
+/// }).join().unwrap();
+///
+/// assert!(obj.get_ref().is_none());
+/// obj.post(|_| unreachable!());
+/// drop(obj);
+/// ```
+///
+/// 对象的引用不能超出句柄的生命周期：
+/// ```compile_fail
+/// use vnbase::run_loop;
+///
+/// let r = {
+///     let obj = run_loop::new_object(1);
+///     obj.get_ref().unwrap()
+/// };
+/// ```
+pub struct ObjectHandle<T: ?Sized + 'static> {
     core: super::Handle,
-    handle: *mut ObjH<T>,
+    handle: *mut ObjH,
+    obj: *const T,
     phantom: PhantomData<T>,
 }
 
-unsafe impl<T> Send for ObjectHandle<T> {}
-unsafe impl<T> Sync for ObjectHandle<T> {}
+// 句柄本身只包含原子计数和循环句柄，可以在线程间传递和共享，不要求 `T: Send`：
+// - 对象只在所在的线程访问：`get_ref` 检查循环标识，`post` 的函数在所在的线程执行，
+//   因此非 `Sync` 的 `T` 也不会被并发访问；`&T` 能否离开该线程由 `T: Sync` 决定。
+// - 对象只在所在的线程释放：在其它线程释放最后一个强引用时只投递释放，
+//   投递的函数只包含控制块和对象的指针，不会在其它线程读取或释放 `T`。
+// - 投递时为消息增加强引用，节点在消息执行前不会被释放；循环销毁时先丢弃剩余的消息，
+//   再释放所有节点，之后的投递不会执行，因此消息中的对象指针不会悬空。
+// - `erase` 得到的指针由对象的引用转换而来，只在所在的线程转换，和原句柄共享控制块。
+// - 循环销毁后 `current_id` 返回 0，`get_ref` 总是失败，不会访问已释放的节点。
+unsafe impl<T: ?Sized> Send for ObjectHandle<T> {}
+unsafe impl<T: ?Sized> Sync for ObjectHandle<T> {}
 
-impl<T> Clone for ObjectHandle<T> {
+#[cfg(feature = "nightly")]
+impl<T, U> CoerceUnsized<ObjectHandle<U>> for ObjectHandle<T> where T: ?Sized + Unsize<U>, U: ?Sized {}
+
+impl<T: ?Sized> Clone for ObjectHandle<T> {
     fn clone(&self) -> Self {
         
         unsafe { ObjH::inc_strong(self.handle); }
@@ -152,48 +329,447 @@ impl<T> Clone for ObjectHandle<T> {
         ObjectHandle {
             core: self.core.clone(),
             handle: self.handle,
+            obj: self.obj,
             phantom: PhantomData,
         }
     }
 }
 
-impl<T> Drop for ObjectHandle<T> {
+impl<T: ?Sized> Drop for ObjectHandle<T> {
     fn drop(&mut self) {
         if let Some(ptr) = unsafe { ObjH::dec_strong(self.handle) } {
             if super::is_own_handle(&self.core) {
                 unsafe { super::drop_object(ptr.0); }
             }
-            else {
+            else if self.core.is_alive() {
                 self.core.post(move || unsafe { super::drop_object(ptr.0) });
             }
+            // 循环已经销毁时节点由 `ObjectList` 释放，这里什么都不做
         }
     }
 }
 
-impl<T> ObjectHandle<T> {
+impl<T: ?Sized> PartialEq for ObjectHandle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr_eq(other)
+    }
+}
+
+impl<T: ?Sized> Eq for ObjectHandle<T> {}
+
+impl<T: ?Sized> Hash for ObjectHandle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.handle.hash(state)
+    }
+}
+
+impl<T: ?Sized> ObjectHandle<T> {
+    /// 判断两个句柄是否指向同一个对象
+    ///
+    /// 句柄的 `Eq` 和 `Hash` 也以此为准，可以直接放入 `HashSet`。
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::collections::HashSet;
+    ///
+    /// let a = run_loop::new_object(1);
+    /// let b = run_loop::new_object(1);
+    /// let weak = a.downgrade();
+    ///
+    /// assert!(a.ptr_eq(&a.clone()));
+    /// assert!(!a.ptr_eq(&b));
+    /// assert!(weak.ptr_eq(&a.downgrade()));
+    /// assert!(weak.ptr_eq_handle(&a));
+    /// assert!(!weak.ptr_eq_handle(&b));
+    ///
+    /// let mut set = HashSet::new();
+    /// set.insert(a.clone());
+    /// set.insert(a.clone());
+    /// set.insert(b.clone());
+    /// assert_eq!(set.len(), 2);
+    /// assert!(set.contains(&weak.upgrade().unwrap()));
+    /// ```
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        self.handle == other.handle
+    }
+
+    /// 强引用计数
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::thread;
+    ///
+    /// let before = run_loop::object_count();
+    /// let obj = run_loop::new_object(());
+    /// assert_eq!(run_loop::object_count(), before + 1);
+    /// assert_eq!((obj.strong_count(), obj.weak_count()), (1, 0));
+    ///
+    /// let weak = obj.downgrade();
+    /// let other = obj.clone();
+    /// assert_eq!((obj.strong_count(), obj.weak_count()), (2, 1));
+    /// assert_eq!((weak.strong_count(), weak.weak_count()), (2, 1));
+    ///
+    /// thread::spawn(move || drop(other)).join().unwrap();
+    /// assert_eq!(obj.strong_count(), 1);
+    ///
+    /// // 在其它线程释放最后一个句柄，对象的释放被投递回本线程
+    /// thread::spawn(move || drop(obj)).join().unwrap();
+    /// assert_eq!((weak.strong_count(), weak.weak_count()), (0, 0));
+    /// assert_eq!(run_loop::object_count(), before + 1);
+    ///
+    /// run_loop::clone_handle().post(run_loop::stop);
+    /// run_loop::run();
+    /// assert_eq!(run_loop::object_count(), before);
+    /// ```
+    pub fn strong_count(&self) -> usize {
+        unsafe { (*self.handle).strong.load(atomic::Ordering::Relaxed) }
+    }
+
+    /// 弱引用计数
+    pub fn weak_count(&self) -> usize {
+        unsafe { (*self.handle).weak.load(atomic::Ordering::Relaxed).saturating_sub(2) }
+    }
+
+    /// 向对象所在的循环投递函数
+    ///
+    /// 同一个线程先后投递的函数按投递的顺序执行，即使通过同一个对象的不同句柄投递，
+    /// 也包括通过 `Handle::post` 和弱引用投递的函数。不同线程之间投递的顺序不作保证。
+    ///
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::cell::RefCell;
+    /// use std::thread;
+    ///
+    /// let obj = run_loop::new_object(RefCell::new(Vec::new()));
+    /// let threads: Vec<_> = (0..4).map(|t| {
+    ///     let handles = vec![obj.clone(), obj.clone(), obj.clone()];
+    ///     let weak = obj.downgrade();
+    ///     thread::spawn(move || {
+    ///         for seq in 0..1000 {
+    ///             if seq % 4 == 3 {
+    ///                 assert!(weak.post(move |v| v.borrow_mut().push((t, seq))));
+    ///             }
+    ///             else {
+    ///                 handles[seq % 4].post(move |v| v.borrow_mut().push((t, seq)));
+    ///             }
+    ///         }
+    ///     })
+    /// }).collect();
+    /// for th in threads {
+    ///     th.join().unwrap();
+    /// }
+    /// run_loop::clone_handle().post(run_loop::stop);
+    /// run_loop::run();
+    ///
+    /// let v = obj.get_ref().unwrap().borrow();
+    /// assert_eq!(v.len(), 4000);
+    /// for t in 0..4 {
+    ///     let seqs: Vec<_> = v.iter().filter(|e| e.0 == t).map(|e| e.1).collect();
+    ///     assert_eq!(seqs, (0..1000).collect::<Vec<_>>());
+    /// }
+    /// ```
+    ///
+    /// 投递的函数持有一个强引用，在其它线程释放所有句柄后仍然可以执行：
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::cell::Cell;
+    /// use std::thread;
+    ///
+    /// let before = run_loop::object_count();
+    /// let obj = run_loop::new_object(Cell::new(0));
+    /// let weak = obj.downgrade();
+    ///
+    /// thread::spawn(move || {
+    ///     obj.post(|c| c.set(1));
+    ///     obj.post(|_| run_loop::stop());
+    ///     drop(obj);
+    /// }).join().unwrap();
+    ///
+    /// assert_eq!(weak.strong_count(), 2);
+    /// run_loop::run();
+    /// assert!(weak.upgrade().is_none());
+    /// assert_eq!(run_loop::object_count(), before);
+    /// ```
+    ///
+    /// 循环所在的线程结束时，未执行的消息释放持有的强引用，对象只析构一次：
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::sync::{Arc, Barrier, mpsc};
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::thread;
+    ///
+    /// struct Counted (Arc<AtomicUsize>);
+    /// impl Drop for Counted {
+    ///     fn drop(&mut self) {
+    ///         self.0.fetch_add(1, Ordering::SeqCst);
+    ///     }
+    /// }
+    ///
+    /// for _ in 0..50 {
+    ///     let drops = Arc::new(AtomicUsize::new(0));
+    ///     let barrier = Arc::new(Barrier::new(2));
+    ///     let (tx, rx) = mpsc::channel();
+    ///     let (d, b) = (drops.clone(), barrier.clone());
+    ///     let th = thread::spawn(move || {
+    ///         tx.send(run_loop::new_object(Counted(d))).unwrap();
+    ///         b.wait();
+    ///     });
+    ///     let obj = rx.recv().unwrap();
+    ///     let weak = obj.downgrade();
+    ///     // 不会执行的消息
+    ///     obj.post(|_| unreachable!());
+    ///     barrier.wait();
+    ///     // 和循环所在线程的退出同时释放最后一个句柄
+    ///     drop(obj);
+    ///     th.join().unwrap();
+    ///
+    ///     assert_eq!(drops.load(Ordering::SeqCst), 1);
+    ///     assert!(weak.upgrade().is_none());
+    ///     assert_eq!(weak.strong_count(), 0);
+    /// }
+    /// ```
+    ///
+    /// 投递时为消息增加强引用，和最后一个句柄的释放、循环的退出同时投递时，
+    /// 函数要么在对象释放前执行，要么不执行并在投递返回前释放：
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::sync::Arc;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::mpsc;
+    /// use std::thread;
+    ///
+    /// struct Counted (Arc<AtomicUsize>);
+    /// impl Drop for Counted {
+    ///     fn drop(&mut self) {
+    ///         self.0.fetch_add(1, Ordering::SeqCst);
+    ///     }
+    /// }
+    ///
+    /// for _ in 0..20 {
+    ///     let obj_drops = Arc::new(AtomicUsize::new(0));
+    ///     let msg_drops = Arc::new(AtomicUsize::new(0));
+    ///     let (tx, rx) = mpsc::channel();
+    ///     let d = obj_drops.clone();
+    ///     let th = thread::spawn(move || {
+    ///         tx.send((run_loop::clone_handle(), run_loop::new_object(Counted(d)))).unwrap();
+    ///         run_loop::run();
+    ///     });
+    ///     let (handle, obj) = rx.recv().unwrap();
+    ///     let producers: Vec<_> = (0..4).map(|_| {
+    ///         let obj = obj.clone();
+    ///         let d = msg_drops.clone();
+    ///         thread::spawn(move || for _ in 0..1000 {
+    ///             let c = Counted(d.clone());
+    ///             obj.post(move |_| drop(c));
+    ///         })
+    ///     }).collect();
+    ///     drop(obj);
+    ///     handle.post(run_loop::stop);
+    ///     for p in producers {
+    ///         p.join().unwrap();
+    ///     }
+    ///     th.join().unwrap();
+    ///     // 循环句柄仍然存在，没有函数留在队列中
+    ///     assert_eq!(msg_drops.load(Ordering::SeqCst), 4000);
+    ///     assert_eq!(obj_drops.load(Ordering::SeqCst), 1);
+    /// }
+    /// ```
+    ///
+    /// 函数会在其它线程执行，必须是 `Send`：
+    /// ```compile_fail
+    /// use vnbase::run_loop;
+    /// use std::rc::Rc;
+    ///
+    /// let obj = run_loop::new_object(1);
+    /// let rc = Rc::new(1);
+    /// obj.post(move |_| drop(rc));
+    /// ```
+    #[cfg_attr(feature = "debug-origin", track_caller)]
     pub fn post<F>(&self, msg: F) where F: FnOnce(&T) + 'static + Send {
-        unsafe { ObjH::inc_strong(self.handle); }
-        let ptr = ObjectNodePtr (unsafe { (*self.handle).ptr });
+        unsafe {
+            ObjH::inc_strong(self.handle);
+            post_strong(&self.core, self.handle, self.obj, msg);
+        }
+    }
+
+    /// 投递函数，函数同时获得对象和指向自身的句柄，可以继续向自身投递
+    ///
+    /// 句柄在函数执行期间持有强引用，需要保留时克隆即可。
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::cell::Cell;
+    ///
+    /// struct Counter (Cell<u32>);
+    /// impl Counter {
+    ///     fn step(&self, this: &run_loop::ObjectHandle<Counter>) {
+    ///         self.0.set(self.0.get() + 1);
+    ///         if self.0.get() < 3 {
+    ///             this.post_with_self(Counter::step);
+    ///         }
+    ///         else {
+    ///             run_loop::stop();
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let obj = run_loop::new_object(Counter(Cell::new(0)));
+    /// obj.post_with_self(Counter::step);
+    /// run_loop::run();
+    /// assert_eq!(obj.get_ref().unwrap().0.get(), 3);
+    /// ```
+    pub fn post_with_self<F>(&self, msg: F) where F: FnOnce(&T, &ObjectHandle<T>) + 'static + Send {
+        let this = self.clone();
         self.core.post(move || {
-            let obj = unsafe { &(*ptr.0) };
-            msg(&obj.obj);
-            unsafe { 
-                if let Some(_) = ObjH::dec_strong(obj.handle) {
-                    super::drop_object(ptr.0);
-                }
+            msg(unsafe { &*this.obj }, &this);
+        });
+    }
+
+    /// 投递 f，执行时同时传入对象的弱引用，便于在 f 中继续向自己投递
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::cell::Cell;
+    ///
+    /// fn step(n: &Cell<u32>, me: &run_loop::ObjectWeak<Cell<u32>>) {
+    ///     n.set(n.get() + 1);
+    ///     if n.get() < 5 {
+    ///         let me = me.clone();
+    ///         me.upgrade().unwrap().post_with_weak_self(step);
+    ///     }
+    ///     else {
+    ///         run_loop::stop();
+    ///     }
+    /// }
+    ///
+    /// let obj = run_loop::new_object(Cell::new(0));
+    /// obj.post_with_weak_self(step);
+    /// run_loop::run();
+    /// assert_eq!(obj.get_ref().unwrap().get(), 5);
+    /// ```
+    pub fn post_with_weak_self<F>(&self, msg: F) where F: FnOnce(&T, &ObjectWeak<T>) + 'static + Send {
+        let weak = self.downgrade();
+        self.post(move |obj| msg(obj, &weak));
+    }
+
+    /// 投递 f，并通过 sender 把结果交还给调用者
+    ///
+    /// 对象在执行前被释放时 sender 随消息一起释放，接收端得到 `Canceled`。
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::thread;
+    ///
+    /// let obj = run_loop::new_object(String::from("hello"));
+    /// let handle = run_loop::clone_handle();
+    /// let th = thread::spawn(move || {
+    ///     let (tx, rx) = run_loop::oneshot();
+    ///     obj.post_reply(|s| s.len(), tx);
+    ///     let len = rx.recv();
+    ///     handle.stop();
+    ///     len
+    /// });
+    /// run_loop::run();
+    /// assert_eq!(th.join().unwrap(), Ok(5));
+    /// ```
+    pub fn post_reply<R, F>(&self, f: F, sender: oneshot::Sender<R>) where F: FnOnce(&T) -> R + 'static + Send, R: Send + 'static {
+        self.post(move |obj| {
+            let _ = sender.send(f(obj));
+        });
+    }
+
+    /// 投递 f，执行时距投递已经超过 timeout 则丢弃 f，用于过时就没有意义的请求
+    ///
+    /// 和 `post` 一样在执行前持有对象的强引用。
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::cell::RefCell;
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// let obj = run_loop::new_object(RefCell::new(Vec::new()));
+    /// // 循环被前面的消息占住
+    /// run_loop::clone_handle().post(|| thread::sleep(Duration::from_millis(50)));
+    /// obj.post_timeout(Duration::from_millis(10), |log| log.borrow_mut().push("stale"));
+    /// obj.post_timeout(Duration::from_secs(10), |log| log.borrow_mut().push("fresh"));
+    /// run_loop::clone_handle().post(run_loop::stop);
+    /// run_loop::run();
+    ///
+    /// assert_eq!(*obj.get_ref().unwrap().borrow(), vec!["fresh"]);
+    /// ```
+    pub fn post_timeout<F>(&self, timeout: Duration, f: F) where F: FnOnce(&T) + 'static + Send {
+        let posted = Instant::now();
+        self.post(move |obj| {
+            if posted.elapsed() <= timeout {
+                f(obj);
             }
-        })
+        });
     }
 
     pub fn get_ref(&self) -> Option<&T> {
-        if super::is_own_handle(&self.core) {
-            Some(unsafe { &(*(*self.handle).ptr).obj })
+        self.try_get_ref().ok()
+    }
+
+    /// 在对象所在的线程获得对象引用，失败时返回原因
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::thread;
+    ///
+    /// let obj = run_loop::new_object(100);
+    /// assert_eq!(*obj.try_get_ref().unwrap(), 100);
+    ///
+    /// let other = obj.clone();
+    /// let id = run_loop::current_id();
+    /// thread::spawn(move || {
+    ///     match other.try_get_ref() {
+    ///         Err(run_loop::AccessError::WrongThread { expected_loop_id, current_loop_id }) => {
+    ///             assert_eq!(expected_loop_id, id);
+    ///             assert_eq!(current_loop_id, run_loop::current_id());
+    ///         },
+    ///         Ok(_) => unreachable!(),
+    ///     }
+    /// }).join().unwrap();
+    /// ```
+    pub fn try_get_ref(&self) -> Result<&T, AccessError> {
+        let current = super::current_id();
+        let expected = self.core.core.id;
+        if current == expected {
+            Ok(unsafe { &*self.obj })
         }
         else {
-            None
+            Err(AccessError::WrongThread {
+                expected_loop_id: expected,
+                current_loop_id: current,
+            })
         }
     }
 
+    /// 创建弱引用
+    ///
+    /// ```
+    /// use vnbase::run_loop;
+    ///
+    /// let obj = run_loop::new_object(String::from("hello"));
+    /// let weak = obj.downgrade();
+    /// let strong = weak.upgrade().unwrap();
+    /// assert_eq!(strong.get_ref().unwrap(), "hello");
+    ///
+    /// let cloned = strong.clone();
+    /// drop(obj);
+    /// drop(strong);
+    /// assert!(weak.clone().upgrade().is_some());
+    /// drop(cloned);
+    /// assert!(weak.upgrade().is_none());
+    /// ```
     pub fn downgrade(&self) -> ObjectWeak<T> {
         let mut n = unsafe { (*self.handle).weak.load(atomic::Ordering::Relaxed) };
         loop {
@@ -209,6 +785,486 @@ impl<T> ObjectHandle<T> {
                 return ObjectWeak {
                     core: self.core.clone(),
                     handle: self.handle,
+                    obj: self.obj,
+                }
+            }
+        }
+    }
+}
+
+/// 类型擦除的对象，用于在同一个容器中保存不同类型的对象
+///
+/// 类型只能在对象所在的线程检查，因此向下转换以投递的方式进行。
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop;
+/// use std::any::Any;
+/// use std::sync::mpsc;
+///
+/// let objs: Vec<run_loop::ObjectHandle<Box<Any>>> = vec![
+///     run_loop::new_object(Box::new(10u32) as Box<Any>),
+///     run_loop::new_object(Box::new(String::from("hello")) as Box<Any>),
+/// ];
+///
+/// assert_eq!(objs[0].downcast_ref::<u32>(), Some(&10));
+/// assert!(objs[1].downcast_ref::<u32>().is_none());
+///
+/// let (tx, rx) = mpsc::channel();
+/// for obj in objs.iter() {
+///     let hit = tx.clone();
+///     let miss = tx.clone();
+///     obj.post_downcast(move |s: &String| hit.send(Some(s.clone())).unwrap(),
+///                       move || miss.send(None).unwrap());
+/// }
+/// run_loop::clone_handle().post(run_loop::stop);
+/// run_loop::run();
+///
+/// assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![None, Some(String::from("hello"))]);
+/// ```
+impl<T> ObjectHandle<T> {
+    /// 转换为对象控制块的指针，用于交给 C 代码保存，不改变引用计数
+    ///
+    /// 和 `Arc::into_raw` 一样，指针原样保存在控制块中，不会另外分配。
+    /// 返回的指针不指向对象，只能通过 `from_raw` 还原，
+    /// 每次 `into_raw` 都必须以相同的 `T` 调用恰好一次 `from_raw`，否则句柄泄漏。
+    ///
+    /// # Panics
+    /// 由 `map` 或 `erase` 得到、指向对象内部其它位置的句柄无法还原，调用时 panic。
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::sync::{Arc, Mutex};
+    /// use std::thread;
+    ///
+    /// let hits = Arc::new(Mutex::new(0));
+    /// let obj = run_loop::new_object(hits.clone());
+    /// let raw = obj.clone().into_raw() as usize;
+    /// assert_eq!(obj.strong_count(), 2);
+    /// // 同一个对象的句柄得到同一个指针
+    /// let again = obj.clone().into_raw();
+    /// assert_eq!(again as usize, raw);
+    /// drop(unsafe { run_loop::ObjectHandle::<Arc<Mutex<u32>>>::from_raw(again) });
+    ///
+    /// let handle = run_loop::clone_handle();
+    /// thread::spawn(move || {
+    ///     let obj = unsafe { run_loop::ObjectHandle::<Arc<Mutex<u32>>>::from_raw(raw as *const ()) };
+    ///     obj.post(|hits| *hits.lock().unwrap() += 1);
+    ///     handle.post(run_loop::stop);
+    /// }).join().unwrap();
+    ///
+    /// run_loop::run();
+    /// assert_eq!(*hits.lock().unwrap(), 1);
+    /// assert_eq!(obj.strong_count(), 1);
+    /// ```
+    pub fn into_raw(self) -> *const () {
+        let handle = self.handle;
+        assert!(unsafe { (*handle).obj } == self.obj as *const (),
+                "ObjectHandle::into_raw: handle does not point to the whole object");
+        unsafe { drop(ptr::read(&self.core)); }
+        mem::forget(self);
+        handle as *const ()
+    }
+
+    /// 从 `into_raw` 返回的指针还原句柄
+    ///
+    /// # Safety
+    /// ptr 必须来自 `ObjectHandle::<T>::into_raw`，且没有被还原过。
+    pub unsafe fn from_raw(ptr: *const ()) -> Self {
+        let handle = ptr as *mut ObjH;
+        ObjectHandle {
+            core: (*handle).core.clone(),
+            handle: handle,
+            obj: (*handle).obj as *const T,
+            phantom: PhantomData,
+        }
+    }
+
+    /// 只有一个强引用时，在对象所在的线程把对象移出循环，否则返回原句柄
+    ///
+    /// 对象移出前调用 `LoopObject::detaching`，移出后执行释放回调，但不会调用对象的析构。
+    /// 由 `map` 或 `erase` 得到、不指向整个对象的句柄总是返回原句柄，即使类型和对象相同。
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop;
+    ///
+    /// let before = run_loop::object_count();
+    /// let obj = run_loop::new_object(vec![1, 2, 3]);
+    /// let other = obj.clone();
+    /// let obj = obj.try_unwrap().unwrap_err();
+    ///
+    /// drop(other);
+    /// assert_eq!(obj.try_unwrap().ok(), Some(vec![1, 2, 3]));
+    /// assert_eq!(run_loop::object_count(), before);
+    ///
+    /// struct Node {
+    ///     value: u32,
+    ///     child: Option<Box<Node>>,
+    /// }
+    ///
+    /// let parent = run_loop::new_object(Node { value: 1, child: Some(Box::new(Node { value: 2, child: None })) });
+    /// let child = parent.map(|n| &**n.child.as_ref().unwrap()).unwrap();
+    /// drop(parent);
+    /// let child = child.try_unwrap().err().unwrap();
+    /// assert_eq!(child.get_ref().unwrap().value, 2);
+    /// ```
+    pub fn try_unwrap(self) -> Result<T, Self> {
+        if !super::is_own_handle(&self.core) {
+            return Err(self);
+        }
+        unsafe {
+            let handle = self.handle;
+            let node_ptr = (*handle).ptr.unwrap();
+            if (*node_ptr).obj_type_id() != TypeId::of::<T>() || (*handle).obj != self.obj as *const () {
+                return Err(self);
+            }
+            if (*handle).strong.compare_exchange(1, 0, atomic::Ordering::Acquire, atomic::Ordering::Relaxed).is_err() {
+                return Err(self);
+            }
+            drop(ptr::read(&self.core));
+            mem::forget(self);
+
+            ObjH::release_strong(handle);
+            let mut node = super::unlink_object(node_ptr);
+            node.detach();
+            let node = Box::from_raw(Box::into_raw(node) as *mut ObjectNode<T>);
+            let ObjectNode { obj, .. } = *node;
+            ObjH::notify_dropped(handle);
+            ObjH::dec_weak(handle);
+            Ok(obj)
+        }
+    }
+
+    /// 在对象所在的循环执行 `try_unwrap`，并以结果调用 f，失败时参数为 None
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::sync::mpsc;
+    /// use std::thread;
+    ///
+    /// let obj = run_loop::new_object(String::from("buffer"));
+    /// let other = obj.clone();
+    /// let (tx, rx) = mpsc::channel();
+    /// thread::spawn(move || {
+    ///     let tx2 = tx.clone();
+    ///     other.into_inner_via_post(move |s| tx.send(s).unwrap());
+    ///     obj.into_inner_via_post(move |s| tx2.send(s).unwrap());
+    /// }).join().unwrap();
+    ///
+    /// run_loop::clone_handle().post(run_loop::stop);
+    /// run_loop::run();
+    /// assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![None, Some(String::from("buffer"))]);
+    /// ```
+    pub fn into_inner_via_post<F>(self, f: F) where T: Send, F: FnOnce(Option<T>) + 'static + Send {
+        let core = self.core.clone();
+        core.post(move || f(self.try_unwrap().ok()));
+    }
+}
+
+impl ObjectHandle<Box<Any>> {
+    /// 在对象所在的线程获得指定类型的引用，类型不符或不在所在的线程时返回 None
+    pub fn downcast_ref<U>(&self) -> Option<&U> where U: Any {
+        self.get_ref().and_then(|obj| obj.downcast_ref::<U>())
+    }
+
+    /// 投递函数，在对象所在的线程检查类型，类型相符时调用 f，否则调用 miss
+    pub fn post_downcast<U, F, M>(&self, f: F, miss: M)
+        where U: Any, F: FnOnce(&U) + 'static + Send, M: FnOnce() + 'static + Send {
+        self.post(move |obj| {
+            match obj.downcast_ref::<U>() {
+                Some(obj) => f(obj),
+                None => miss(),
+            }
+        })
+    }
+}
+
+impl<T: ?Sized> ObjectHandle<T> {
+    /// 注册对象释放后执行的回调，回调在对象所在的线程、对象析构之后执行
+    ///
+    /// 可以注册多个回调，按注册的顺序执行。即使最后一个句柄在其它线程释放，
+    /// 回调也在对象所在的线程执行。循环销毁时仍然存在的对象在销毁过程中执行回调，
+    /// 此时回调中不能再调用 `run_loop` 的函数。
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::sync::mpsc;
+    /// use std::thread;
+    ///
+    /// let obj = run_loop::new_object(());
+    /// let (tx, rx) = mpsc::channel();
+    /// let tx2 = tx.clone();
+    /// obj.on_drop(move || tx.send(run_loop::current_id()).unwrap());
+    /// obj.on_drop(move || tx2.send(0).unwrap());
+    /// let dropped = obj.dropped();
+    ///
+    /// // 在其它线程释放最后一个句柄，释放被投递回对象所在的线程
+    /// thread::spawn(move || drop(obj)).join().unwrap();
+    /// assert!(rx.try_recv().is_err());
+    ///
+    /// run_loop::clone_handle().post(run_loop::stop);
+    /// run_loop::run();
+    /// assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![run_loop::current_id(), 0]);
+    /// assert_eq!(dropped.recv(), Ok(()));
+    /// ```
+    pub fn on_drop<F>(&self, f: F) where F: FnOnce() + Send + 'static {
+        unsafe { ObjH::on_drop(self.handle, Box::new(f)); }
+    }
+
+    /// 对象释放后完成，可以阻塞等待也可以作为 `Future` 使用
+    pub fn dropped(&self) -> oneshot::Receiver<()> {
+        let (tx, rx) = oneshot::channel();
+        self.on_drop(move || { let _ = tx.send(()); });
+        rx
+    }
+
+    /// 在对象所在的线程把句柄转换为其它类型，通常用于转换为 trait 对象
+    ///
+    /// 转换后的句柄和原句柄共享引用计数，不需要额外的分配。
+    /// 不在对象所在的线程时返回原句柄。开启 `nightly` 特性后句柄可以像 `Arc` 一样直接转换。
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::thread;
+    /// use std::sync::mpsc;
+    ///
+    /// trait Shape {
+    ///     fn area(&self) -> u32;
+    /// }
+    ///
+    /// struct Square (u32);
+    /// impl Shape for Square {
+    ///     fn area(&self) -> u32 { self.0 * self.0 }
+    /// }
+    ///
+    /// struct Rect (u32, u32);
+    /// impl Shape for Rect {
+    ///     fn area(&self) -> u32 { self.0 * self.1 }
+    /// }
+    ///
+    /// let square = run_loop::new_object(Square(3));
+    /// let shapes: Vec<run_loop::ObjectHandle<Shape>> = vec![
+    ///     square.clone().erase(|s| s as &Shape).ok().unwrap(),
+    ///     run_loop::new_object(Rect(2, 5)).erase(|s| s as &Shape).ok().unwrap(),
+    /// ];
+    /// assert_eq!(square.strong_count(), 2);
+    /// assert!(shapes[0].downgrade().upgrade().is_some());
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let handle = run_loop::clone_handle();
+    /// thread::spawn(move || {
+    ///     for shape in shapes.iter() {
+    ///         let tx = tx.clone();
+    ///         shape.post(move |s| tx.send(s.area()).unwrap());
+    ///     }
+    ///     handle.post(run_loop::stop);
+    /// }).join().unwrap();
+    /// run_loop::run();
+    ///
+    /// assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![9, 10]);
+    /// assert_eq!(square.strong_count(), 1);
+    /// ```
+    pub fn erase<U, F>(self, f: F) -> Result<ObjectHandle<U>, Self>
+        where U: ?Sized + 'static, F: FnOnce(&T) -> &U {
+        let obj = match self.try_get_ref() {
+            Ok(obj) => f(obj) as *const U,
+            Err(_) => return Err(self),
+        };
+        let handle = ObjectHandle {
+            core: unsafe { ptr::read(&self.core) },
+            handle: self.handle,
+            obj: obj,
+            phantom: PhantomData,
+        };
+        mem::forget(self);
+        Ok(handle)
+    }
+
+    /// 在对象所在的线程得到指向对象一部分的句柄，原句柄不变
+    ///
+    /// 得到的句柄和原句柄共享引用计数，持有它时整个对象都不会释放，
+    /// 投递的函数得到的是 f 返回的部分。不在对象所在的线程时返回原因。
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::cell::Cell;
+    /// use std::thread;
+    ///
+    /// struct Big {
+    ///     name: String,
+    ///     hits: Cell<u32>,
+    /// }
+    ///
+    /// let big = run_loop::new_object(Big { name: "big".to_string(), hits: Cell::new(0) });
+    /// let hits = big.map(|b| &b.hits).unwrap();
+    /// assert_eq!(big.strong_count(), 2);
+    /// let weak = big.downgrade();
+    /// drop(big);
+    ///
+    /// let handle = run_loop::clone_handle();
+    /// let other = hits.clone();
+    /// thread::spawn(move || {
+    ///     assert!(other.map(|h| h).is_err());
+    ///     other.post(|h| h.set(h.get() + 1));
+    ///     other.post(|h| h.set(h.get() + 1));
+    ///     handle.post(run_loop::stop);
+    /// }).join().unwrap();
+    /// run_loop::run();
+    /// assert_eq!(hits.get_ref().unwrap().get(), 2);
+    ///
+    /// // 最后一个映射的句柄释放后整个对象才释放
+    /// assert!(weak.upgrade().is_some());
+    /// drop(hits);
+    /// assert!(weak.upgrade().is_none());
+    /// ```
+    pub fn map<U, F>(&self, f: F) -> Result<ObjectHandle<U>, AccessError>
+        where U: ?Sized + 'static, F: FnOnce(&T) -> &U {
+        let obj = f(self.try_get_ref()?) as *const U;
+        unsafe { ObjH::inc_strong(self.handle); }
+        Ok(ObjectHandle {
+            core: self.core.clone(),
+            handle: self.handle,
+            obj: obj,
+            phantom: PhantomData,
+        })
+    }
+}
+
+/// 访问循环内对象失败的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessError {
+    /// 当前线程不是对象所在的循环线程
+    WrongThread {
+        expected_loop_id: u64,
+        current_loop_id: u64,
+    },
+}
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AccessError::WrongThread { expected_loop_id, current_loop_id } => {
+                write!(f, "object belongs to run loop #{}, but current run loop is #{}", expected_loop_id, current_loop_id)
+            },
+        }
+    }
+}
+
+impl error::Error for AccessError {}
+
+/// 销毁节点：调用 `detaching`，释放对象，执行释放回调，最后释放节点持有的弱引用
+pub unsafe fn destroy(mut node: Box<Object>) {
+    node.detach();
+    let handle = node.handle();
+    drop(node);
+    ObjH::notify_dropped(handle);
+    ObjH::dec_weak(handle);
+}
+
+/// 先创建控制块和弱引用，再以弱引用构造对象，见 `run_loop::new_object_cyclic`
+///
+/// 构造期间不借用 list，f 中可以创建其它对象。
+pub fn create_cyclic<T, F>(list: &RefCell<ObjectList>, f: F) -> ObjectHandle<T>
+    where T: 'static, F: FnOnce(&ObjectWeak<T>) -> T {
+    unsafe {
+        let handle = Box::into_raw(Box::new(ObjH {
+            ptr: None,
+            obj: ptr::null(),
+            core: super::clone_handle(),
+            strong: AtomicUsize::new(0),
+            // 一个由构造完成后的强引用共同持有，一个由节点持有，一个由传给 f 的弱引用持有
+            weak: AtomicUsize::new(3),
+            watched: AtomicUsize::new(0),
+            on_drop: Mutex::new(Some(Vec::new())),
+        }));
+        // 先分配节点，弱引用中的对象指针在构造前就是有效的地址
+        let node = Box::into_raw(Box::new(MaybeUninit::<ObjectNode<T>>::uninit())) as *mut ObjectNode<T>;
+        let weak = ObjectWeak {
+            core: (*handle).core.clone(),
+            handle: handle,
+            obj: ptr::addr_of!((*node).obj),
+        };
+
+        let guard = CyclicGuard { handle: handle, node: node };
+        let obj = f(&weak);
+        mem::forget(guard);
+
+        ptr::write(node, ObjectNode {
+            handle: handle,
+            next: None,
+            prev: None,
+            detach: None,
+            obj: obj,
+        });
+        (*handle).ptr = Some(node);
+        (*handle).obj = &(*node).obj as *const T as *const ();
+        list.borrow_mut().link(node);
+        (*handle).strong.store(1, atomic::Ordering::Release);
+
+        ObjectHandle {
+            core: weak.core.clone(),
+            handle: handle,
+            obj: &(*node).obj,
+            phantom: PhantomData,
+        }
+    }
+}
+
+/// f 发生 panic 时释放尚未初始化的节点和控制块
+struct CyclicGuard<T: 'static> {
+    handle: *mut ObjH,
+    node: *mut ObjectNode<T>,
+}
+
+impl<T> Drop for CyclicGuard<T> {
+    fn drop(&mut self) {
+        unsafe {
+            drop(Box::from_raw(self.node as *mut MaybeUninit<ObjectNode<T>>));
+            ObjH::notify_dropped(self.handle);
+            ObjH::dec_weak(self.handle);
+            ObjH::dec_weak(self.handle);
+        }
+    }
+}
+
+/// 投递到对象所在的循环，调用前必须已为此次投递增加强引用计数
+#[cfg_attr(feature = "debug-origin", track_caller)]
+unsafe fn post_strong<T: ?Sized + 'static, F>(core: &super::Handle, handle: *mut ObjH, obj: *const T, msg: F) where F: FnOnce(&T) + 'static + Send {
+    let strong = StrongRef {
+        handle: handle,
+        loop_id: core.id(),
+    };
+    let obj = SendPtr(obj);
+    core.post(move || {
+        msg(unsafe { &*obj.0 });
+        drop(strong);
+    })
+}
+
+/// 消息持有的强引用，消息执行后或者未执行就被丢弃时释放
+///
+/// 循环销毁时丢弃的消息同样释放强引用，弱引用之后不能再升级，等待释放的线程也会被唤醒。
+/// 只在对象所在的循环中释放节点：循环销毁时节点由 `ObjectList` 释放，
+/// 和循环的销毁同时投递的消息由投递者释放，可能在其它线程，此时节点早已释放。
+struct StrongRef {
+    handle: *mut ObjH,
+    loop_id: u64,
+}
+
+unsafe impl Send for StrongRef {}
+
+impl Drop for StrongRef {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some(node) = ObjH::dec_strong(self.handle) {
+                if super::current_id() == self.loop_id {
+                    super::drop_object(node.0);
                 }
             }
         }
@@ -217,43 +1273,107 @@ impl<T> ObjectHandle<T> {
 
 const MAX_REFCOUNT: usize = isize::MAX as usize;
 
-struct ObjH<T: 'static> {
-    ptr: *mut ObjectNode<T>,
+/// 对象释放后执行的回调
+type DropCallback = Box<FnOnce() + Send>;
+
+/// 对象的控制块，不含对象类型，同一个对象不同类型的句柄共享同一个控制块
+pub struct ObjH {
+    /// 对象所在的节点，`ObjectWeak::new` 创建的控制块为 None
+    ptr: Option<*mut Object>,
+    /// 对象本身的地址，用于 `ObjectHandle::from_raw` 还原句柄，`ObjectWeak::new` 创建的控制块为空
+    obj: *const (),
+    /// 对象所在的循环
+    core: super::Handle,
     strong: AtomicUsize,
     weak: AtomicUsize,
+    /// 正在 `wait_any_dropped` 中等待该对象的线程数
+    watched: AtomicUsize,
+    /// 对象释放后执行的回调，对象释放后为 None
+    on_drop: Mutex<Option<Vec<DropCallback>>>,
 }
 
-impl<T> ObjH<T> {
-    unsafe fn inc_strong(ptr: *mut ObjH<T>) {
+/// 强引用计数归零的通知，只在有线程等待时使用
+static DROPPED: (Mutex<()>, Condvar) = (Mutex::new(()), Condvar::new());
+
+impl ObjH {
+    unsafe fn inc_strong(ptr: *mut ObjH) {
         if (*ptr).strong.fetch_add(1, atomic::Ordering::Relaxed) > MAX_REFCOUNT {
             abort();
         }
     }
 
-    unsafe fn dec_strong(ptr: *mut ObjH<T>) -> Option<ObjectNodePtr<T>> {
+    unsafe fn try_inc_strong(ptr: *mut ObjH) -> bool {
+        let mut n = (*ptr).strong.load(atomic::Ordering::Relaxed);
+        loop {
+            if n == 0 {
+                return false;
+            }
+
+            if n > MAX_REFCOUNT {
+                abort();
+            }
+
+            if let Err(old) = (*ptr).strong.compare_exchange_weak(n, n + 1, atomic::Ordering::Relaxed, atomic::Ordering::Relaxed) {
+                n = old;
+            }
+            else {
+                return true;
+            }
+        }
+    }
+
+    unsafe fn dec_strong(ptr: *mut ObjH) -> Option<ObjectPtr> {
         if (*ptr).strong.fetch_sub(1, atomic::Ordering::Release) != 1 {
             return None;
         }
 
-        atomic::fence(atomic::Ordering::Acquire);
+        Some(ObjH::release_strong(ptr))
+    }
 
-        let node_ptr = ObjectNodePtr((*ptr).ptr);
+    /// 强引用计数归零后调用，返回需要销毁的节点
+    unsafe fn release_strong(ptr: *mut ObjH) -> ObjectPtr {
+        atomic::fence(atomic::Ordering::SeqCst);
 
-        if (*ptr).weak.fetch_sub(1, atomic::Ordering::Release) == 1 {
-            atomic::fence(atomic::Ordering::Acquire);
-            Box::from_raw(ptr);
+        // 和 wait_any_dropped 中的 fence 配对，两边至少有一边能看到对方的修改
+        if (*ptr).watched.load(atomic::Ordering::Relaxed) > 0 {
+            let _lck = DROPPED.0.lock().unwrap();
+            DROPPED.1.notify_all();
+        }
+
+        // 节点持有弱引用，控制块不会在这里释放
+        let node_ptr = ObjectPtr((*ptr).ptr.unwrap());
+        ObjH::dec_weak(ptr);
+        node_ptr
+    }
+
+    /// 注册释放回调，对象已经释放时立即调用
+    unsafe fn on_drop(ptr: *mut ObjH, f: DropCallback) {
+        let mut callbacks = (*ptr).on_drop.lock().unwrap();
+        match *callbacks {
+            Some(ref mut v) => v.push(f),
+            None => {
+                drop(callbacks);
+                f();
+            },
         }
+    }
 
-        Some(node_ptr)
+    unsafe fn notify_dropped(ptr: *mut ObjH) {
+        let callbacks = (*ptr).on_drop.lock().unwrap().take();
+        if let Some(v) = callbacks {
+            for f in v {
+                f();
+            }
+        }
     }
 
-    unsafe fn inc_weak(ptr: *mut ObjH<T>) {
+    unsafe fn inc_weak(ptr: *mut ObjH) {
         if (*ptr).weak.fetch_add(1, atomic::Ordering::Relaxed) > MAX_REFCOUNT {
             abort();
         }
     }
 
-    unsafe fn dec_weak(ptr: *mut ObjH<T>) {
+    unsafe fn dec_weak(ptr: *mut ObjH) {
         if (*ptr).weak.fetch_sub(1, atomic::Ordering::Release) == 1 {
             atomic::fence(atomic::Ordering::Acquire);
             Box::from_raw(ptr);
@@ -261,9 +1381,13 @@ impl<T> ObjH<T> {
     }
 }
 
-struct ObjectNodePtr<T: 'static> (*mut ObjectNode<T>);
+struct ObjectPtr (*mut Object);
+
+unsafe impl Send for ObjectPtr {}
 
-unsafe impl<T> Send for ObjectNodePtr<T> {}
+struct SendPtr<T: ?Sized> (*const T);
+
+unsafe impl<T: ?Sized> Send for SendPtr<T> {}
 
 /// 循环内对象句柄的弱引用，部分特征和std::sync::Weak类似
 /// 
@@ -289,63 +1413,215 @@ unsafe impl<T> Send for ObjectNodePtr<T> {}
 /// run_loop::run();
 /// th.join().unwrap();
 /// ```
-pub struct ObjectWeak<T: 'static> {
+pub struct ObjectWeak<T: ?Sized + 'static> {
     core: super::Handle,
-    handle: *mut ObjH<T>,
+    handle: *mut ObjH,
+    obj: *const T,
 }
 
-unsafe impl<T> Send for ObjectWeak<T> {}
-unsafe impl<T> Sync for ObjectWeak<T> {}
+// 理由同 `ObjectHandle`，弱引用只能通过 `upgrade` 或 `post` 访问对象
+unsafe impl<T: ?Sized> Send for ObjectWeak<T> {}
+unsafe impl<T: ?Sized> Sync for ObjectWeak<T> {}
+
+#[cfg(feature = "nightly")]
+impl<T, U> CoerceUnsized<ObjectWeak<U>> for ObjectWeak<T> where T: ?Sized + Unsize<U>, U: ?Sized {}
 
-impl<T> Clone for ObjectWeak<T> {
+impl<T: ?Sized> Clone for ObjectWeak<T> {
     fn clone(&self) -> Self {
         unsafe { ObjH::inc_weak(self.handle); }
         ObjectWeak {
             core: self.core.clone(),
             handle: self.handle,
+            obj: self.obj,
         }
     }
 }
 
-impl<T> Drop for ObjectWeak<T> {
+impl<T: ?Sized> Drop for ObjectWeak<T> {
     fn drop(&mut self) {
         unsafe { ObjH::dec_weak(self.handle); }
     }
 }
 
+impl<T: ?Sized> PartialEq for ObjectWeak<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr_eq(other)
+    }
+}
+
+impl<T: ?Sized> Eq for ObjectWeak<T> {}
+
+impl<T: ?Sized> Hash for ObjectWeak<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.handle.hash(state)
+    }
+}
+
 impl<T> ObjectWeak<T> {
     pub fn new() -> Self {
         ObjectWeak {
             core: super::clone_handle(),
             handle: Box::into_raw(Box::new(ObjH {
-                ptr: ptr::null_mut(),
+                ptr: None,
+                obj: ptr::null(),
+                core: super::clone_handle(),
                 strong: AtomicUsize::new(0),
                 weak: AtomicUsize::new(1),
+                watched: AtomicUsize::new(0),
+                on_drop: Mutex::new(None),
             })),
+            obj: ptr::null(),
         }
     }
+}
 
-    pub fn upgrade(&self) -> Option<ObjectHandle<T>> {
-        let mut n = unsafe { (*self.handle).strong.load(atomic::Ordering::Relaxed) };
-        loop {
-            if n == 0 {
-                return None;
-            }
+impl<T> Default for ObjectWeak<T> {
+    fn default() -> Self {
+        ObjectWeak::new()
+    }
+}
 
-            if n > MAX_REFCOUNT {
-                abort();
+impl<T: ?Sized> ObjectWeak<T> {
+    /// 判断两个弱引用是否指向同一个对象
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        self.handle == other.handle
+    }
+
+    /// 判断弱引用和句柄是否指向同一个对象
+    pub fn ptr_eq_handle(&self, other: &ObjectHandle<T>) -> bool {
+        self.handle == other.handle
+    }
+
+    /// 强引用计数，对象已释放时为 0
+    pub fn strong_count(&self) -> usize {
+        unsafe { (*self.handle).strong.load(atomic::Ordering::Relaxed) }
+    }
+
+    /// 弱引用计数，和 `std::sync::Weak` 一样，对象已释放时为 0
+    pub fn weak_count(&self) -> usize {
+        unsafe {
+            let weak = (*self.handle).weak.load(atomic::Ordering::Relaxed);
+            if (*self.handle).strong.load(atomic::Ordering::Relaxed) == 0 {
+                0
             }
+            else {
+                weak.saturating_sub(2)
+            }
+        }
+    }
 
-            if let Err(old) = unsafe { (*self.handle).strong.compare_exchange_weak(n, n + 1, atomic::Ordering::Relaxed, atomic::Ordering::Relaxed) } {
-                n = old;
+    pub fn upgrade(&self) -> Option<ObjectHandle<T>> {
+        if unsafe { ObjH::try_inc_strong(self.handle) } {
+            Some(ObjectHandle {
+                core: self.core.clone(),
+                handle: self.handle,
+                obj: self.obj,
+                phantom: PhantomData,
+            })
+        }
+        else {
+            None
+        }
+    }
+
+    /// 注册对象释放后执行的回调，对象已经释放时在当前线程立即执行，见 `ObjectHandle::on_drop`
+    ///
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::sync::Arc;
+    /// use std::sync::atomic::{AtomicBool, Ordering};
+    ///
+    /// let weak = run_loop::new_object(()).downgrade();
+    /// let fired = Arc::new(AtomicBool::new(false));
+    /// let f = fired.clone();
+    /// weak.on_drop(move || f.store(true, Ordering::SeqCst));
+    /// assert!(fired.load(Ordering::SeqCst));
+    /// assert_eq!(weak.dropped().recv(), Ok(()));
+    /// ```
+    pub fn on_drop<F>(&self, f: F) where F: FnOnce() + Send + 'static {
+        unsafe { ObjH::on_drop(self.handle, Box::new(f)); }
+    }
+
+    /// 对象释放后完成，见 `ObjectHandle::dropped`
+    pub fn dropped(&self) -> oneshot::Receiver<()> {
+        let (tx, rx) = oneshot::channel();
+        self.on_drop(move || { let _ = tx.send(()); });
+        rx
+    }
+
+    /// 对象仍然存在时向其投递函数，返回是否投递成功
+    ///
+    /// 和 `upgrade` 后再 `post` 不同，不会产生临时的 `ObjectHandle`，
+    /// 因此不会在当前线程触发对象的释放投递。对象已经释放时函数不会执行。
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::thread;
+    /// use std::time::Duration;
+    /// use std::sync::Arc;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    ///
+    /// let obj = run_loop::new_object(());
+    /// let weak = obj.downgrade();
+    /// let ran = Arc::new(AtomicUsize::new(0));
+    ///
+    /// let handle = run_loop::clone_handle();
+    /// let counter = ran.clone();
+    /// let th = thread::spawn(move || {
+    ///     let mut delivered = 0;
+    ///     loop {
+    ///         let counter = counter.clone();
+    ///         if !weak.post(move |_| { counter.fetch_add(1, Ordering::SeqCst); }) {
+    ///             break;
+    ///         }
+    ///         delivered += 1;
+    ///     }
+    ///     handle.post(run_loop::stop);
+    ///     delivered
+    /// });
+    ///
+    /// let _timer = run_loop::new_timer()
+    ///     .with_callback_once(move || drop(obj))
+    ///     .and_start(Duration::from_millis(10));
+    /// run_loop::run();
+    ///
+    /// assert_eq!(th.join().unwrap(), ran.load(Ordering::SeqCst));
+    /// ```
+    #[cfg_attr(feature = "debug-origin", track_caller)]
+    pub fn post<F>(&self, msg: F) -> bool where F: FnOnce(&T) + 'static + Send {
+        unsafe {
+            if ObjH::try_inc_strong(self.handle) {
+                post_strong(&self.core, self.handle, self.obj, msg);
+                true
             }
             else {
-                return Some(ObjectHandle {
-                    core: self.core.clone(),
-                    handle: self.handle,
-                    phantom: PhantomData,
-                })
+                false
             }
         }
     }
-}
\ No newline at end of file
+}
+
+/// 阻塞直到其中一个弱引用无法再升级，返回它的序号
+#[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+pub fn wait_any_dropped<T: ?Sized>(weaks: &[ObjectWeak<T>]) -> usize {
+    assert!(!weaks.is_empty(), "wait_any_dropped on empty slice");
+    for weak in weaks {
+        unsafe { (*weak.handle).watched.fetch_add(1, atomic::Ordering::Relaxed); }
+    }
+    atomic::fence(atomic::Ordering::SeqCst);
+
+    let mut lck = DROPPED.0.lock().unwrap();
+    let index = loop {
+        if let Some(index) = weaks.iter().position(|weak| weak.strong_count() == 0) {
+            break index;
+        }
+        lck = DROPPED.1.wait(lck).unwrap();
+    };
+    drop(lck);
+
+    for weak in weaks {
+        unsafe { (*weak.handle).watched.fetch_sub(1, atomic::Ordering::Relaxed); }
+    }
+    index
+}
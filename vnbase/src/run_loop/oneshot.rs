@@ -0,0 +1,181 @@
+//! 单次传值通道，用于把循环中的结果交还给调用者
+use std::sync::{Arc, Mutex, Condvar};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+use std::fmt;
+use std::error;
+
+use super::Handle;
+
+/// 发送端在发送前被释放
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Canceled;
+
+impl fmt::Display for Canceled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "oneshot canceled")
+    }
+}
+
+impl error::Error for Canceled {}
+
+struct Inner<T> {
+    state: Mutex<State<T>>,
+    cond: Condvar,
+}
+
+/// `Receiver::on_ready` 的回调
+type ReadyCallback<T> = Box<FnOnce(Result<T, Canceled>) + Send>;
+
+struct State<T> {
+    value: Option<T>,
+    closed: bool,
+    waker: Option<Waker>,
+    /// `Receiver::on_ready` 设置的回调，接收端已被消耗
+    ready: Option<ReadyCallback<T>>,
+}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Inner {
+        state: Mutex::new(State {
+            value: None,
+            closed: false,
+            waker: None,
+            ready: None,
+        }),
+        cond: Condvar::new(),
+    });
+    (Sender { inner: Some(inner.clone()) }, Receiver { inner: inner })
+}
+
+/// 发送端
+pub struct Sender<T> {
+    inner: Option<Arc<Inner<T>>>,
+}
+
+impl<T> Sender<T> {
+    /// 发送值，接收端已释放时返回原值
+    pub fn send(mut self, t: T) -> Result<(), T> {
+        let inner = self.inner.take().unwrap();
+        let mut state = inner.state.lock().unwrap();
+        state.closed = true;
+        if let Some(f) = state.ready.take() {
+            drop(state);
+            f(Ok(t));
+            return Ok(());
+        }
+        if Arc::strong_count(&inner) == 1 {
+            return Err(t);
+        }
+        state.value = Some(t);
+        state.closed = true;
+        wake(&inner, &mut state);
+        Ok(())
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if let Some(inner) = self.inner.take() {
+            let mut state = inner.state.lock().unwrap();
+            state.closed = true;
+            if let Some(f) = state.ready.take() {
+                drop(state);
+                f(Err(Canceled));
+                return;
+            }
+            wake(&inner, &mut state);
+        }
+    }
+}
+
+fn wake<T>(inner: &Inner<T>, state: &mut State<T>) {
+    inner.cond.notify_all();
+    if let Some(waker) = state.waker.take() {
+        waker.wake();
+    }
+}
+
+/// 接收端，可以阻塞等待，也可以作为 `Future` 使用
+pub struct Receiver<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// 阻塞等待直到收到值，发送端未发送就被释放时返回 `Canceled`
+    #[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+    pub fn recv(self) -> Result<T, Canceled> {
+        let mut state = self.inner.state.lock().unwrap();
+        while !state.closed {
+            state = self.inner.cond.wait(state).unwrap();
+        }
+        state.value.take().ok_or(Canceled)
+    }
+
+    /// 不阻塞地尝试接收，值尚未到达时返回 `Ok(None)`
+    pub fn try_recv(&mut self) -> Result<Option<T>, Canceled> {
+        let mut state = self.inner.state.lock().unwrap();
+        match state.value.take() {
+            Some(t) => Ok(Some(t)),
+            None if state.closed => Err(Canceled),
+            None => Ok(None),
+        }
+    }
+
+    /// 值到达（或发送端被释放）时把 f 投递到 handle 对应的循环执行
+    ///
+    /// 已经到达时立即投递。f 在循环所在的线程执行，可以访问该线程的循环。
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::sync::mpsc;
+    /// use std::thread;
+    ///
+    /// let (tx, rx) = run_loop::oneshot::<u32>();
+    /// let (canceled_tx, canceled_rx) = run_loop::oneshot::<u32>();
+    /// let (result_tx, results) = mpsc::channel();
+    /// let r = result_tx.clone();
+    /// rx.on_ready(&run_loop::clone_handle(), move |v| r.send(v).unwrap());
+    /// canceled_rx.on_ready(&run_loop::clone_handle(), move |v| {
+    ///     result_tx.send(v).unwrap();
+    ///     run_loop::stop();
+    /// });
+    ///
+    /// thread::spawn(move || {
+    ///     tx.send(7).unwrap();
+    ///     drop(canceled_tx);
+    /// }).join().unwrap();
+    /// run_loop::run();
+    ///
+    /// assert_eq!(results.try_iter().collect::<Vec<_>>(), vec![Ok(7), Err(run_loop::oneshot::Canceled)]);
+    /// ```
+    pub fn on_ready<F>(self, handle: &Handle, f: F) where F: FnOnce(Result<T, Canceled>) + Send + 'static, T: Send + 'static {
+        let handle = handle.clone();
+        let mut state = self.inner.state.lock().unwrap();
+        if state.closed {
+            let r = state.value.take().ok_or(Canceled);
+            drop(state);
+            handle.post(move || f(r));
+        }
+        else {
+            state.ready = Some(Box::new(move |r| handle.post(move || f(r))));
+        }
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, Canceled>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut state = self.inner.state.lock().unwrap();
+        if state.closed {
+            Poll::Ready(state.value.take().ok_or(Canceled))
+        }
+        else {
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! 投递消息、设置定时器回调的源码位置，开启 `debug-origin` 特性时记录，见 `run_loop::current_origin`
+//!
+//! 没有开启时 `Origin` 是空类型，记录和切换都不产生代码。
+#[cfg(feature = "debug-origin")]
+use std::cell::Cell;
+#[cfg(feature = "debug-origin")]
+use std::panic::Location;
+
+#[cfg(feature = "debug-origin")]
+thread_local! {
+    /// 正在执行的回调的位置
+    static CURRENT: Cell<Option<&'static Location<'static>>> = const { Cell::new(None) };
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Origin {
+    #[cfg(feature = "debug-origin")]
+    loc: Option<&'static Location<'static>>,
+}
+
+impl Origin {
+    /// 调用者的位置，沿着标记了 `track_caller` 的函数向外找
+    #[cfg_attr(feature = "debug-origin", track_caller)]
+    #[inline]
+    pub fn caller() -> Origin {
+        Origin {
+            #[cfg(feature = "debug-origin")]
+            loc: Some(Location::caller()),
+        }
+    }
+
+    /// 作为正在执行的回调的位置，由外层的 `Scope` 恢复
+    #[inline]
+    pub fn enter(self) {
+        #[cfg(feature = "debug-origin")]
+        CURRENT.with(|c| c.set(self.loc));
+    }
+
+    #[cfg(feature = "debug-origin")]
+    pub fn location(self) -> Option<&'static Location<'static>> {
+        self.loc
+    }
+}
+
+/// 执行一个回调期间的位置，离开时（包括 panic 时）恢复外层回调的位置
+///
+/// 回调开始前清除，没有记录位置的回调不会沿用上一个回调的位置。
+pub struct Scope {
+    #[cfg(feature = "debug-origin")]
+    outer: Option<&'static Location<'static>>,
+}
+
+impl Scope {
+    #[inline]
+    pub fn new() -> Scope {
+        Scope {
+            #[cfg(feature = "debug-origin")]
+            outer: CURRENT.with(|c| c.replace(None)),
+        }
+    }
+}
+
+#[cfg(feature = "debug-origin")]
+impl Drop for Scope {
+    fn drop(&mut self) {
+        CURRENT.with(|c| c.set(self.outer));
+    }
+}
+
+#[cfg(feature = "debug-origin")]
+pub fn current() -> Option<&'static Location<'static>> {
+    CURRENT.with(|c| c.get())
+}
@@ -0,0 +1,110 @@
+//! 在任意线程准备、在目标循环上启动的定时器，见 `Handle::new_timer`
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::{Handle, ObjectHandle, Timer};
+
+/// 尚未启动的定时器，`arm` 后在目标循环上创建并启动
+pub struct PendingTimer {
+    handle: Handle,
+    callback: Option<Callback>,
+    priority: i32,
+}
+
+enum Callback {
+    Repeat(Box<FnMut() + Send>),
+    Once(Box<FnOnce() + Send>),
+}
+
+impl PendingTimer {
+    pub fn new(handle: Handle) -> PendingTimer {
+        PendingTimer {
+            handle: handle,
+            callback: None,
+            priority: 0,
+        }
+    }
+
+    /// 见 `Timer::with_callback`，回调在目标循环上执行
+    pub fn with_callback<T>(mut self, cb: T) -> Self where T: FnMut() + Send + 'static {
+        self.callback = Some(Callback::Repeat(Box::new(cb)));
+        self
+    }
+
+    pub fn with_callback_once<T>(mut self, cb: T) -> Self where T: FnOnce() + Send + 'static {
+        self.callback = Some(Callback::Once(Box::new(cb)));
+        self
+    }
+
+    /// 见 `Timer::with_priority`
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// 投递到目标循环，在那里创建定时器并在 time 之后触发
+    ///
+    /// 从投递执行时开始计时。目标循环已经结束时定时器不会创建，返回的 `TimerHandle` 上的操作都被忽略。
+    pub fn arm(self, time: Duration) -> TimerHandle {
+        let PendingTimer { handle, callback, priority } = self;
+        let slot = Arc::new(Mutex::new(None));
+        let s = slot.clone();
+        handle.post(move || {
+            let timer = Timer::new().with_priority(priority);
+            match callback {
+                Some(Callback::Repeat(cb)) => timer.set_callback_boxed(cb),
+                Some(Callback::Once(cb)) => timer.set_callback_once(cb),
+                None => {},
+            }
+            timer.start(time);
+            *s.lock().unwrap() = Some(super::new_object(timer));
+        });
+        TimerHandle {
+            handle: handle,
+            timer: slot,
+        }
+    }
+}
+
+/// 可以在任意线程操作的定时器，操作投递到定时器所在的循环执行
+///
+/// 操作和 `arm` 投递到同一个循环，按调用的顺序执行。释放所有 `TimerHandle` 不会取消定时器。
+#[derive(Clone)]
+pub struct TimerHandle {
+    handle: Handle,
+    /// 只在目标循环上访问
+    timer: Arc<Mutex<Option<ObjectHandle<Timer>>>>,
+}
+
+impl TimerHandle {
+    /// 见 `Timer::start`
+    pub fn start(&self, time: Duration) {
+        self.with_timer(move |t| t.start(time));
+    }
+
+    /// 见 `Timer::cancel`
+    pub fn cancel(&self) {
+        self.with_timer(|t| t.cancel());
+    }
+
+    /// 在目标循环上查询定时器是否已经启动，结果传给 f
+    ///
+    /// 目标循环已经结束时 f 不会执行。
+    pub fn request_active<F>(&self, f: F) where F: FnOnce(bool) + Send + 'static {
+        self.with_timer(move |t| f(t.is_active()));
+    }
+
+    /// 定时器所在的循环
+    pub fn loop_handle(&self) -> &Handle {
+        &self.handle
+    }
+
+    fn with_timer<F>(&self, f: F) where F: FnOnce(&Timer) + Send + 'static {
+        let timer = self.timer.clone();
+        self.handle.post(move || {
+            if let Some(t) = timer.lock().unwrap().as_ref().and_then(|t| t.get_ref()) {
+                f(t);
+            }
+        });
+    }
+}
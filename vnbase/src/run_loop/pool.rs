@@ -0,0 +1,229 @@
+//! 运行在各自线程上的一组循环，按轮转或者按键分派工作，见 `run_loop::pool`
+//!
+//! `post_stealable` 的工作放在池共享的队列中，由先空闲下来的循环取走。
+//! 循环在即将等待时取一个执行，并在等待前设置空闲标记；投递者放入工作后唤醒一个有空闲标记的循环。
+use std::collections::VecDeque;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{self, AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use super::{Handle, LoopGroup};
+
+/// 池中的循环 panic 时的处理方式
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop::{LoopPool, PanicPolicy};
+/// use std::sync::mpsc;
+///
+/// let pool = LoopPool::new(2, PanicPolicy::Restart);
+/// let (tx, rx) = mpsc::channel();
+/// pool.handles()[0].post(|| panic!("member failed"));
+/// pool.handles()[0].post(move || tx.send(()).unwrap());
+/// assert_eq!(rx.recv(), Ok(()));
+/// assert_eq!(pool.panic_count(), 1);
+/// assert!(pool.shutdown().is_ok());
+///
+/// let pool = LoopPool::new(2, PanicPolicy::Report);
+/// pool.handles()[1].post(|| panic!("member failed"));
+/// assert!(pool.shutdown().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// 线程随 panic 结束，之后投递到该循环的函数被丢弃，`shutdown` 返回第一个 panic
+    Report,
+    /// 在同一个线程上继续运行同一个循环，句柄保持有效，panic 的消息之后的消息照常执行
+    Restart,
+}
+
+/// 运行在各自线程上的一组循环
+///
+/// 池中的循环和其它循环一样，可以用 `new_object_on` 在上面创建对象。
+/// 释放时和 `shutdown` 一样关闭所有循环，但忽略 panic。
+pub struct LoopPool {
+    handles: Vec<Handle>,
+    group: LoopGroup,
+    next: AtomicUsize,
+    panics: Arc<AtomicUsize>,
+    injector: Arc<Injector>,
+}
+
+/// 可以被任意循环取走的工作
+struct Injector {
+    jobs: Mutex<VecDeque<Box<FnOnce() + Send>>>,
+    /// 各循环是否已经或者即将进入等待
+    idle: Vec<AtomicBool>,
+}
+
+impl Injector {
+    fn pop(&self) -> Option<Box<FnOnce() + Send>> {
+        self.jobs.lock().unwrap().pop_front()
+    }
+
+    /// 池中第 i 个循环即将等待时调用，取走一个工作执行，返回是否执行了
+    fn steal(&self, i: usize) -> bool {
+        let job = match self.pop() {
+            Some(job) => job,
+            None => {
+                self.idle[i].store(true, Ordering::SeqCst);
+                // 和 `post_stealable` 配对：要么这里看到新的工作，要么投递者看到空闲标记
+                atomic::fence(Ordering::SeqCst);
+                match self.pop() {
+                    Some(job) => job,
+                    None => return false,
+                }
+            },
+        };
+        self.idle[i].store(false, Ordering::Relaxed);
+        job();
+        true
+    }
+}
+
+impl LoopPool {
+    /// 启动 n 个循环线程，线程名为 `vnbase-pool-<序号>`
+    pub fn new(n: usize, policy: PanicPolicy) -> LoopPool {
+        assert!(n > 0, "loop pool needs at least one loop");
+        let panics = Arc::new(AtomicUsize::new(0));
+        let injector = Arc::new(Injector {
+            jobs: Mutex::new(VecDeque::new()),
+            idle: (0..n).map(|_| AtomicBool::new(false)).collect(),
+        });
+        let mut group = LoopGroup::new();
+        let handles = (0..n).map(|i| {
+            let (tx, rx) = mpsc::channel();
+            let panics = panics.clone();
+            let injector = injector.clone();
+            let thread = thread::Builder::new()
+                .name(format!("vnbase-pool-{}", i))
+                .spawn(move || {
+                    tx.send(super::clone_handle()).unwrap();
+                    drop(tx);
+                    super::set_idle_hook(move || injector.steal(i));
+                    run_member(policy, &panics);
+                })
+                .expect("failed to spawn pool thread");
+            let handle = rx.recv().unwrap();
+            group.add(handle.clone(), thread);
+            handle
+        }).collect();
+        LoopPool {
+            handles: handles,
+            group: group,
+            next: AtomicUsize::new(0),
+            panics: panics,
+            injector: injector,
+        }
+    }
+
+    pub fn handles(&self) -> &[Handle] {
+        &self.handles
+    }
+
+    /// 依次投递到下一个循环
+    pub fn post<F>(&self, f: F) where F: FnOnce() + Send + 'static {
+        let i = self.next.fetch_add(1, Ordering::Relaxed) % self.handles.len();
+        self.handles[i].post(f);
+    }
+
+    /// 投递到 key 对应的循环，同一个键总是投递到同一个循环，按投递的顺序执行
+    pub fn post_keyed<K, F>(&self, key: &K, f: F) where K: Hash + ?Sized, F: FnOnce() + Send + 'static {
+        self.handle_for(key).post(f);
+    }
+
+    /// 放入池共享的队列，由先空闲下来的循环取走执行
+    ///
+    /// 循环只在没有自己的消息和到期的定时器时取走，每次一个，不影响 `post` 和 `post_keyed` 的顺序。
+    /// 适合互不相关、耗时不均的工作。
+    ///
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::sync::mpsc;
+    /// use std::thread;
+    ///
+    /// let pool = run_loop::pool(4);
+    /// // 第一个循环被自己的消息占住
+    /// let (release, blocked) = mpsc::channel::<()>();
+    /// pool.handles()[0].post(move || blocked.recv().unwrap());
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// for i in 0..16 {
+    ///     let tx = tx.clone();
+    ///     pool.post_stealable(move || tx.send((i, thread::current().name().unwrap().to_string())).unwrap());
+    /// }
+    /// // 其它循环取走了全部工作
+    /// let mut done: Vec<_> = rx.iter().take(16).collect();
+    /// assert!(done.iter().all(|&(_, ref name)| name != "vnbase-pool-0"));
+    /// done.sort();
+    /// assert!(done.iter().map(|&(i, _)| i).eq(0..16));
+    ///
+    /// release.send(()).unwrap();
+    /// pool.shutdown().unwrap();
+    /// ```
+    pub fn post_stealable<F>(&self, f: F) where F: FnOnce() + Send + 'static {
+        self.injector.jobs.lock().unwrap().push_back(Box::new(f));
+        atomic::fence(Ordering::SeqCst);
+        // 唤醒一个空闲的循环；都在忙时由先空闲下来的取走
+        let n = self.handles.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        for i in (0..n).map(|k| (start + k) % n) {
+            if self.injector.idle[i].compare_exchange(true, false, Ordering::SeqCst, Ordering::Relaxed).is_ok() {
+                self.handles[i].post(|| {});
+                return;
+            }
+        }
+    }
+
+    /// key 对应的循环，在进程内固定不变
+    pub fn handle_for<K>(&self, key: &K) -> &Handle where K: Hash + ?Sized {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.handles[(hasher.finish() % self.handles.len() as u64) as usize]
+    }
+
+    /// 已经发生的 panic 次数，包括 `PanicPolicy::Restart` 恢复的
+    pub fn panic_count(&self) -> usize {
+        self.panics.load(Ordering::SeqCst)
+    }
+
+    /// 同时关闭所有循环，已经投递的函数和共享队列中的工作执行完后等待全部线程结束
+    ///
+    /// 使用 `PanicPolicy::Report` 时有线程 panic 则返回第一个 panic。
+    pub fn shutdown(mut self) -> thread::Result<()> {
+        self.close()
+    }
+
+    fn close(&mut self) -> thread::Result<()> {
+        for (i, handle) in self.handles.iter().enumerate() {
+            let injector = self.injector.clone();
+            handle.post(move || while injector.steal(i) {});
+        }
+        mem::take(&mut self.group).shutdown_all()
+    }
+}
+
+impl Drop for LoopPool {
+    fn drop(&mut self) {
+        let _ = self.close();
+    }
+}
+
+/// 在池的线程上运行循环
+fn run_member(policy: PanicPolicy, panics: &AtomicUsize) {
+    loop {
+        match panic::catch_unwind(AssertUnwindSafe(super::run)) {
+            Ok(()) => return,
+            Err(e) => {
+                panics.fetch_add(1, Ordering::SeqCst);
+                if policy == PanicPolicy::Report {
+                    panic::resume_unwind(e);
+                }
+            },
+        }
+    }
+}
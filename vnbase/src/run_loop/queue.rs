@@ -0,0 +1,452 @@
+//! 多生产者单消费者的无锁消息队列
+//!
+//! 消息按顺序写入块中的槽。生产者只以 CAS 推进尾部的位置，取得块中最后一个位置的生产者负责接上下一个块；
+//! 只有循环所在的线程消费，消息全部取出的块留作下一个块重用。结构参考 crossbeam 的 list channel。
+use std::alloc::{self, Layout};
+use std::cell::{Cell, UnsafeCell};
+use std::hint;
+use std::mem::{self, MaybeUninit};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::thread;
+
+/// 每个块占用的位置数，最后一个位置不对应槽，表示正在接上下一个块
+const LAP: usize = 64;
+/// 每个块的槽数
+const BLOCK_CAP: usize = LAP - 1;
+/// 槽的大小，放不下的消息单独装箱后把指针放入槽中
+const SLOT_SIZE: usize = 64;
+
+// 捕获几个指针的闭包直接放在槽中，装箱后的指针总能放下
+const _: () = assert!(mem::size_of::<Inline<[usize; 8]>>() <= SLOT_SIZE);
+const _: () = assert!(mem::size_of::<Boxed<[u8; 1024]>>() <= SLOT_SIZE);
+const _: () = assert!(mem::align_of::<Boxed<[u8; 1024]>>() <= mem::align_of::<Storage>());
+
+pub trait Action : Send {
+    /// 执行消息的函数，见 `run`
+    fn runner(&self) -> unsafe fn(*mut ());
+}
+
+/// 把 act 移出所在的内存后执行，之后不能再访问或释放 act
+///
+/// 执行期间不保留指向槽的引用，回调中嵌套的 `pop` 可以回收 act 所在的块。
+pub unsafe fn run(act: *mut Action) {
+    let runner = (*act).runner();
+    runner(act as *mut ())
+}
+
+/// 直接放在槽中的消息
+struct Inline<T> (T);
+
+impl<T> Action for Inline<T> where T: FnOnce() + Send {
+    fn runner(&self) -> unsafe fn(*mut ()) {
+        run_inline::<T>
+    }
+}
+
+unsafe fn run_inline<T>(p: *mut ()) where T: FnOnce() {
+    let f = ptr::read(p as *mut Inline<T>).0;
+    f()
+}
+
+/// 槽中放不下的消息
+struct Boxed<T> (Box<T>);
+
+impl<T> Action for Boxed<T> where T: FnOnce() + Send {
+    fn runner(&self) -> unsafe fn(*mut ()) {
+        run_boxed::<T>
+    }
+}
+
+unsafe fn run_boxed<T>(p: *mut ()) where T: FnOnce() {
+    let f = *ptr::read(p as *mut Boxed<T>).0;
+    f()
+}
+
+#[repr(align(16))]
+struct Storage (MaybeUninit<[u8; SLOT_SIZE]>);
+
+struct Slot {
+    storage: UnsafeCell<Storage>,
+    act: UnsafeCell<MaybeUninit<*mut Action>>,
+    /// 消息已经写入
+    ready: AtomicBool,
+}
+
+/// 全部以零初始化，槽的 ready 为 false，next 为空
+struct Block {
+    next: AtomicPtr<Block>,
+    slots: [Slot; BLOCK_CAP],
+}
+
+impl Block {
+    fn new() -> Box<Block> {
+        unsafe {
+            let layout = Layout::new::<Block>();
+            let p = alloc::alloc_zeroed(layout) as *mut Block;
+            if p.is_null() {
+                alloc::handle_alloc_error(layout);
+            }
+            Box::from_raw(p)
+        }
+    }
+}
+
+#[repr(align(64))]
+struct Tail {
+    index: AtomicUsize,
+    block: AtomicPtr<Block>,
+    /// 曾经同时排队的最多消息数，和 index 在同一缓存行，投递时不多碰一行
+    high: AtomicUsize,
+}
+
+/// 只由循环所在的线程访问
+#[repr(align(64))]
+struct Head {
+    index: AtomicUsize,
+    block: Cell<*mut Block>,
+    /// 最后一条消息已经取出的块，在下一次取出时才回收，此时那条消息已经移出槽
+    retired: Cell<*mut Block>,
+}
+
+/// 消息直接放在槽中或者装箱，执行和原地释放两条路径都需要正确处理
+///
+/// 可以用 `cargo +nightly miri test --doc queue` 检查。
+///
+/// ```
+/// use vnbase::run_loop;
+/// use std::sync::Arc;
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::sync::mpsc;
+/// use std::thread;
+///
+/// struct Counted (Arc<AtomicUsize>);
+/// impl Drop for Counted {
+///     fn drop(&mut self) {
+///         self.0.fetch_add(1, Ordering::SeqCst);
+///     }
+/// }
+///
+/// let dropped = Arc::new(AtomicUsize::new(0));
+/// let (tx, rx) = mpsc::channel();
+/// let (done_tx, done_rx) = mpsc::channel();
+/// let th = thread::spawn(move || {
+///     tx.send(run_loop::clone_handle()).unwrap();
+///     run_loop::run();
+/// });
+/// let handle = rx.recv().unwrap();
+///
+/// // 执行：放在槽中和装箱的消息
+/// for i in 0..100 {
+///     let c = Counted(dropped.clone());
+///     let big = [i as u8; 256];
+///     let tx = done_tx.clone();
+///     if i % 2 == 0 {
+///         handle.post(move || { let _c = c; tx.send(i).unwrap(); });
+///     }
+///     else {
+///         handle.post(move || { let _c = c; tx.send(big[0] as usize).unwrap(); });
+///     }
+/// }
+/// let mut got: Vec<usize> = (0..100).map(|_| done_rx.recv().unwrap()).collect();
+/// got.sort();
+/// assert_eq!(got, (0..100).collect::<Vec<_>>());
+/// assert_eq!(dropped.load(Ordering::SeqCst), 100);
+///
+/// // 原地释放：循环退出后剩余的消息随循环释放，循环释放后投递的消息随最后一个句柄释放
+/// handle.post(run_loop::stop);
+/// for i in 0..100 {
+///     let c = Counted(dropped.clone());
+///     let big = [0u8; 256];
+///     if i % 2 == 0 {
+///         handle.post(move || drop(c));
+///     }
+///     else {
+///         handle.post(move || { drop(c); let _ = big; });
+///     }
+/// }
+/// th.join().unwrap();
+/// drop(handle);
+/// assert_eq!(dropped.load(Ordering::SeqCst), 200);
+/// ```
+pub struct Queue {
+    head: Head,
+    tail: Tail,
+    /// 留作重用的块，通过原子交换转移所有权
+    spare: AtomicPtr<Block>,
+}
+
+unsafe impl Send for Queue {}
+unsafe impl Sync for Queue {}
+
+impl Queue {
+    pub fn new() -> Queue {
+        let block = Box::into_raw(Block::new());
+        Queue {
+            head: Head {
+                index: AtomicUsize::new(0),
+                block: Cell::new(block),
+                retired: Cell::new(ptr::null_mut()),
+            },
+            tail: Tail {
+                index: AtomicUsize::new(0),
+                block: AtomicPtr::new(block),
+                high: AtomicUsize::new(0),
+            },
+            spare: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    pub fn push<T>(&self, t: T) where T: FnOnce() + Send + 'static {
+        let mut backoff = Backoff::new();
+        let mut tail = self.tail.index.load(Ordering::Acquire);
+        let mut block = self.tail.block.load(Ordering::Acquire);
+        let mut next_block = None;
+        loop {
+            let offset = tail % LAP;
+            if offset == BLOCK_CAP {
+                // 其它生产者正在接上下一个块
+                backoff.snooze();
+                tail = self.tail.index.load(Ordering::Acquire);
+                block = self.tail.block.load(Ordering::Acquire);
+                continue;
+            }
+            // 提前准备下一个块，避免其它生产者等待分配
+            if offset + 1 == BLOCK_CAP && next_block.is_none() {
+                next_block = Some(self.alloc_block());
+            }
+            // SeqCst 和循环进入等待前的检查配对，见 `Core::prepare_wait`
+            match self.tail.index.compare_exchange_weak(tail, tail + 1, Ordering::SeqCst, Ordering::Acquire) {
+                Ok(_) => unsafe {
+                    if offset + 1 == BLOCK_CAP {
+                        let next = Box::into_raw(next_block.take().unwrap());
+                        self.tail.block.store(next, Ordering::Release);
+                        self.tail.index.fetch_add(1, Ordering::Release);
+                        (*block).next.store(next, Ordering::Release);
+                    }
+                    let slot = &(*block).slots[offset];
+                    write(slot, t);
+                    slot.ready.store(true, Ordering::Release);
+                    let head = count(self.head.index.load(Ordering::Relaxed));
+                    let len = (count(tail) + 1).saturating_sub(head);
+                    if len > self.tail.high.load(Ordering::Relaxed) {
+                        self.tail.high.fetch_max(len, Ordering::Relaxed);
+                    }
+                    if let Some(unused) = next_block {
+                        self.free_block(unused);
+                    }
+                    return;
+                },
+                Err(t) => {
+                    tail = t;
+                    block = self.tail.block.load(Ordering::Acquire);
+                    backoff.spin();
+                },
+            }
+        }
+    }
+
+    /// 当前尾部的位置，`pop` 只取出在此之前投递的消息
+    pub fn end(&self) -> usize {
+        let tail = self.tail.index.load(Ordering::SeqCst);
+        if tail % LAP == BLOCK_CAP {
+            tail + 1
+        }
+        else {
+            tail
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.index.load(Ordering::Relaxed) == self.end()
+    }
+
+    /// 尚未取出的消息数
+    pub fn len(&self) -> usize {
+        self.pushed() - self.popped()
+    }
+
+    /// 投递的消息总数
+    pub fn pushed(&self) -> usize {
+        count(self.end())
+    }
+
+    /// 取出的消息总数
+    pub fn popped(&self) -> usize {
+        count(self.head.index.load(Ordering::Relaxed))
+    }
+
+    pub fn high_watermark(&self) -> usize {
+        self.tail.high.load(Ordering::Relaxed)
+    }
+
+    /// 以当前的消息数重新开始记录
+    pub fn reset_high_watermark(&self) {
+        self.tail.high.store(self.len(), Ordering::Relaxed);
+    }
+
+    /// 按投递的顺序取出 end 之前的下一条消息
+    ///
+    /// 只能在循环所在的线程调用。返回的消息必须交给 `run` 或者原地释放，
+    /// 在下一次调用 `pop` 之前一直有效；`run` 会先把消息移出槽，因此可以在执行时嵌套调用 `pop`。
+    pub unsafe fn pop(&self, end: usize) -> Option<*mut Action> {
+        let retired = self.head.retired.replace(ptr::null_mut());
+        if !retired.is_null() {
+            self.free_block(Box::from_raw(retired));
+        }
+
+        let head = self.head.index.load(Ordering::Relaxed);
+        if head >= end {
+            return None;
+        }
+        let block = self.head.block.get();
+        let offset = head % LAP;
+        let slot = &(*block).slots[offset];
+        let mut backoff = Backoff::new();
+        // 位置已经被生产者取得，等待写入完成
+        while !slot.ready.load(Ordering::Acquire) {
+            backoff.snooze();
+        }
+        slot.ready.store(false, Ordering::Relaxed);
+        let act = (*slot.act.get()).assume_init();
+
+        if offset + 1 == BLOCK_CAP {
+            // 最后一个槽写入前下一个块已经接上
+            let next = (*block).next.load(Ordering::Acquire);
+            self.head.block.set(next);
+            self.head.index.store(head + 2, Ordering::Relaxed);
+            self.head.retired.set(block);
+        }
+        else {
+            self.head.index.store(head + 1, Ordering::Relaxed);
+        }
+        Some(act)
+    }
+
+    /// 原地释放所有尚未取出的消息，释放时新投递的消息同样释放
+    ///
+    /// 只能在循环所在的线程调用。逐条释放，不会因为消息很多而递归过深：
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::sync::Arc;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::mpsc;
+    /// use std::thread;
+    ///
+    /// struct Counted (Arc<AtomicUsize>);
+    /// impl Drop for Counted {
+    ///     fn drop(&mut self) {
+    ///         self.0.fetch_add(1, Ordering::Relaxed);
+    ///     }
+    /// }
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let (exit_tx, exit_rx) = mpsc::channel::<()>();
+    /// // 很小的栈，循环不运行，退出时释放所有消息
+    /// let th = thread::Builder::new().stack_size(64 * 1024).spawn(move || {
+    ///     tx.send(run_loop::clone_handle()).unwrap();
+    ///     exit_rx.recv().unwrap();
+    /// }).unwrap();
+    /// let handle = rx.recv().unwrap();
+    ///
+    /// let dropped = Arc::new(AtomicUsize::new(0));
+    /// for _ in 0..1_000_000 {
+    ///     let c = Counted(dropped.clone());
+    ///     handle.post(move || drop(c));
+    /// }
+    /// drop(handle);
+    /// exit_tx.send(()).unwrap();
+    /// th.join().unwrap();
+    /// assert_eq!(dropped.load(Ordering::Relaxed), 1_000_000);
+    /// ```
+    pub unsafe fn clear(&self) {
+        while let Some(act) = self.pop(self.end()) {
+            ptr::drop_in_place(act);
+        }
+    }
+
+    fn alloc_block(&self) -> Box<Block> {
+        let spare = self.spare.swap(ptr::null_mut(), Ordering::Acquire);
+        if spare.is_null() {
+            Block::new()
+        }
+        else {
+            unsafe { Box::from_raw(spare) }
+        }
+    }
+
+    /// 块中的槽都已经取出或从未写入
+    fn free_block(&self, block: Box<Block>) {
+        block.next.store(ptr::null_mut(), Ordering::Relaxed);
+        let block = Box::into_raw(block);
+        if self.spare.compare_exchange(ptr::null_mut(), block, Ordering::Release, Ordering::Relaxed).is_err() {
+            drop(unsafe { Box::from_raw(block) });
+        }
+    }
+}
+
+impl Drop for Queue {
+    fn drop(&mut self) {
+        unsafe {
+            self.clear();
+            let retired = self.head.retired.replace(ptr::null_mut());
+            if !retired.is_null() {
+                drop(Box::from_raw(retired));
+            }
+            drop(Box::from_raw(self.head.block.get()));
+            let spare = self.spare.swap(ptr::null_mut(), Ordering::Relaxed);
+            if !spare.is_null() {
+                drop(Box::from_raw(spare));
+            }
+        }
+    }
+}
+
+/// 位置之前的槽数，每跨过一个块就多出一个不对应槽的位置
+fn count(index: usize) -> usize {
+    index - index / LAP
+}
+
+/// 写入槽中，由调用者随后设置 ready
+unsafe fn write<T>(slot: &Slot, t: T) where T: FnOnce() + Send + 'static {
+    let storage = (*slot.storage.get()).0.as_mut_ptr() as *mut u8;
+    let act: *mut Action = if mem::size_of::<Inline<T>>() <= SLOT_SIZE && mem::align_of::<Inline<T>>() <= mem::align_of::<Storage>() {
+        let p = storage as *mut Inline<T>;
+        ptr::write(p, Inline(t));
+        p
+    }
+    else {
+        let p = storage as *mut Boxed<T>;
+        ptr::write(p, Boxed(Box::new(t)));
+        p
+    };
+    ptr::write(slot.act.get(), MaybeUninit::new(act));
+}
+
+struct Backoff {
+    step: u32,
+}
+
+impl Backoff {
+    fn new() -> Backoff {
+        Backoff { step: 0 }
+    }
+
+    fn spin(&mut self) {
+        for _ in 0..1 << self.step.min(6) {
+            hint::spin_loop();
+        }
+        if self.step <= 6 {
+            self.step += 1;
+        }
+    }
+
+    fn snooze(&mut self) {
+        if self.step <= 6 {
+            self.spin();
+        }
+        else {
+            thread::yield_now();
+        }
+    }
+}
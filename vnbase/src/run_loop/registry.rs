@@ -0,0 +1,157 @@
+//! 按名字查找循环内对象
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use super::{Handle, ObjectHandle, ObjectWeak, RUN_LOOP};
+use super::oneshot;
+
+pub struct Registry {
+    entries: HashMap<String, Entry>,
+    next_key: u64,
+}
+
+struct Entry {
+    /// 区分同名的先后注册，旧对象释放时不会移除新注册的对象
+    key: u64,
+    type_id: TypeId,
+    weak: Box<Any>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Registry {
+            entries: HashMap::new(),
+            next_key: 0,
+        }
+    }
+}
+
+/// 以名字在当前线程的循环注册对象，对象释放后自动移除
+///
+/// 名字已经存在时，replace 为 true 则替换，否则不注册。
+/// 对象不在当前线程或没有注册时返回 false。
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop;
+/// use std::thread;
+///
+/// let renderer = run_loop::new_object(String::from("renderer"));
+/// assert!(run_loop::register_object("renderer", &renderer, false));
+/// assert!(!run_loop::register_object("renderer", &run_loop::new_object(1u32), false));
+///
+/// let handle = run_loop::clone_handle();
+/// let th = thread::spawn(move || {
+///     let found = handle.lookup_object::<String>("renderer").wait();
+///     let mismatch = handle.lookup_object::<u32>("renderer").wait();
+///     let missing = handle.lookup_object::<String>("other").wait();
+///     handle.stop();
+///     (found.is_some(), mismatch.is_none(), missing.is_none())
+/// });
+/// run_loop::run();
+/// assert_eq!(th.join().unwrap(), (true, true, true));
+///
+/// drop(renderer);
+/// let handle = run_loop::clone_handle();
+/// assert!(handle.lookup_object::<String>("renderer").wait().is_none());
+/// ```
+pub fn register_object<T>(name: &str, handle: &ObjectHandle<T>, replace: bool) -> bool where T: ?Sized + 'static {
+    if handle.try_get_ref().is_err() {
+        return false;
+    }
+    let key = RUN_LOOP.with(|rl| {
+        let mut registry = rl.registry.borrow_mut();
+        if !replace && registry.entries.contains_key(name) {
+            return None;
+        }
+        let key = registry.next_key;
+        registry.next_key += 1;
+        registry.entries.insert(name.to_owned(), Entry {
+            key: key,
+            type_id: TypeId::of::<T>(),
+            weak: Box::new(handle.downgrade()),
+        });
+        Some(key)
+    });
+    match key {
+        Some(key) => {
+            let name = name.to_owned();
+            handle.on_drop(move || remove(&name, key));
+            true
+        },
+        None => false,
+    }
+}
+
+/// 移除当前线程的循环中以 name 注册的对象，返回是否存在
+pub fn unregister_object(name: &str) -> bool {
+    RUN_LOOP.with(|rl| rl.registry.borrow_mut().entries.remove(name).is_some())
+}
+
+/// 在当前线程的循环中查找对象，不存在、类型不符或已经释放时返回 None
+pub fn lookup_object<T>(name: &str) -> Option<ObjectHandle<T>> where T: ?Sized + 'static {
+    RUN_LOOP.with(|rl| {
+        let registry = rl.registry.borrow();
+        match registry.entries.get(name) {
+            Some(entry) if entry.type_id == TypeId::of::<T>() => {
+                entry.weak.downcast_ref::<ObjectWeak<T>>().and_then(|weak| weak.upgrade())
+            },
+            _ => None,
+        }
+    })
+}
+
+fn remove(name: &str, key: u64) {
+    let _ = RUN_LOOP.try_with(|rl| {
+        let mut registry = rl.registry.borrow_mut();
+        if registry.entries.get(name).map(|entry| entry.key) == Some(key) {
+            registry.entries.remove(name);
+        }
+    });
+}
+
+impl Handle {
+    /// 在循环所在的线程按名字查找对象，见 `run_loop::register_object`
+    pub fn lookup_object<T>(&self, name: &str) -> ObjectLookup<T> where T: ?Sized + 'static {
+        let (tx, rx) = oneshot::channel();
+        if super::is_own_handle(self) {
+            let _ = tx.send(lookup_object::<T>(name));
+        }
+        else {
+            let name = name.to_owned();
+            self.post(move || {
+                let _ = tx.send(lookup_object::<T>(&name));
+            });
+        }
+        ObjectLookup {
+            rx: rx,
+        }
+    }
+}
+
+/// 查找对象的结果，可以阻塞等待也可以作为 `Future` 使用
+pub struct ObjectLookup<T: ?Sized + 'static> {
+    rx: oneshot::Receiver<Option<ObjectHandle<T>>>,
+}
+
+impl<T: ?Sized> ObjectLookup<T> {
+    /// 阻塞等待查找结果，循环在查找前退出时返回 None
+    #[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+    pub fn wait(self) -> Option<ObjectHandle<T>> {
+        self.rx.recv().unwrap_or(None)
+    }
+}
+
+impl<T: ?Sized> Future for ObjectLookup<T> {
+    type Output = Option<ObjectHandle<T>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        match Pin::new(&mut self.rx).poll(cx) {
+            Poll::Ready(r) => Poll::Ready(r.unwrap_or(None)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
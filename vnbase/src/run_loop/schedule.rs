@@ -1,9 +1,14 @@
 
 use std::rc::Rc;
+use std::fmt;
+use std::ops::Deref;
 use std::cell::{RefCell, Cell};
-use std::time::{Instant, Duration};
+use std::time::Duration;
 
-use super::core::{TimedAction, TimedActionNode};
+use super::Instant;
+use super::core::{self, TimedAction, TimedActionNode};
+use super::context::Context;
+use super::origin::Origin;
 
 /// 周期历程
 /// 
@@ -26,6 +31,35 @@ use super::core::{TimedAction, TimedActionNode};
 /// 
 /// run_loop::run();
 /// ```
+///
+/// 回调 panic 后周期历程不再执行，回调已经随 panic 释放，重新设置回调后可以再次启动：
+/// ```
+/// use vnbase::run_loop;
+/// use std::cell::Cell;
+/// use std::panic;
+/// use std::rc::Rc;
+/// use std::time::Duration;
+///
+/// let schedule = run_loop::new_schedule()
+///     .with_period(Duration::from_millis(1))
+///     .with_callback(|_| panic!("schedule failed"))
+///     .and_start();
+/// assert!(panic::catch_unwind(run_loop::run).is_err());
+/// assert!(!schedule.is_active());
+///
+/// let ticks = Rc::new(Cell::new(0));
+/// let t = ticks.clone();
+/// schedule.set_callback(move |_| {
+///     t.set(t.get() + 1);
+///     if t.get() == 3 {
+///         run_loop::stop();
+///     }
+/// });
+/// schedule.start();
+/// assert!(schedule.is_active());
+/// run_loop::run();
+/// assert_eq!(ticks.get(), 3);
+/// ```
 pub struct Schedule {
     data: Rc<Data>,
     cancel_on_drop: Cell<bool>,
@@ -42,23 +76,201 @@ impl Schedule {
                     period: Duration::from_millis(100),
                     last: now,
                     target: now,
+                    max_ticks: None,
+                    remaining: None,
+                    ticks: 0,
+                    align: None,
+                    drift: Duration::default(),
+                    drift_correction: false,
                     act: None,
+                    origin: Origin::default(),
                 }),
             }),
             cancel_on_drop: Cell::new(false),
         }
     }
 
+    #[cfg_attr(feature = "debug-origin", track_caller)]
     pub fn with_callback<T>(self, cb: T) -> Self where T: FnMut(Duration) + 'static {
         self.set_callback(cb);
         self
     }
 
+    /// 设置接收循环上下文的回调，见 `Context`
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::time::Duration;
+    ///
+    /// let _schedule = run_loop::new_schedule()
+    ///     .with_period(Duration::from_millis(10))
+    ///     .with_callback_ctx(|ctx, _| ctx.stop())
+    ///     .and_start();
+    ///
+    /// run_loop::run();
+    /// ```
+    #[cfg_attr(feature = "debug-origin", track_caller)]
+    pub fn with_callback_ctx<T>(self, cb: T) -> Self where T: FnMut(&Context, Duration) + 'static {
+        self.set_callback_ctx(cb);
+        self
+    }
+
+    /// 使用已经装箱的回调，见 `Timer::with_callback_boxed`
+    #[cfg_attr(feature = "debug-origin", track_caller)]
+    pub fn with_callback_boxed(self, cb: Box<FnMut(Duration)>) -> Self {
+        self.set_callback_boxed(cb);
+        self
+    }
+
+    #[cfg_attr(feature = "debug-origin", track_caller)]
+    pub fn with_callback_while<T>(self, cb: T) -> Self where T: FnMut(Duration) -> bool + 'static {
+        self.set_callback_while(cb);
+        self
+    }
+
     pub fn with_period(self, period: Duration) -> Self {
         self.set_period(period);
         self
     }
 
+    /// 执行 n 次后自动停止，n 为 0 时在第一次处理时直接停止
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::time::Duration;
+    ///
+    /// let schedule = run_loop::new_schedule()
+    ///     .with_period(Duration::from_millis(10))
+    ///     .with_max_ticks(3)
+    ///     .with_callback(|_| {})
+    ///     .and_start();
+    ///
+    /// let _timer = run_loop::new_timer()
+    ///     .with_callback_once(run_loop::stop)
+    ///     .and_start(Duration::from_millis(100));
+    /// run_loop::run();
+    ///
+    /// assert_eq!(schedule.tick_count(), 3);
+    /// assert!(!schedule.is_active());
+    /// ```
+    pub fn with_max_ticks(self, n: u64) -> Self {
+        self.set_max_ticks(n);
+        self
+    }
+
+    /// 使执行时刻对齐到 epoch + k * period 的网格上
+    ///
+    /// 启动后第一次执行在下一个网格点，之后每次都重新从网格计算，
+    /// 回调过慢时跳过已经错过的网格点，误差不会累积。
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::rc::Rc;
+    /// use std::cell::RefCell;
+    /// use std::thread;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let period = Duration::from_millis(10);
+    /// let epoch = Instant::now() + Duration::from_millis(3);
+    /// let ticks = Rc::new(RefCell::new(Vec::new()));
+    ///
+    /// let t = ticks.clone();
+    /// let _schedule = run_loop::new_schedule()
+    ///     .with_period(period)
+    ///     .with_alignment(epoch)
+    ///     .with_max_ticks(5)
+    ///     .with_callback(move |_| {
+    ///         t.borrow_mut().push(Instant::now());
+    ///         // 比周期更慢的回调
+    ///         thread::sleep(Duration::from_millis(15));
+    ///     })
+    ///     .and_start();
+    ///
+    /// let _timer = run_loop::new_timer()
+    ///     .with_callback_once(run_loop::stop)
+    ///     .and_start(Duration::from_millis(200));
+    /// run_loop::run();
+    ///
+    /// // 每次执行都落在不同的网格区间内，没有补执行错过的网格点
+    /// let slots: Vec<_> = ticks.borrow().iter()
+    ///     .map(|t| (t.duration_since(epoch).as_nanos() / period.as_nanos()) as u64)
+    ///     .collect();
+    /// assert_eq!(slots.len(), 5);
+    /// assert!(slots.windows(2).all(|w| w[1] >= w[0] + 2));
+    /// ```
+    pub fn with_alignment(self, epoch: Instant) -> Self {
+        self.set_alignment(Some(epoch));
+        self
+    }
+
+    /// 执行时落后计划超过一个周期时，跳过错过的执行，下一次执行在晚于当前时刻的下一个周期点
+    ///
+    /// 默认关闭，落后时会连续补上错过的执行，总的执行次数和经过的时间一致；
+    /// 开启后不再连续执行，长时间运行时节拍保持在启动时的周期点上，适合时钟一类的用途。
+    /// 设置了对齐时总是跳过错过的网格点，不受此设置影响；周期为 0 时没有可以跳过的执行，同样不受影响。
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
+    /// use std::thread;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let period = Duration::from_millis(20);
+    /// let schedule = |correction: bool| {
+    ///     let ticks = Rc::new(RefCell::new(Vec::new()));
+    ///     let t = ticks.clone();
+    ///     let schedule = run_loop::new_schedule()
+    ///         .with_period(period)
+    ///         .with_max_ticks(3)
+    ///         .with_drift_correction(correction)
+    ///         .with_callback(move |_| t.borrow_mut().push(Instant::now()))
+    ///         .and_start();
+    ///     (schedule, ticks)
+    /// };
+    /// let (_plain, plain_ticks) = schedule(false);
+    /// let (corrected, corrected_ticks) = schedule(true);
+    /// assert!(corrected.is_drift_correction());
+    ///
+    /// // 第一次执行时已经落后一个多周期
+    /// thread::sleep(Duration::from_millis(50));
+    /// let _stop = run_loop::new_timer().with_callback_once(run_loop::stop).and_start(Duration::from_millis(200));
+    /// run_loop::run();
+    ///
+    /// let span = |ticks: &Rc<RefCell<Vec<Instant>>>| {
+    ///     let ticks = ticks.borrow();
+    ///     assert_eq!(ticks.len(), 3);
+    ///     ticks[2] - ticks[0]
+    /// };
+    /// // 补上错过的执行
+    /// assert!(span(&plain_ticks) < Duration::from_millis(25));
+    /// // 跳过错过的执行，之后按周期执行
+    /// assert!(span(&corrected_ticks) >= Duration::from_millis(25));
+    /// assert!(corrected.drift() < period);
+    ///
+    /// // 周期为 0 时每一轮都执行
+    /// let count = Rc::new(RefCell::new(0));
+    /// let c = count.clone();
+    /// let _zero = run_loop::new_schedule()
+    ///     .with_period(Duration::from_secs(0))
+    ///     .with_max_ticks(3)
+    ///     .with_drift_correction(true)
+    ///     .with_callback(move |_| *c.borrow_mut() += 1)
+    ///     .and_start();
+    /// thread::sleep(Duration::from_millis(5));
+    /// let _stop = run_loop::new_timer().with_callback_once(run_loop::stop).and_start(Duration::from_millis(20));
+    /// run_loop::run();
+    /// assert_eq!(*count.borrow(), 3);
+    /// ```
+    pub fn with_drift_correction(self, enable: bool) -> Self {
+        self.set_drift_correction(enable);
+        self
+    }
+
     pub fn with_cancel_on_drop(self, cancel_on_drop: bool) -> Self {
         self.cancel_on_drop.set(cancel_on_drop);
         self
@@ -69,9 +281,46 @@ impl Schedule {
         self
     }
 
+    /// 转为释放时总会取消的周期历程，不受 `cancel_on_drop` 影响，见 `Timer::guard`
+    ///
+    /// ```
+    /// use vnbase::run_loop;
+    ///
+    /// let before = run_loop::active_timer_count();
+    /// {
+    ///     let _tick = run_loop::new_schedule().with_callback(|_| {}).and_start().guard();
+    ///     assert_eq!(run_loop::active_timer_count(), before + 1);
+    /// }
+    /// assert_eq!(run_loop::active_timer_count(), before);
+    /// ```
+    pub fn guard(self) -> ScheduleGuard {
+        ScheduleGuard(self)
+    }
+
+    #[cfg_attr(feature = "debug-origin", track_caller)]
     pub fn set_callback<T>(&self, cb: T) where T: FnMut(Duration) + 'static {
+        self.set_callback_boxed(Box::new(cb));
+    }
+
+    /// 直接保存已经装箱的回调，不会再次分配
+    #[cfg_attr(feature = "debug-origin", track_caller)]
+    pub fn set_callback_boxed(&self, cb: Box<FnMut(Duration)>) {
         let mut inner = self.data.i.borrow_mut();
-        inner.act = Some(Box::new(cb));
+        inner.act = Some(Callback::Plain(cb));
+        inner.origin = Origin::caller();
+    }
+
+    /// 设置回调，回调返回 false 时停止，和在回调中调用 `cancel` 相同
+    #[cfg_attr(feature = "debug-origin", track_caller)]
+    pub fn set_callback_while<T>(&self, cb: T) where T: FnMut(Duration) -> bool + 'static {
+        let mut inner = self.data.i.borrow_mut();
+        inner.act = Some(Callback::While(Box::new(cb)));
+        inner.origin = Origin::caller();
+    }
+
+    #[cfg_attr(feature = "debug-origin", track_caller)]
+    pub fn set_callback_ctx<T>(&self, mut cb: T) where T: FnMut(&Context, Duration) + 'static {
+        self.set_callback(move |dt| cb(&Context::new(), dt));
     }
 
     pub fn set_period(&self, period: Duration) {
@@ -79,15 +328,122 @@ impl Schedule {
         if inner.period != period {
             inner.period = period;
             if inner.state == State::Active {
-                super::adjust_timed_action(&self.data.n, inner.last + period);
+                let target = match inner.align {
+                    Some(_) => inner.next_target(Instant::now()),
+                    None => core::saturating_deadline(inner.last, period),
+                };
+                inner.target = target;
+                super::adjust_timed_action(&self.data.n, target);
             }
         }
     }
 
+    /// 改为新的周期并从现在重新计算相位，下一次执行在一个新周期之后
+    ///
+    /// 和 `set_period` 不同，不再以上一次执行的时刻为基准；设置了对齐时取网格上的下一个点。
+    /// 在回调中调用时对回调之后的下一次执行生效。没有启动时只设置周期。
+    ///
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let start = Instant::now();
+    /// let schedule = run_loop::new_schedule()
+    ///     .with_callback(|_| run_loop::stop())
+    ///     .with_period(Duration::from_secs(3600))
+    ///     .and_start();
+    /// schedule.restart_with_period(Duration::from_millis(10));
+    /// assert_eq!(schedule.get_period(), Duration::from_millis(10));
+    ///
+    /// run_loop::run();
+    /// let elapsed = start.elapsed();
+    /// assert!(elapsed >= Duration::from_millis(10) && elapsed < Duration::from_secs(60));
+    /// assert_eq!(schedule.tick_count(), 1);
+    /// schedule.cancel();
+    /// ```
+    pub fn restart_with_period(&self, period: Duration) {
+        let mut inner = self.data.i.borrow_mut();
+        inner.period = period;
+        let now = Instant::now();
+        inner.last = now;
+        inner.target = inner.next_target(now);
+        if inner.state == State::Active {
+            super::adjust_timed_action(&self.data.n, inner.target);
+        }
+    }
+
     pub fn get_period(&self) -> Duration {
         self.data.i.borrow().period
     }
 
+    pub fn set_max_ticks(&self, n: u64) {
+        let mut inner = self.data.i.borrow_mut();
+        inner.max_ticks = Some(n);
+        inner.remaining = Some(n.saturating_sub(inner.ticks));
+    }
+
+    pub fn get_max_ticks(&self) -> Option<u64> {
+        self.data.i.borrow().max_ticks
+    }
+
+    /// 设置对齐的基准时刻，None 时取消对齐，见 `with_alignment`
+    pub fn set_alignment(&self, epoch: Option<Instant>) {
+        let mut inner = self.data.i.borrow_mut();
+        inner.align = epoch;
+        if inner.state == State::Active {
+            let target = inner.next_target(Instant::now());
+            inner.target = target;
+            super::adjust_timed_action(&self.data.n, target);
+        }
+    }
+
+    pub fn get_alignment(&self) -> Option<Instant> {
+        self.data.i.borrow().align
+    }
+
+    /// 自上次启动以来执行的次数
+    pub fn tick_count(&self) -> u64 {
+        self.data.i.borrow().ticks
+    }
+
+    /// 见 `with_drift_correction`
+    pub fn set_drift_correction(&self, enable: bool) {
+        self.data.i.borrow_mut().drift_correction = enable;
+    }
+
+    pub fn is_drift_correction(&self) -> bool {
+        self.data.i.borrow().drift_correction
+    }
+
+    /// 最近一次执行比计划的时刻晚了多久，启动后尚未执行时为 0
+    ///
+    /// 在回调中调用时得到的是本次执行的延迟。
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// let schedule = run_loop::new_schedule()
+    ///     .with_period(Duration::from_millis(10))
+    ///     .with_max_ticks(1)
+    ///     .with_callback(|_| run_loop::stop())
+    ///     .and_start();
+    /// assert_eq!(schedule.drift(), Duration::from_secs(0));
+    ///
+    /// thread::sleep(Duration::from_millis(40));
+    /// run_loop::run();
+    /// assert!(schedule.drift() >= Duration::from_millis(30));
+    ///
+    /// schedule.start();
+    /// assert_eq!(schedule.drift(), Duration::from_secs(0));
+    /// schedule.cancel();
+    /// ```
+    pub fn drift(&self) -> Duration {
+        self.data.i.borrow().drift
+    }
+
     pub fn set_cancel_on_drop(&self, cancel_on_drop: bool) {
         self.cancel_on_drop.set(cancel_on_drop);
     }
@@ -97,20 +453,21 @@ impl Schedule {
     }
 
     pub fn is_active(&self) -> bool {
-        match self.data.i.borrow().state {
-            State::None | State::Cancelled => false,
-            _ => true,
-        }
+        !matches!(self.data.i.borrow().state, State::None | State::Cancelled)
     }
 
     pub fn start(&self) {
         let mut inner = self.data.i.borrow_mut();
         let now = Instant::now();
         inner.last = now;
-        inner.target = now + inner.period;
+        inner.target = inner.next_target(now);
+        inner.ticks = 0;
+        inner.drift = Duration::default();
+        inner.remaining = inner.max_ticks;
         match inner.state {
             State::None => {
                 super::push_timed_action(self.data.clone(), inner.target);
+                inner.state = State::Active;
             },
             State::Active => {
                 super::adjust_timed_action(&self.data.n, inner.target);
@@ -128,6 +485,7 @@ impl Schedule {
             State::None | State::Cancelled => {},
             State::Active => {
                 super::remove_timed_action(&self.data.n);
+                inner.state = State::None;
             },
             State::Processing => {
                 inner.state = State::Cancelled;
@@ -136,6 +494,19 @@ impl Schedule {
     }
 }
 
+/// 开启 `debug-origin` 特性时同时显示设置回调的位置
+impl fmt::Debug for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut d = f.debug_struct("Schedule");
+        d.field("active", &self.is_active());
+        d.field("period", &self.get_period());
+        d.field("ticks", &self.tick_count());
+        #[cfg(feature = "debug-origin")]
+        d.field("origin", &self.data.i.borrow().origin.location().map(|loc| loc.to_string()));
+        d.finish()
+    }
+}
+
 impl Drop for Schedule {
     fn drop(&mut self) {
         if self.cancel_on_drop.get() {
@@ -144,6 +515,23 @@ impl Drop for Schedule {
     }
 }
 
+/// 释放时取消的周期历程，见 `Schedule::guard`
+pub struct ScheduleGuard (Schedule);
+
+impl Deref for ScheduleGuard {
+    type Target = Schedule;
+
+    fn deref(&self) -> &Schedule {
+        &self.0
+    }
+}
+
+impl Drop for ScheduleGuard {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
 struct Data {
     n: TimedActionNode,
     i: RefCell<Inner>,
@@ -154,7 +542,73 @@ struct Inner {
     period: Duration,
     last: Instant,
     target: Instant,
-    act: Option<Box<FnMut(Duration)>>,
+    max_ticks: Option<u64>,
+    remaining: Option<u64>,
+    ticks: u64,
+    /// 对齐的基准时刻
+    align: Option<Instant>,
+    /// 最近一次执行比计划晚了多久
+    drift: Duration,
+    /// 落后超过一个周期时跳过错过的执行
+    drift_correction: bool,
+    act: Option<Callback>,
+    /// 设置回调的位置
+    origin: Origin,
+}
+
+impl Inner {
+    /// now 之后的下一次执行时刻，对齐时为网格上晚于 now 的第一个点
+    fn next_target(&self, now: Instant) -> Instant {
+        let epoch = match self.align {
+            Some(epoch) => epoch,
+            None => return core::saturating_deadline(now, self.period),
+        };
+        let period = self.period.as_nanos();
+        if period == 0 {
+            return now;
+        }
+        if now >= epoch {
+            let k = (now - epoch).as_nanos() / period + 1;
+            epoch.checked_add(nanos(k * period)).unwrap_or(core::saturating_deadline(now, self.period))
+        }
+        else {
+            let k = (epoch - now).as_nanos() / period;
+            epoch.checked_sub(nanos(k * period)).unwrap_or(epoch)
+        }
+    }
+
+    /// 从 target 起按周期前进，晚于 now 的第一个点，周期不为 0
+    fn skip_missed(&self, now: Instant) -> Instant {
+        let period = self.period.as_nanos();
+        let k = now.saturating_duration_since(self.target).as_nanos() / period + 1;
+        self.target.checked_add(nanos(k * period)).unwrap_or(core::saturating_deadline(now, self.period))
+    }
+}
+
+fn nanos(n: u128) -> Duration {
+    let secs = n / 1_000_000_000;
+    if secs > u64::MAX as u128 {
+        return Duration::MAX;
+    }
+    Duration::new(secs as u64, (n % 1_000_000_000) as u32)
+}
+
+enum Callback {
+    Plain(Box<FnMut(Duration)>),
+    /// 返回 false 时停止
+    While(Box<FnMut(Duration) -> bool>),
+}
+
+impl Callback {
+    fn call(&mut self, dt: Duration) -> bool {
+        match *self {
+            Callback::Plain(ref mut f) => {
+                f(dt);
+                true
+            },
+            Callback::While(ref mut f) => f(dt),
+        }
+    }
 }
 
 #[derive(PartialEq, Eq)]
@@ -170,21 +624,51 @@ impl TimedAction for Data {
         &self.n
     }
 
+    fn is_periodic(&self) -> bool {
+        true
+    }
+
     fn process(&self) -> Option<Instant> {
         let mut inner = self.i.borrow_mut();
+        if inner.remaining == Some(0) {
+            inner.state = State::None;
+            return None;
+        }
+        inner.ticks += 1;
+        if let Some(ref mut n) = inner.remaining {
+            *n -= 1;
+        }
         let now = Instant::now();
         let dur = now - inner.last;
         inner.last = now;
-        inner.target += inner.period;
+        inner.drift = now.saturating_duration_since(inner.target);
+        inner.target = match inner.align {
+            Some(_) => inner.next_target(now),
+            None if inner.drift_correction && inner.period > Duration::from_secs(0) && inner.drift > inner.period => inner.skip_missed(now),
+            None => core::saturating_deadline(inner.target, inner.period),
+        };
         if let Some(mut f) = inner.act.take() {
             inner.state = State::Processing;
+            inner.origin.enter();
             drop(inner);
-            f(dur);
+            // 回调 panic 时把状态恢复为 None，之后可以重新设置回调和启动
+            let go_on = core::reset_on_unwind(&self.i, |inner| inner.state = State::None, || f.call(dur));
             inner = self.i.borrow_mut();
+            if inner.align.is_some() {
+                // 回调可能很慢，按结束的时刻重新对齐
+                inner.target = inner.next_target(Instant::now());
+            }
             if inner.act.is_none() {
                 inner.act = Some(f);
             }
+            if !go_on && inner.state == State::Processing {
+                inner.state = State::Cancelled;
+            }
             match inner.state {
+                State::Processing if inner.remaining == Some(0) => {
+                    inner.state = State::None;
+                    None
+                },
                 State::Processing => {
                     inner.state = State::Active;
                     Some(inner.target)
@@ -196,6 +680,10 @@ impl TimedAction for Data {
                 _ => unreachable!(),
             }
         }
+        else if inner.remaining == Some(0) {
+            inner.state = State::None;
+            None
+        }
         else {
             Some(inner.target)
         }
@@ -0,0 +1,112 @@
+//! 投递可以借用调用者栈上数据的函数，并等待它们全部执行，见 `Handle::scope`
+use std::marker::PhantomData;
+use std::mem;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use super::Handle;
+
+/// `Handle::scope` 中投递函数的作用域
+///
+/// 只能通过 `Handle::scope` 得到，由它保证返回前等待投递的函数：
+/// ```compile_fail
+/// use vnbase::run_loop::{self, Scope};
+///
+/// let scope = Scope::new(run_loop::clone_handle());
+/// ```
+///
+/// 对 'env 不变，投递的函数不能借用只在作用域内存在的数据：
+/// ```compile_fail
+/// use vnbase::run_loop;
+///
+/// let handle = run_loop::clone_handle();
+/// handle.scope(|s| {
+///     let local = 1;
+///     s.spawn(|| { let _ = &local; });
+/// });
+/// ```
+pub struct Scope<'env> {
+    handle: Handle,
+    state: Arc<State>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+struct State {
+    /// 尚未执行或者释放的函数数
+    pending: Mutex<usize>,
+    cond: Condvar,
+    panicked: AtomicBool,
+}
+
+impl<'env> Scope<'env> {
+    pub(crate) fn new(handle: Handle) -> Scope<'env> {
+        Scope {
+            handle: handle,
+            state: Arc::new(State {
+                pending: Mutex::new(0),
+                cond: Condvar::new(),
+                panicked: AtomicBool::new(false),
+            }),
+            env: PhantomData,
+        }
+    }
+
+    /// 向循环投递 f，`Handle::scope` 返回前 f 已经执行
+    ///
+    /// 循环所在的线程已经结束时 f 不会执行，直接释放。
+    pub fn spawn<F>(&self, f: F) where F: FnOnce() + Send + 'env {
+        *self.state.pending.lock().unwrap() += 1;
+        let mut job = Job {
+            f: Some(f),
+            _done: Done(self.state.clone()),
+        };
+        let job: Box<FnOnce() + Send + 'env> = Box::new(move || {
+            let f = job.f.take().unwrap();
+            f();
+        });
+        // `wait` 在所有函数执行或者释放之前不会返回，借用的数据在此之前一直有效
+        let job: Box<FnOnce() + Send + 'static> = unsafe { mem::transmute(job) };
+        self.handle.post(job);
+    }
+
+    /// 等待所有投递的函数执行或者释放，返回是否有函数 panic
+    pub(crate) fn wait(&self) -> bool {
+        let mut pending = self.state.pending.lock().unwrap();
+        while *pending > 0 {
+            pending = self.state.cond.wait(pending).unwrap();
+        }
+        self.state.panicked.load(Ordering::SeqCst)
+    }
+}
+
+/// 投递的函数和完成标记，字段按声明的顺序释放，函数总是先于标记释放
+struct Job<F> {
+    f: Option<F>,
+    _done: Done,
+}
+
+/// 函数执行完、panic 或者未执行就被释放时减少计数
+struct Done(Arc<State>);
+
+impl Drop for Done {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.0.panicked.store(true, Ordering::SeqCst);
+        }
+        let mut pending = self.0.pending.lock().unwrap();
+        *pending -= 1;
+        if *pending == 0 {
+            self.0.cond.notify_all();
+        }
+    }
+}
+
+/// f 发生 panic 时同样等待，借用的数据在投递的函数结束前不能释放
+pub(crate) struct WaitGuard<'a, 'env: 'a>(pub(crate) &'a Scope<'env>);
+
+impl<'a, 'env> Drop for WaitGuard<'a, 'env> {
+    fn drop(&mut self) {
+        self.0.wait();
+    }
+}
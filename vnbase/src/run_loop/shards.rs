@@ -0,0 +1,122 @@
+//! 按投递线程分片的消息队列
+//!
+//! 每个分片是独立的 `Queue`，投递线程固定使用其中一个，多个线程同时投递时不再争用同一个尾部。
+//! 循环按分片的顺序依次取出各分片在快照之前的消息，同一个线程投递的消息仍然按顺序执行。
+//! 只有一个分片时和直接使用 `Queue` 相同。
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::queue::{Action, Queue};
+
+/// 分片数的上限
+pub const MAX_SHARDS: usize = 16;
+
+static NEXT_PRODUCER: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// 投递线程的序号，决定使用的分片，第一次投递时分配
+    ///
+    /// 常量初始化并且不需要析构，线程结束时的析构函数中投递也能取到同一个序号。
+    static PRODUCER: Cell<usize> = const { Cell::new(usize::MAX) };
+}
+
+/// 当前线程的序号
+fn producer() -> usize {
+    PRODUCER.with(|p| {
+        if p.get() == usize::MAX {
+            p.set(NEXT_PRODUCER.fetch_add(1, Ordering::Relaxed));
+        }
+        p.get()
+    })
+}
+
+pub struct Shards {
+    shards: Box<[Queue]>,
+}
+
+/// 各分片尾部位置的快照，`pop` 依次取完每个分片后前进到下一个
+#[derive(Clone, Copy)]
+pub struct End {
+    ends: [usize; MAX_SHARDS],
+    next: usize,
+}
+
+impl Shards {
+    /// n 个分片，超出范围时取最近的有效值
+    pub fn new(n: usize) -> Shards {
+        Shards {
+            shards: (0..n.clamp(1, MAX_SHARDS)).map(|_| Queue::new()).collect::<Vec<_>>().into_boxed_slice(),
+        }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn push<T>(&self, t: T) where T: FnOnce() + Send + 'static {
+        let n = self.shards.len();
+        let shard = if n == 1 {
+            0
+        }
+        else {
+            producer() % n
+        };
+        self.shards[shard].push(t);
+    }
+
+    pub fn end(&self) -> End {
+        let mut end = End {
+            ends: [0; MAX_SHARDS],
+            next: 0,
+        };
+        for (e, q) in end.ends.iter_mut().zip(self.shards.iter()) {
+            *e = q.end();
+        }
+        end
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|q| q.is_empty())
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|q| q.len()).sum()
+    }
+
+    pub fn pushed(&self) -> usize {
+        self.shards.iter().map(|q| q.pushed()).sum()
+    }
+
+    pub fn popped(&self) -> usize {
+        self.shards.iter().map(|q| q.popped()).sum()
+    }
+
+    /// 各分片最高水位之和，多于一个分片时是实际最高水位的上限
+    pub fn high_watermark(&self) -> usize {
+        self.shards.iter().map(|q| q.high_watermark()).sum()
+    }
+
+    pub fn reset_high_watermark(&self) {
+        for q in self.shards.iter() {
+            q.reset_high_watermark();
+        }
+    }
+
+    /// 取出 end 之前的下一条消息，要求同 `Queue::pop`
+    pub unsafe fn pop(&self, end: &mut End) -> Option<*mut Action> {
+        while end.next < self.shards.len() {
+            if let Some(act) = self.shards[end.next].pop(end.ends[end.next]) {
+                return Some(act);
+            }
+            end.next += 1;
+        }
+        None
+    }
+
+    /// 同 `Queue::clear`
+    pub unsafe fn clear(&self) {
+        for q in self.shards.iter() {
+            q.clear();
+        }
+    }
+}
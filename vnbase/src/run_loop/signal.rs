@@ -0,0 +1,84 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+/// 循环内的条件信号，等待不会阻塞线程
+///
+/// `wait` 注册的回调在 `notify` 时按注册的顺序投递到当前线程的循环执行，
+/// 每个回调只执行一次。只能在创建它的线程使用。
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop;
+/// use std::rc::Rc;
+/// use std::cell::RefCell;
+///
+/// let log = Rc::new(RefCell::new(Vec::new()));
+/// let signal = run_loop::LoopSignal::new();
+///
+/// for i in 0..3 {
+///     let log = log.clone();
+///     signal.wait(move || log.borrow_mut().push(i));
+/// }
+/// assert_eq!(signal.waiters(), 3);
+///
+/// signal.notify_one();
+/// signal.notify();
+/// assert_eq!(signal.waiters(), 0);
+/// // 回调通过投递执行，此时还没有执行
+/// assert!(log.borrow().is_empty());
+///
+/// run_loop::clone_handle().post(run_loop::stop);
+/// run_loop::run();
+/// assert_eq!(*log.borrow(), vec![0, 1, 2]);
+/// ```
+#[derive(Clone)]
+pub struct LoopSignal {
+    waiters: Rc<RefCell<VecDeque<Waiter>>>,
+}
+
+type Waiter = Box<FnOnce()>;
+
+impl Default for LoopSignal {
+    fn default() -> Self {
+        LoopSignal::new()
+    }
+}
+
+impl LoopSignal {
+    pub fn new() -> Self {
+        LoopSignal {
+            waiters: Rc::new(RefCell::new(VecDeque::new())),
+        }
+    }
+
+    /// 注册回调，在下一次通知时执行
+    pub fn wait<T>(&self, f: T) where T: FnOnce() + 'static {
+        self.waiters.borrow_mut().push_back(Box::new(f));
+    }
+
+    /// 投递最早注册的回调，返回是否有等待的回调
+    pub fn notify_one(&self) -> bool {
+        let f = self.waiters.borrow_mut().pop_front();
+        match f {
+            Some(f) => {
+                super::post_local(f);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// 按注册的顺序投递所有等待的回调
+    pub fn notify(&self) {
+        let waiters = ::std::mem::take(&mut *self.waiters.borrow_mut());
+        for f in waiters {
+            super::post_local(f);
+        }
+    }
+
+    /// 等待中的回调数
+    pub fn waiters(&self) -> usize {
+        self.waiters.borrow().len()
+    }
+}
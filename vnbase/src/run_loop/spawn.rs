@@ -0,0 +1,202 @@
+//! 在循环上运行 `Future`，见 `Handle::spawn`、`run_loop::spawn_local`
+//!
+//! 两者共用循环中的任务表：`Send` 的 `Future` 先投递到循环所在的线程，之后和本地任务一样保存在表中，
+//! 只在循环所在的线程轮询和释放。任务被唤醒时投递一次轮询，完成后释放 `Future`。
+use std::error;
+use std::fmt;
+use std::mem;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll, Wake, Waker};
+
+use super::{Handle, RUN_LOOP};
+
+/// 循环所在的线程已经结束，不能再运行任务
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpawnError;
+
+impl fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("run loop is dead")
+    }
+}
+
+impl error::Error for SpawnError {}
+
+/// 循环中尚未完成的任务
+pub struct Tasks {
+    slots: Vec<Slot>,
+    /// 空闲的位置
+    free: Vec<usize>,
+}
+
+struct Slot {
+    /// 位置每次重新使用时加一，之前的任务的唤醒不会轮询新的任务
+    generation: u64,
+    /// 正在轮询或者位置空闲时为 None
+    fut: Option<Pin<Box<Future<Output = ()>>>>,
+    waker: Option<Waker>,
+    /// 正在轮询时又收到轮询，轮询结束后重新唤醒
+    rewake: bool,
+}
+
+impl Tasks {
+    pub fn new() -> Tasks {
+        Tasks {
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    fn insert(&mut self, fut: Pin<Box<Future<Output = ()>>>, handle: Handle) -> Waker {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                self.slots.push(Slot { generation: 0, fut: None, waker: None, rewake: false });
+                self.slots.len() - 1
+            },
+        };
+        let slot = &mut self.slots[index];
+        let waker = Waker::from(Arc::new(TaskWaker {
+            handle: handle,
+            index: index,
+            generation: slot.generation,
+            queued: AtomicBool::new(false),
+        }));
+        slot.fut = Some(fut);
+        slot.waker = Some(waker.clone());
+        waker
+    }
+}
+
+/// 把 fut 加入当前线程循环的任务表，之后由循环轮询
+pub fn spawn_local(fut: Pin<Box<Future<Output = ()>>>) {
+    let waker = RUN_LOOP.with(|rl| {
+        let handle = Handle { core: rl.core.clone() };
+        rl.tasks.borrow_mut().insert(fut, handle)
+    });
+    waker.wake();
+}
+
+/// 在 handle 的循环上加入 fut，循环所在的线程已经结束时返回错误
+pub fn spawn(handle: &Handle, fut: Pin<Box<Future<Output = ()> + Send>>) -> Result<(), SpawnError> {
+    handle.try_post(move || spawn_local(fut)).map_err(|_| SpawnError)
+}
+
+/// 在循环所在的线程轮询 index 处的任务，任务已经完成时什么都不做
+fn poll(index: usize, generation: u64) {
+    let taken = RUN_LOOP.with(|rl| {
+        let mut tasks = rl.tasks.borrow_mut();
+        let slot = &mut tasks.slots[index];
+        if slot.generation != generation {
+            return None;
+        }
+        match slot.fut.take() {
+            Some(fut) => Some((fut, slot.waker.clone().unwrap())),
+            None => {
+                slot.rewake = true;
+                None
+            },
+        }
+    });
+    let (mut fut, waker) = match taken {
+        Some(t) => t,
+        None => return,
+    };
+    let done = fut.as_mut().poll(&mut Context::from_waker(&waker)) == Poll::Ready(());
+    let rewake = RUN_LOOP.with(|rl| {
+        let mut tasks = rl.tasks.borrow_mut();
+        let rewake = mem::replace(&mut tasks.slots[index].rewake, false);
+        if done {
+            let slot = &mut tasks.slots[index];
+            slot.generation += 1;
+            slot.waker = None;
+            tasks.free.push(index);
+            false
+        }
+        else {
+            tasks.slots[index].fut = Some(fut);
+            rewake
+        }
+    });
+    if rewake {
+        waker.wake();
+    }
+}
+
+/// 任务的唤醒，只保存任务的位置，可以在任意线程唤醒
+struct TaskWaker {
+    handle: Handle,
+    index: usize,
+    generation: u64,
+    /// 已经投递了轮询，尚未执行
+    queued: AtomicBool,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if !self.queued.swap(true, Ordering::SeqCst) {
+            let t = self.clone();
+            // 循环已经销毁时任务随之丢弃
+            self.handle.post(move || {
+                t.queued.store(false, Ordering::SeqCst);
+                poll(t.index, t.generation);
+            });
+        }
+    }
+}
+
+/// `Handle` 可以作为 futures 0.3 的执行器，`SpawnExt` 的方法同样可用
+///
+/// # Examples
+/// ```
+/// extern crate futures;
+/// extern crate vnbase;
+///
+/// use futures::channel::oneshot;
+/// use futures::task::{SpawnError, SpawnExt};
+/// use std::sync::{Arc, Mutex};
+/// use std::thread;
+/// use std::time::Duration;
+/// use vnbase::run_loop;
+///
+/// # fn main() {
+/// let (tx, rx) = oneshot::channel();
+/// let _timer = run_loop::new_timer()
+///     .with_callback_once(move || tx.send(7).unwrap())
+///     .and_start(Duration::from_millis(10));
+///
+/// let result = Arc::new(Mutex::new(None));
+/// let (handle, r) = (run_loop::clone_handle(), result.clone());
+/// thread::spawn(move || {
+///     SpawnExt::spawn(&handle, record(rx, r)).unwrap();
+/// }).join().unwrap();
+/// run_loop::run();
+/// assert_eq!(*result.lock().unwrap(), Some(7));
+///
+/// let dead = thread::spawn(run_loop::clone_handle).join().unwrap();
+/// let err: SpawnError = SpawnExt::spawn(&dead, futures::future::ready(())).unwrap_err();
+/// assert!(err.is_shutdown());
+/// # }
+///
+/// // 测试按 2015 版编译，不能使用 async
+/// fn record(rx: oneshot::Receiver<u32>, r: Arc<Mutex<Option<u32>>>) -> impl futures::Future<Output = ()> {
+///     use futures::FutureExt;
+///     rx.map(move |v| {
+///         *r.lock().unwrap() = v.ok();
+///         run_loop::stop();
+///     })
+/// }
+/// ```
+#[cfg(feature = "futures")]
+impl ::futures::task::Spawn for Handle {
+    fn spawn_obj(&self, fut: ::futures::task::FutureObj<'static, ()>) -> Result<(), ::futures::task::SpawnError> {
+        spawn(self, Box::pin(fut)).map_err(|_| ::futures::task::SpawnError::shutdown())
+    }
+}
@@ -0,0 +1,99 @@
+use std::rc::{Rc, Weak};
+use std::cell::{RefCell, Cell};
+use std::time::Duration;
+
+use super::timer::Timer;
+
+/// 节流器，保证回调在每个间隔内最多执行一次
+///
+/// 冷却期外提交的函数会立即执行并开始冷却；冷却期内提交的函数只保留最后一个，
+/// 在冷却结束时执行。
+///
+/// # Examples
+/// ```
+/// use vnbase::run_loop;
+/// use std::rc::Rc;
+/// use std::cell::Cell;
+/// use std::time::Duration;
+///
+/// let count = Rc::new(Cell::new(0));
+/// let throttle = run_loop::Throttle::new(Duration::from_millis(50));
+///
+/// for i in 1..6 {
+///     let count = count.clone();
+///     throttle.submit(move || count.set(i));
+/// }
+/// // 第一次立即执行，其余的合并为最后一次
+/// assert_eq!(count.get(), 1);
+/// assert!(throttle.is_pending());
+///
+/// let _timer = run_loop::new_timer()
+///     .with_callback_once(run_loop::stop)
+///     .and_start(Duration::from_millis(100));
+/// run_loop::run();
+///
+/// assert_eq!(count.get(), 5);
+/// assert!(!throttle.is_pending());
+/// ```
+pub struct Throttle {
+    inner: Rc<Inner>,
+}
+
+impl Throttle {
+    pub fn new(interval: Duration) -> Self {
+        let inner = Rc::new(Inner {
+            interval: Cell::new(interval),
+            timer: Timer::new().with_cancel_on_drop(true),
+            pending: RefCell::new(None),
+        });
+        let weak: Weak<Inner> = Rc::downgrade(&inner);
+        inner.timer.set_callback(move || {
+            if let Some(inner) = weak.upgrade() {
+                inner.cooldown_end();
+            }
+        });
+        Throttle {
+            inner: inner,
+        }
+    }
+
+    /// 提交函数，冷却期外立即执行，否则替换等待中的函数
+    pub fn submit<T>(&self, f: T) where T: FnOnce() + 'static {
+        if self.inner.timer.is_active() {
+            *self.inner.pending.borrow_mut() = Some(Box::new(f));
+        }
+        else {
+            self.inner.timer.start(self.inner.interval.get());
+            f();
+        }
+    }
+
+    /// 是否有函数等待在冷却结束时执行
+    pub fn is_pending(&self) -> bool {
+        self.inner.pending.borrow().is_some()
+    }
+
+    pub fn set_interval(&self, interval: Duration) {
+        self.inner.interval.set(interval);
+    }
+
+    pub fn get_interval(&self) -> Duration {
+        self.inner.interval.get()
+    }
+}
+
+struct Inner {
+    interval: Cell<Duration>,
+    timer: Timer,
+    pending: RefCell<Option<Box<FnOnce()>>>,
+}
+
+impl Inner {
+    fn cooldown_end(&self) {
+        let pending = self.pending.borrow_mut().take();
+        if let Some(f) = pending {
+            self.timer.start(self.interval.get());
+            f();
+        }
+    }
+}
@@ -1,9 +1,15 @@
 
 use std::rc::Rc;
+use std::fmt;
+use std::ops::Deref;
 use std::cell::{RefCell, Cell};
-use std::time::{Instant, Duration};
+use std::time::Duration;
 
-use super::core::{TimedAction, TimedActionNode};
+use super::Instant;
+use super::core::{self, TimedAction, TimedActionNode};
+use super::context::Context;
+use super::ObjectWeak;
+use super::origin::Origin;
 
 /// 定时器
 /// 
@@ -22,6 +28,25 @@ use super::core::{TimedAction, TimedActionNode};
 /// println!("start.");
 /// run_loop::run();
 /// ```
+///
+/// 回调 panic 后定时器不再启动，回调已经随 panic 释放，重新设置回调后可以再次启动：
+/// ```
+/// use vnbase::run_loop;
+/// use std::panic;
+/// use std::time::Duration;
+///
+/// let timer = run_loop::new_timer()
+///     .with_callback(|| panic!("timer failed"))
+///     .and_start(Duration::from_millis(1));
+/// assert!(panic::catch_unwind(run_loop::run).is_err());
+/// assert!(!timer.is_active());
+///
+/// timer.set_callback_once(run_loop::stop);
+/// timer.start(Duration::from_millis(1));
+/// assert!(timer.is_active());
+/// run_loop::run();
+/// assert!(!timer.is_active());
+/// ```
 pub struct Timer {
     data: Rc<Data>,
     cancel_on_drop: Cell<bool>,
@@ -34,50 +59,278 @@ impl Timer {
                 n: TimedActionNode::new(),
                 i: RefCell::new(Inner {
                     state: State::None,
+                    interval: None,
                     act: None,
+                    armed: None,
+                    deadline: None,
+                    origin: Origin::default(),
                 }),
             }),
             cancel_on_drop: Cell::new(false),
         }
     }
 
+    #[cfg_attr(feature = "debug-origin", track_caller)]
     pub fn with_callback<T>(self, cb: T) -> Self where T: FnMut() + 'static {
         self.set_callback(cb);
         self
     }
 
+    #[cfg_attr(feature = "debug-origin", track_caller)]
     pub fn with_callback_once<T>(self, cb: T) -> Self where T: FnOnce() + 'static {
         self.set_callback_once(cb);
         self
     }
 
+    /// 使用已经装箱的回调，可以先把不同的回调放在同一个容器中再创建定时器
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::time::Duration;
+    ///
+    /// let callbacks: Vec<Box<FnMut()>> = vec![
+    ///     Box::new(|| println!("tick")),
+    ///     Box::new(run_loop::stop),
+    /// ];
+    /// let timers: Vec<_> = callbacks.into_iter().enumerate().map(|(i, cb)| {
+    ///     run_loop::new_timer()
+    ///         .with_callback_boxed(cb)
+    ///         .and_start(Duration::from_millis(10 * (i as u64 + 1)))
+    /// }).collect();
+    ///
+    /// run_loop::run();
+    /// assert!(timers.iter().all(|t| !t.is_active()));
+    /// ```
+    #[cfg_attr(feature = "debug-origin", track_caller)]
+    pub fn with_callback_boxed(self, cb: Box<FnMut()>) -> Self {
+        self.set_callback_boxed(cb);
+        self
+    }
+
+    /// 设置接收循环上下文的回调，见 `Context`
+    #[cfg_attr(feature = "debug-origin", track_caller)]
+    pub fn with_callback_ctx<T>(self, cb: T) -> Self where T: FnMut(&Context) + 'static {
+        self.set_callback_ctx(cb);
+        self
+    }
+
+    /// 每次执行时提升 weak，对象存在时以对象调用 f，已经释放时取消定时器
+    ///
+    /// 对象需要属于当前线程的循环，否则视为已经释放。
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::cell::Cell;
+    /// use std::time::Duration;
+    ///
+    /// let obj = run_loop::new_object(Cell::new(0));
+    /// let timer = run_loop::new_timer()
+    ///     .with_weak_callback(obj.downgrade(), |n| n.set(n.get() + 1))
+    ///     .and_start(Duration::from_millis(10));
+    /// let _stop = run_loop::new_timer()
+    ///     .with_callback_once(run_loop::stop)
+    ///     .and_start(Duration::from_millis(50));
+    /// run_loop::run();
+    /// assert_eq!(obj.get_ref().unwrap().get(), 1);
+    ///
+    /// let weak = obj.downgrade();
+    /// timer.set_weak_callback(weak, |_| unreachable!());
+    /// timer.start(Duration::from_millis(10));
+    /// drop(obj);
+    /// let _stop = run_loop::new_timer()
+    ///     .with_callback_once(run_loop::stop)
+    ///     .and_start(Duration::from_millis(50));
+    /// run_loop::run();
+    /// assert!(!timer.is_active());
+    /// ```
+    #[cfg_attr(feature = "debug-origin", track_caller)]
+    pub fn with_weak_callback<T, F>(self, weak: ObjectWeak<T>, f: F) -> Self where T: ?Sized + 'static, F: FnMut(&T) + 'static {
+        self.set_weak_callback(weak, f);
+        self
+    }
+
+    /// 以 period 为间隔重复执行 count 次，count 为 0 时不执行
+    ///
+    /// 第一次执行的时间由 `start` 决定，之后每次从上一次预定的时间开始计算，回调的耗时不会累积。
+    ///
+    /// # Examples
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::rc::Rc;
+    /// use std::cell::{Cell, RefCell};
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// let count = Rc::new(Cell::new(0));
+    /// let deadlines = Rc::new(RefCell::new(Vec::new()));
+    /// let period = Duration::from_millis(10);
+    /// let (c, d) = (count.clone(), deadlines.clone());
+    /// let _timer = run_loop::new_timer()
+    ///     .with_interval_count(period, 3, move || {
+    ///         c.set(c.get() + 1);
+    ///         thread::sleep(Duration::from_millis(5));
+    ///         let d = d.clone();
+    ///         run_loop::yield_now(move || {
+    ///             d.borrow_mut().push(run_loop::next_timer_deadline().unwrap());
+    ///         });
+    ///     })
+    ///     .and_start(period);
+    /// let first = run_loop::next_timer_deadline().unwrap();
+    ///
+    /// let _stop = run_loop::new_timer()
+    ///     .with_callback_once(run_loop::stop)
+    ///     .and_start(Duration::from_millis(100));
+    /// run_loop::run();
+    ///
+    /// assert_eq!(count.get(), 3);
+    /// assert_eq!(deadlines.borrow()[0], first + period);
+    /// assert_eq!(deadlines.borrow()[1], first + period * 2);
+    /// ```
+    #[cfg_attr(feature = "debug-origin", track_caller)]
+    pub fn with_interval_count<T>(self, period: Duration, count: u32, cb: T) -> Self where T: FnMut() + 'static {
+        self.set_interval_count(period, count, cb);
+        self
+    }
+
     pub fn with_cancel_on_drop(self, cancel_on_drop: bool) -> Self {
         self.cancel_on_drop.set(cancel_on_drop);
         self
     }
 
+    /// 设置优先级，到期时间相同时优先级高的先执行，默认为 0
+    ///
+    /// 相同优先级的按启动的先后执行。到期时间很少完全相同，通常和 `set_timer_coalescing` 一起使用。
+    /// 使用时间轮时同时到期的定时器之间不保证先后，不受优先级影响。
+    ///
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
+    /// use std::time::Duration;
+    ///
+    /// run_loop::set_timer_coalescing(Duration::from_millis(50));
+    /// let log = Rc::new(RefCell::new(Vec::new()));
+    /// let timers: Vec<_> = [("low", -1), ("normal", 0), ("high", 10), ("normal 2", 0)].iter().map(|&(name, p)| {
+    ///     let log = log.clone();
+    ///     run_loop::new_timer()
+    ///         .with_priority(p)
+    ///         .with_callback_once(move || log.borrow_mut().push(name))
+    ///         .and_start(Duration::from_millis(1))
+    /// }).collect();
+    /// let _stop = run_loop::new_timer().with_priority(-100).with_callback_once(run_loop::stop).and_start(Duration::from_millis(1));
+    /// run_loop::run();
+    /// run_loop::set_timer_coalescing(Duration::from_secs(0));
+    ///
+    /// assert_eq!(*log.borrow(), vec!["high", "normal", "normal 2", "low"]);
+    /// assert_eq!(timers[2].get_priority(), 10);
+    /// ```
+    pub fn with_priority(self, priority: i32) -> Self {
+        self.set_priority(priority);
+        self
+    }
+
     pub fn and_start(self, time: Duration) -> Self {
         self.start(time);
         self
     }
 
+    /// 转为释放时总会取消的定时器，不受 `cancel_on_drop` 影响
+    ///
+    /// ```
+    /// use vnbase::run_loop::{self, TimerGuard};
+    /// use std::time::Duration;
+    ///
+    /// struct Session {
+    ///     _timeout: TimerGuard,
+    /// }
+    ///
+    /// let before = run_loop::active_timer_count();
+    /// let session = Session {
+    ///     _timeout: run_loop::new_timer()
+    ///         .with_callback(|| println!("session timeout"))
+    ///         .and_start(Duration::from_secs(30))
+    ///         .guard(),
+    /// };
+    /// assert!(session._timeout.is_active());
+    /// drop(session);
+    /// assert_eq!(run_loop::active_timer_count(), before);
+    /// ```
+    pub fn guard(self) -> TimerGuard {
+        TimerGuard(self)
+    }
+
+    #[cfg_attr(feature = "debug-origin", track_caller)]
     pub fn set_callback<T>(&self, cb: T) where T: FnMut() + 'static {
         let mut inner = self.data.i.borrow_mut();
-        inner.act = Some(Box::new(cb));
+        inner.interval = None;
+        inner.act = Some(Callback::Action(Box::new(cb)));
+        inner.origin = Origin::caller();
+    }
+
+    /// 直接保存已经装箱的回调，不会再次分配
+    #[cfg_attr(feature = "debug-origin", track_caller)]
+    pub fn set_callback_boxed(&self, cb: Box<FnMut()>) {
+        let mut inner = self.data.i.borrow_mut();
+        inner.interval = None;
+        inner.act = Some(Callback::Boxed(cb));
+        inner.origin = Origin::caller();
+    }
+
+    #[cfg_attr(feature = "debug-origin", track_caller)]
+    pub fn set_callback_ctx<T>(&self, mut cb: T) where T: FnMut(&Context) + 'static {
+        self.set_callback(move || cb(&Context::new()));
+    }
+
+    #[cfg_attr(feature = "debug-origin", track_caller)]
+    pub fn set_weak_callback<T, F>(&self, weak: ObjectWeak<T>, f: F) where T: ?Sized + 'static, F: FnMut(&T) + 'static {
+        let mut inner = self.data.i.borrow_mut();
+        inner.interval = None;
+        inner.act = Some(Callback::Action(Box::new(WeakCallback { weak: weak, f: f })));
+        inner.origin = Origin::caller();
     }
 
+    #[cfg_attr(feature = "debug-origin", track_caller)]
     pub fn set_callback_once<T>(&self, cb: T) where T: FnOnce() + 'static {
         let mut inner = self.data.i.borrow_mut();
-        inner.act = Some(Box::new(Some(cb)));
+        inner.interval = None;
+        inner.act = Some(Callback::Action(Box::new(Some(cb))));
+        inner.origin = Origin::caller();
+    }
+
+    #[cfg_attr(feature = "debug-origin", track_caller)]
+    pub fn set_interval_count<T>(&self, period: Duration, count: u32, cb: T) where T: FnMut() + 'static {
+        let mut inner = self.data.i.borrow_mut();
+        inner.interval = Some(period);
+        inner.origin = Origin::caller();
+        inner.act = if count == 0 {
+            None
+        }
+        else {
+            Some(Callback::Action(Box::new(Counted { remaining: count, f: cb })))
+        };
     }
 
     pub fn is_active(&self) -> bool {
-        match self.data.i.borrow().state {
-            State::Active | State::Restart(_) => true,
-            _ => false,
+        matches!(self.data.i.borrow().state, State::Active | State::Restart(_))
+    }
+
+    pub fn set_priority(&self, priority: i32) {
+        let node = &self.data.n;
+        if node.priority.replace(priority) == priority {
+            return;
+        }
+        if let State::Active = self.data.i.borrow().state {
+            // 调整在堆中的位置
+            super::adjust_timed_action(node, node.time.get());
         }
     }
 
+    pub fn get_priority(&self) -> i32 {
+        self.data.n.priority.get()
+    }
+
     pub fn set_cancel_on_drop(&self, cancel_on_drop: bool) {
         self.cancel_on_drop.set(cancel_on_drop);
     }
@@ -86,27 +339,170 @@ impl Timer {
         self.cancel_on_drop.get()
     }
 
+    /// 在 time 之后触发，已经启动时重新计时
+    ///
+    /// time 超过约一百年时按一百年计算，`Duration::MAX` 可以表示永不触发。
+    ///
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::cell::Cell;
+    /// use std::rc::Rc;
+    /// use std::time::Duration;
+    ///
+    /// let fired = Rc::new(Cell::new(false));
+    /// let f = fired.clone();
+    /// let timer = run_loop::new_timer().with_callback(move || f.set(true)).and_start(Duration::MAX);
+    /// timer.start(Duration::MAX);
+    /// let schedule = run_loop::new_schedule()
+    ///     .with_period(Duration::MAX)
+    ///     .with_callback(|_| panic!("should never tick"))
+    ///     .and_start();
+    /// assert!(run_loop::next_wait_duration().unwrap() > Duration::from_secs(365 * 24 * 3600));
+    ///
+    /// let _stop = run_loop::new_timer().with_callback_once(run_loop::stop).and_start(Duration::from_millis(20));
+    /// run_loop::run();
+    /// assert!(!fired.get());
+    /// assert!(timer.is_active() && schedule.is_active());
+    ///
+    /// timer.cancel();
+    /// schedule.cancel();
+    /// assert!(!timer.is_active() && !schedule.is_active());
+    /// assert_eq!(run_loop::next_timer_deadline(), None);
+    /// ```
     pub fn start(&self, time: Duration) {
         let mut inner = self.data.i.borrow_mut();
+        let now = Instant::now();
+        let deadline = core::saturating_deadline(now, time);
+        inner.armed = Some(now);
+        inner.deadline = Some(deadline);
         match inner.state {
             State::None => {
-                super::push_timed_action(self.data.clone(), Instant::now() + time);
+                super::push_timed_action(self.data.clone(), deadline);
+                inner.state = State::Active;
             },
             State::Active => {
-                super::adjust_timed_action(&self.data.n, Instant::now() + time);
+                super::adjust_timed_action(&self.data.n, deadline);
             },
             State::Processing | State::Restart(_) => {
-                inner.state = State::Restart(Instant::now() + time);
+                inner.state = State::Restart(deadline);
             },
         }
     }
 
+    /// 距离最近一次启动经过的时间，未启动、已经取消或者已经触发时返回 `None`
+    ///
+    /// 每次 `start` 重新计时，重复执行的定时器每次重新计时后也从头计算。
+    /// 在回调中调用时得到的是本次触发对应的启动时间。
+    ///
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// let timer = run_loop::new_timer().with_callback_once(|| {});
+    /// assert_eq!(timer.elapsed_since_armed(), None);
+    ///
+    /// timer.start(Duration::from_secs(60));
+    /// thread::sleep(Duration::from_millis(20));
+    /// assert!(timer.elapsed_since_armed().unwrap() >= Duration::from_millis(20));
+    ///
+    /// // 重新启动时从头计算
+    /// timer.start(Duration::from_millis(1));
+    /// assert!(timer.elapsed_since_armed().unwrap() < Duration::from_millis(20));
+    ///
+    /// // 触发后不再计时
+    /// let _stop = run_loop::new_timer().with_callback_once(run_loop::stop).and_start(Duration::from_millis(10));
+    /// run_loop::run();
+    /// assert_eq!(timer.elapsed_since_armed(), None);
+    ///
+    /// timer.start(Duration::from_secs(60));
+    /// timer.cancel();
+    /// assert_eq!(timer.elapsed_since_armed(), None);
+    /// ```
+    pub fn elapsed_since_armed(&self) -> Option<Duration> {
+        self.data.i.borrow().armed.map(|t| t.elapsed())
+    }
+
+    /// 取消定时器，未启动、已经触发或者已经取消时不做处理
+    ///
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::time::Duration;
+    ///
+    /// let before = run_loop::active_timer_count();
+    /// let a = run_loop::new_timer().with_callback(|| {}).and_start(Duration::from_secs(60));
+    /// let b = run_loop::new_timer().with_callback(|| {}).and_start(Duration::from_secs(30));
+    /// let c = run_loop::new_timer().with_callback(|| {}).and_start(Duration::from_secs(10));
+    ///
+    /// // 重复取消
+    /// a.cancel();
+    /// a.cancel();
+    /// assert_eq!(run_loop::active_timer_count(), before + 2);
+    ///
+    /// // 取消后重新启动、调整
+    /// a.start(Duration::from_secs(5));
+    /// a.start(Duration::from_secs(20));
+    /// assert_eq!(run_loop::active_timer_count(), before + 3);
+    ///
+    /// // 触发后取消
+    /// let fired = run_loop::new_timer().with_callback_once(|| {}).and_start(Duration::from_millis(1));
+    /// let _stop = run_loop::new_timer().with_callback_once(run_loop::stop).and_start(Duration::from_millis(10));
+    /// run_loop::run();
+    /// fired.cancel();
+    /// fired.cancel();
+    /// assert_eq!(run_loop::active_timer_count(), before + 3);
+    ///
+    /// // 其余定时器仍然按顺序到期
+    /// assert!(run_loop::next_timer_deadline().unwrap() <= std::time::Instant::now() + Duration::from_secs(10));
+    /// c.cancel();
+    /// b.cancel();
+    /// a.cancel();
+    /// assert_eq!(run_loop::active_timer_count(), before);
+    /// ```
+    ///
+    /// 同时到期的定时器中，先执行的回调取消或者重新启动的定时器不会在本轮触发：
+    /// ```
+    /// use vnbase::run_loop;
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// let log = Rc::new(RefCell::new(Vec::new()));
+    /// let timer = |name: &'static str| {
+    ///     let log = log.clone();
+    ///     Rc::new(run_loop::new_timer().with_callback(move || log.borrow_mut().push(name)))
+    /// };
+    /// let (a, b, c) = (timer("a"), timer("b"), timer("c"));
+    /// let (b2, c2) = (b.clone(), c.clone());
+    /// a.set_callback_once(move || {
+    ///     b2.cancel();
+    ///     c2.start(Duration::from_millis(50));
+    /// });
+    /// a.start(Duration::from_millis(1));
+    /// b.start(Duration::from_millis(2));
+    /// c.start(Duration::from_millis(3));
+    ///
+    /// // 让三个定时器在同一轮到期
+    /// thread::sleep(Duration::from_millis(10));
+    /// let _stop = run_loop::new_timer().with_callback_once(run_loop::stop).and_start(Duration::from_millis(20));
+    /// run_loop::run();
+    /// assert!(log.borrow().is_empty());
+    /// assert!(!b.is_active());
+    /// assert!(c.is_active());
+    ///
+    /// let _stop = run_loop::new_timer().with_callback_once(run_loop::stop).and_start(Duration::from_millis(100));
+    /// run_loop::run();
+    /// assert_eq!(*log.borrow(), vec!["c"]);
+    /// ```
     pub fn cancel(&self) {
         let mut inner = self.data.i.borrow_mut();
         match inner.state {
             State::None | State::Processing => {},
             State::Active => {
                 super::remove_timed_action(&self.data.n);
+                inner.state = State::None;
+                inner.armed = None;
             },
             State::Restart(_) => {
                 inner.state = State::Processing;
@@ -115,6 +511,18 @@ impl Timer {
     }
 }
 
+/// 开启 `debug-origin` 特性时同时显示设置回调的位置
+impl fmt::Debug for Timer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut d = f.debug_struct("Timer");
+        d.field("active", &self.is_active());
+        d.field("priority", &self.get_priority());
+        #[cfg(feature = "debug-origin")]
+        d.field("origin", &self.data.i.borrow().origin.location().map(|loc| loc.to_string()));
+        d.finish()
+    }
+}
+
 impl Drop for Timer {
     fn drop(&mut self) {
         if self.cancel_on_drop.get() {
@@ -123,6 +531,23 @@ impl Drop for Timer {
     }
 }
 
+/// 释放时取消的定时器，见 `Timer::guard`
+pub struct TimerGuard (Timer);
+
+impl Deref for TimerGuard {
+    type Target = Timer;
+
+    fn deref(&self) -> &Timer {
+        &self.0
+    }
+}
+
+impl Drop for TimerGuard {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
 
 
 struct Data {
@@ -132,7 +557,14 @@ struct Data {
 
 struct Inner {
     state: State,
-    act: Option<Box<Action>>,
+    interval: Option<Duration>,
+    act: Option<Callback>,
+    /// 最近一次启动的时间，用于 `elapsed_since_armed`
+    armed: Option<Instant>,
+    /// 最近一次启动时预定的触发时间，重复执行时下一次从它开始计算
+    deadline: Option<Instant>,
+    /// 设置回调的位置
+    origin: Origin,
 }
 
 enum State {
@@ -151,16 +583,35 @@ impl TimedAction for Data {
         let mut inner = self.i.borrow_mut();
         if let Some(mut f) = inner.act.take() {
             inner.state = State::Processing;
+            inner.origin.enter();
             drop(inner);
-            let ok = f.call();
+            // 回调 panic 时把状态恢复为 None，之后可以重新设置回调和启动
+            let ok = core::reset_on_unwind(&self.i, |inner| {
+                inner.state = State::None;
+                inner.armed = None;
+            }, || f.call());
             inner = self.i.borrow_mut();
             if inner.act.is_none() && ok {
                 inner.act = Some(f);
             }
             match inner.state {
                 State::Processing => {
-                    inner.state = State::None;
-                    None
+                    match inner.interval {
+                        Some(period) if ok => {
+                            // 从上一次预定的时间开始计算，回调的延迟不会累积
+                            let last = inner.deadline.unwrap_or_else(Instant::now);
+                            let next = core::saturating_deadline(last, period);
+                            inner.state = State::Active;
+                            inner.armed = Some(last);
+                            inner.deadline = Some(next);
+                            Some(next)
+                        },
+                        _ => {
+                            inner.state = State::None;
+                            inner.armed = None;
+                            None
+                        },
+                    }
                 },
                 State::Restart(t) => {
                     inner.state = State::Active;
@@ -171,6 +622,7 @@ impl TimedAction for Data {
         }
         else {
             inner.state = State::None;
+            inner.armed = None;
             None
         }
     }
@@ -180,6 +632,24 @@ trait Action {
     fn call(&mut self) -> bool;
 }
 
+enum Callback {
+    Action(Box<Action>),
+    /// 调用者已经装箱的回调
+    Boxed(Box<FnMut()>),
+}
+
+impl Callback {
+    fn call(&mut self) -> bool {
+        match *self {
+            Callback::Action(ref mut act) => act.call(),
+            Callback::Boxed(ref mut f) => {
+                f();
+                true
+            },
+        }
+    }
+}
+
 impl<T> Action for T where T: FnMut() {
     fn call(&mut self) -> bool {
         self();
@@ -196,6 +666,39 @@ impl<T> Action for Option<T> where T: FnOnce() {
     }
 }
 
+struct Counted<T> {
+    remaining: u32,
+    f: T,
+}
+
+impl<T> Action for Counted<T> where T: FnMut() {
+    fn call(&mut self) -> bool {
+        (self.f)();
+        self.remaining -= 1;
+        self.remaining != 0
+    }
+}
+
+struct WeakCallback<T: ?Sized + 'static, F> {
+    weak: ObjectWeak<T>,
+    f: F,
+}
+
+impl<T: ?Sized, F> Action for WeakCallback<T, F> where F: FnMut(&T) {
+    fn call(&mut self) -> bool {
+        match self.weak.upgrade() {
+            Some(handle) => match handle.get_ref() {
+                Some(obj) => {
+                    (self.f)(obj);
+                    true
+                },
+                None => false,
+            },
+            None => false,
+        }
+    }
+}
+
 /*
 struct ActionOnce<T> {
     once: Option<T>,
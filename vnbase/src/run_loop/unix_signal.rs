@@ -0,0 +1,213 @@
+//! 把 Unix 信号转为循环上的回调，需要开启 `signal` 特性，见 `run_loop::on_signal`
+//!
+//! 信号处理函数只向自管道写入信号编号。专门的线程从管道读出编号，
+//! 向注册了该信号的循环投递，回调在注册的线程执行。
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr;
+use std::rc::Rc;
+use std::sync::Mutex;
+use std::sync::Once;
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use std::thread;
+
+use libc;
+
+use super::Handle;
+
+/// Unix 信号编号
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Signal(libc::c_int);
+
+impl Signal {
+    pub const HUP: Signal = Signal(libc::SIGHUP);
+    pub const INT: Signal = Signal(libc::SIGINT);
+    pub const QUIT: Signal = Signal(libc::SIGQUIT);
+    pub const TERM: Signal = Signal(libc::SIGTERM);
+    pub const USR1: Signal = Signal(libc::SIGUSR1);
+    pub const USR2: Signal = Signal(libc::SIGUSR2);
+    pub const CHLD: Signal = Signal(libc::SIGCHLD);
+    pub const WINCH: Signal = Signal(libc::SIGWINCH);
+
+    /// 使用其它信号编号，不能是 `SIGKILL` 和 `SIGSTOP`
+    pub fn from_raw(signum: libc::c_int) -> Signal {
+        Signal(signum)
+    }
+
+    pub fn as_raw(&self) -> libc::c_int {
+        self.0
+    }
+}
+
+/// 注册的信号回调，释放时注销；同一个信号的最后一个注册注销时恢复注册前的处理方式
+///
+/// 回调保存在注册的线程，因此不是 `Send`。
+pub struct SignalHandle {
+    id: u64,
+    signal: Signal,
+    phantom: PhantomData<Rc<()>>,
+}
+
+impl Drop for SignalHandle {
+    fn drop(&mut self) {
+        // 循环所在的线程正在结束时回调已经随之释放
+        let _ = CALLBACKS.try_with(|cbs| cbs.borrow_mut().remove(&self.id));
+        let mut entries = ENTRIES.lock().unwrap();
+        if let Some(pos) = entries.iter().position(|e| e.signal == self.signal) {
+            entries[pos].regs.retain(|&(id, _)| id != self.id);
+            if entries[pos].regs.is_empty() {
+                let entry = entries.swap_remove(pos);
+                unsafe { libc::sigaction(entry.signal.0, &entry.prev, ptr::null_mut()); }
+            }
+        }
+    }
+}
+
+/// 一个信号的所有注册
+struct Entry {
+    signal: Signal,
+    /// 注册前的处理方式
+    prev: libc::sigaction,
+    regs: Vec<(u64, Handle)>,
+}
+
+unsafe impl Send for Entry {}
+
+static ENTRIES: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+/// 自管道的写端，信号处理函数只使用它
+static WRITE_FD: AtomicI32 = AtomicI32::new(-1);
+static INIT: Once = Once::new();
+
+type Callback = Box<FnMut()>;
+
+thread_local! {
+    /// 本线程注册的回调
+    static CALLBACKS: RefCell<HashMap<u64, Option<Callback>>> = RefCell::new(HashMap::new());
+}
+
+pub fn register(signal: Signal, callback: Callback) -> io::Result<SignalHandle> {
+    init()?;
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let mut entries = ENTRIES.lock().unwrap();
+    if !entries.iter().any(|e| e.signal == signal) {
+        let prev = install(signal)?;
+        entries.push(Entry {
+            signal: signal,
+            prev: prev,
+            regs: Vec::new(),
+        });
+    }
+    CALLBACKS.with(|cbs| cbs.borrow_mut().insert(id, Some(callback)));
+    let entry = entries.iter_mut().find(|e| e.signal == signal).unwrap();
+    entry.regs.push((id, super::clone_handle()));
+    Ok(SignalHandle {
+        id: id,
+        signal: signal,
+        phantom: PhantomData,
+    })
+}
+
+/// 创建自管道和读取管道的线程
+fn init() -> io::Result<()> {
+    let mut result = Ok(());
+    INIT.call_once(|| {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            result = Err(io::Error::last_os_error());
+            return;
+        }
+        unsafe {
+            libc::fcntl(fds[0], libc::F_SETFD, libc::FD_CLOEXEC);
+            libc::fcntl(fds[1], libc::F_SETFD, libc::FD_CLOEXEC);
+            // 管道满时丢弃，处理函数不能阻塞；读出的线程只需要知道信号到达过
+            libc::fcntl(fds[1], libc::F_SETFL, libc::O_NONBLOCK);
+        }
+        WRITE_FD.store(fds[1], Ordering::SeqCst);
+        let read = fds[0];
+        thread::Builder::new()
+            .name(String::from("vnbase-signal"))
+            .spawn(move || dispatch_thread(read))
+            .expect("failed to spawn signal thread");
+    });
+    result?;
+    if WRITE_FD.load(Ordering::SeqCst) < 0 {
+        return Err(io::Error::other("signal pipe is not available"));
+    }
+    Ok(())
+}
+
+/// 安装处理函数，返回之前的处理方式
+fn install(signal: Signal) -> io::Result<libc::sigaction> {
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = handler as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        let mut prev: libc::sigaction = mem::zeroed();
+        if libc::sigaction(signal.0, &action, &mut prev) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(prev)
+    }
+}
+
+extern "C" fn handler(signum: libc::c_int) {
+    // 只做异步信号安全的操作：写管道，并保留被打断的代码的 errno
+    unsafe {
+        let errno = *errno_location();
+        let byte = signum as u8;
+        libc::write(WRITE_FD.load(Ordering::Relaxed), &byte as *const u8 as *const libc::c_void, 1);
+        *errno_location() = errno;
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+unsafe fn errno_location() -> *mut libc::c_int {
+    libc::__errno_location()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+unsafe fn errno_location() -> *mut libc::c_int {
+    libc::__error()
+}
+
+/// 读出信号编号，投递到注册了该信号的循环
+fn dispatch_thread(read: libc::c_int) {
+    let mut buf = [0u8; 64];
+    loop {
+        let n = unsafe { libc::read(read, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if n < 0 {
+            if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return;
+        }
+        let entries = ENTRIES.lock().unwrap();
+        for &signum in &buf[..n as usize] {
+            if let Some(entry) = entries.iter().find(|e| e.signal.0 as u8 == signum) {
+                for &(id, ref handle) in &entry.regs {
+                    handle.post(move || invoke(id));
+                }
+            }
+        }
+    }
+}
+
+/// 在注册的线程执行回调，回调中可以释放自己的 `SignalHandle`
+fn invoke(id: u64) {
+    let cb = CALLBACKS.with(|cbs| cbs.borrow_mut().get_mut(&id).and_then(|cb| cb.take()));
+    if let Some(mut cb) = cb {
+        cb();
+        CALLBACKS.with(|cbs| {
+            if let Some(slot) = cbs.borrow_mut().get_mut(&id) {
+                if slot.is_none() {
+                    *slot = Some(cb);
+                }
+            }
+        });
+    }
+}
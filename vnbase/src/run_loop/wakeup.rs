@@ -0,0 +1,138 @@
+//! 唤醒阻塞在 `poll` 中的循环
+//!
+//! Linux 上使用 eventfd，其它 unix 平台使用自管道，其余平台退回 `mio::Waker`。
+//! 多次唤醒在循环取走之前只写入一次。
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use mio::{Interest, Registry, Token};
+
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+#[cfg(unix)]
+use mio::unix::SourceFd;
+
+pub struct Wakeup {
+    #[cfg(unix)]
+    read: RawFd,
+    #[cfg(unix)]
+    write: RawFd,
+    #[cfg(not(unix))]
+    waker: ::mio::Waker,
+    signaled: AtomicBool,
+}
+
+unsafe impl Send for Wakeup {}
+unsafe impl Sync for Wakeup {}
+
+impl Wakeup {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn new(registry: &Registry, token: Token) -> io::Result<Wakeup> {
+        let fd = unsafe { ::libc::eventfd(0, ::libc::EFD_NONBLOCK | ::libc::EFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let wakeup = Wakeup {
+            read: fd,
+            write: fd,
+            signaled: AtomicBool::new(false),
+        };
+        registry.register(&mut SourceFd(&wakeup.read), token, Interest::READABLE)?;
+        Ok(wakeup)
+    }
+
+    #[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+    pub fn new(registry: &Registry, token: Token) -> io::Result<Wakeup> {
+        let mut fds = [0 as RawFd; 2];
+        if unsafe { ::libc::pipe(fds.as_mut_ptr()) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let wakeup = Wakeup {
+            read: fds[0],
+            write: fds[1],
+            signaled: AtomicBool::new(false),
+        };
+        for fd in fds.iter() {
+            unsafe {
+                let fl = ::libc::fcntl(*fd, ::libc::F_GETFL);
+                if fl < 0 || ::libc::fcntl(*fd, ::libc::F_SETFL, fl | ::libc::O_NONBLOCK) < 0
+                    || ::libc::fcntl(*fd, ::libc::F_SETFD, ::libc::FD_CLOEXEC) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+        registry.register(&mut SourceFd(&wakeup.read), token, Interest::READABLE)?;
+        Ok(wakeup)
+    }
+
+    #[cfg(not(unix))]
+    pub fn new(registry: &Registry, token: Token) -> io::Result<Wakeup> {
+        Ok(Wakeup {
+            waker: ::mio::Waker::new(registry, token)?,
+            signaled: AtomicBool::new(false),
+        })
+    }
+
+    /// 唤醒循环，在循环调用 `reset` 之前重复调用不会再次写入
+    pub fn wake(&self) -> io::Result<()> {
+        if self.signaled.swap(true, Ordering::AcqRel) {
+            return Ok(());
+        }
+        self.signal()
+    }
+
+    /// 由循环在收到唤醒事件后调用，清空已写入的数据
+    ///
+    /// 必须先读空再清除标记，否则清除后写入的数据可能被读掉而标记仍然保留，
+    /// 之后的唤醒都不会再写入。
+    pub fn reset(&self) {
+        self.drain();
+        self.signaled.store(false, Ordering::Release);
+    }
+
+    #[cfg(unix)]
+    fn signal(&self) -> io::Result<()> {
+        let buf = 1u64.to_ne_bytes();
+        let len = if self.read == self.write { 8 } else { 1 };
+        let n = unsafe { ::libc::write(self.write, buf.as_ptr() as *const _, len) };
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            // 缓冲区已满说明已经有未读取的唤醒
+            if err.kind() != io::ErrorKind::WouldBlock {
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn signal(&self) -> io::Result<()> {
+        self.waker.wake()
+    }
+
+    #[cfg(unix)]
+    fn drain(&self) {
+        let mut buf = [0u8; 64];
+        loop {
+            let n = unsafe { ::libc::read(self.read, buf.as_mut_ptr() as *mut _, buf.len()) };
+            if n <= 0 {
+                break;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn drain(&self) {}
+}
+
+#[cfg(unix)]
+impl Drop for Wakeup {
+    fn drop(&mut self) {
+        unsafe {
+            ::libc::close(self.read);
+            if self.write != self.read {
+                ::libc::close(self.write);
+            }
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! wasm32 上由宿主（浏览器或 Node）的事件循环驱动循环，见 `run_loop::run`
+//!
+//! 驱动方式和 `run_pending` 加外部唤醒函数相同：投递通过 `queueMicrotask` 唤醒，
+//! 一轮之后仍有消息时改用 `setTimeout(0)`，让页面有机会渲染和处理输入。
+//! 定时器和周期历程共用一个按最近到期时间设置的 `setTimeout`，
+//! 周期历程按自己的节拍计算下一次到期，`setTimeout` 的延迟不会累积。
+use std::cell::{Cell, RefCell};
+
+use wasm_bindgen::prelude::*;
+
+use super::{Instant, PumpStatus};
+
+/// `setTimeout` 能表示的最长延迟，超过时会立即触发
+const MAX_TIMEOUT_MS: f64 = 2147483647.0;
+
+/// 交给宿主的回调，`#[wasm_bindgen]` 在 2015 版中不接受参数里直接写 trait 对象，因此用别名
+type Callback = Closure<FnMut()>;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_name = queueMicrotask)]
+    fn queue_microtask(f: &Callback);
+    #[wasm_bindgen(js_name = setTimeout)]
+    fn set_timeout(f: &Callback, ms: f64) -> JsValue;
+    #[wasm_bindgen(js_name = clearTimeout)]
+    fn clear_timeout(id: &JsValue);
+}
+
+/// 交给宿主的回调只创建一次，停止后保留，再次 `run` 时重用
+struct Driver {
+    /// 微任务中处理一轮
+    task: Callback,
+    /// `setTimeout` 到期时处理一轮
+    timer: Callback,
+    active: Cell<bool>,
+    /// 已经排入微任务，尚未执行
+    queued: Cell<bool>,
+    /// 等待中的 `setTimeout` 及其对应的到期时间
+    timeout: RefCell<Option<(JsValue, Instant)>>,
+}
+
+thread_local! {
+    static DRIVER: Driver = Driver {
+        task: Closure::new(pump),
+        timer: Closure::new(|| {
+            DRIVER.with(|d| d.timeout.borrow_mut().take());
+            pump();
+        }),
+        active: Cell::new(false),
+        queued: Cell::new(false),
+        timeout: RefCell::new(None),
+    };
+}
+
+/// 开始由宿主驱动当前线程的循环，已经开始时不做处理
+pub fn start() {
+    let started = DRIVER.with(|d| !d.active.replace(true));
+    if started {
+        super::set_external_waker(wake);
+        wake();
+    }
+}
+
+/// 外部唤醒函数，只在当前线程调用
+fn wake() {
+    DRIVER.with(|d| {
+        if d.active.get() && !d.queued.replace(true) {
+            queue_microtask(&d.task);
+        }
+    })
+}
+
+fn pump() {
+    let active = DRIVER.with(|d| {
+        d.queued.set(false);
+        d.active.get()
+    });
+    if !active {
+        return;
+    }
+    let status = super::run_pending();
+    DRIVER.with(|d| {
+        match status {
+            PumpStatus::Stop => {
+                d.active.set(false);
+                d.cancel_timeout();
+                super::clear_external_waker();
+            },
+            PumpStatus::Wait => d.cancel_timeout(),
+            PumpStatus::WaitUntil(t) => d.arm(t),
+        }
+    })
+}
+
+impl Driver {
+    fn cancel_timeout(&self) {
+        if let Some((id, _)) = self.timeout.borrow_mut().take() {
+            clear_timeout(&id);
+        }
+    }
+
+    /// 让 `setTimeout` 在 deadline 触发，已经按同一时间设置时不做处理
+    fn arm(&self, deadline: Instant) {
+        let mut timeout = self.timeout.borrow_mut();
+        if let Some((_, t)) = *timeout {
+            if t == deadline {
+                return;
+            }
+        }
+        if let Some((id, _)) = timeout.take() {
+            clear_timeout(&id);
+        }
+        // 提前触发时 run_pending 仍返回同一时间，由 timer 清除记录后重新设置
+        let ms = deadline.saturating_duration_since(Instant::now()).as_secs_f64() * 1000.0;
+        let id = set_timeout(&self.timer, ms.ceil().min(MAX_TIMEOUT_MS));
+        *timeout = Some((id, deadline));
+    }
+}
@@ -0,0 +1,345 @@
+//! 分层时间轮，定时器很多时代替二叉堆
+//!
+//! 时间按固定的精度分为刻度，共 6 层，每层 64 个槽，第 n 层的一个槽覆盖 64^n 个刻度。
+//! 定时器按到期的刻度放入能容纳它的最低一层；高层的槽到期时，其中的定时器按剩余的时间重新放入低层。
+//! 到期时间向上取整到刻度，定时器不会提前触发，最多推迟一个精度。
+use std::mem;
+use std::rc::Rc;
+use std::time::Duration;
+
+use super::Instant;
+use super::core::TimedAction;
+
+const LEVELS: usize = 6;
+const SLOT_BITS: usize = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+/// 一圈时间轮覆盖的刻度，更远的定时器先放在最高层，到时再重新计算
+const MAX_TICKS: u64 = (1 << (LEVELS * SLOT_BITS)) - 1;
+/// 已经到期、等待处理的定时器所在的位置
+const PENDING: usize = LEVELS * SLOTS;
+
+pub struct TimerWheel {
+    origin: Instant,
+    resolution: Duration,
+    res_nanos: u128,
+    /// 已经处理到的刻度
+    elapsed: u64,
+    levels: Vec<Level>,
+    pending: Vec<Rc<TimedAction>>,
+    len: usize,
+}
+
+struct Level {
+    /// 非空的槽
+    occupied: u64,
+    slots: Vec<Vec<Rc<TimedAction>>>,
+}
+
+impl TimerWheel {
+    pub fn new(resolution: Duration) -> TimerWheel {
+        let resolution = resolution.max(Duration::from_micros(1));
+        TimerWheel {
+            origin: Instant::now(),
+            resolution: resolution,
+            res_nanos: resolution.as_nanos(),
+            elapsed: 0,
+            levels: (0..LEVELS).map(|_| Level {
+                occupied: 0,
+                slots: (0..SLOTS).map(|_| Vec::new()).collect(),
+            }).collect(),
+            pending: Vec::new(),
+            len: 0,
+        }
+    }
+
+    pub fn resolution(&self) -> Duration {
+        self.resolution
+    }
+
+    pub fn push(&mut self, act: Rc<TimedAction>, time: Instant) {
+        act.node().time.set(time);
+        self.insert(act);
+        self.len += 1;
+    }
+
+    /// 返回一个在 time 之前到期的定时器，到期的定时器之间不保证先后
+    pub fn peek(&mut self, time: Instant) -> Option<Rc<TimedAction>> {
+        let now = self.floor_tick(time);
+        loop {
+            if let Some(act) = self.pending.last() {
+                return Some(act.clone());
+            }
+            match self.next_expiration() {
+                Some((level, slot, deadline)) if deadline <= now => {
+                    self.elapsed = deadline;
+                    self.levels[level].occupied &= !(1 << slot);
+                    let entries = mem::take(&mut self.levels[level].slots[slot]);
+                    for act in entries {
+                        self.insert(act);
+                    }
+                },
+                _ => {
+                    // 此前没有需要处理的槽，直接前进不会破坏各层的位置
+                    if now > self.elapsed {
+                        self.elapsed = now;
+                    }
+                    return None;
+                },
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn count_periodic(&self) -> usize {
+        self.iter().filter(|ta| ta.is_periodic()).count()
+    }
+
+    /// 下一次需要处理的时刻，可能是高层的槽需要重新分配的时刻，不晚于最早的到期时间
+    pub fn peek_time(&self) -> Option<Instant> {
+        if !self.pending.is_empty() {
+            return Some(self.instant(self.elapsed));
+        }
+        self.next_expiration().map(|(_, _, deadline)| self.instant(deadline))
+    }
+
+    pub fn adjust(&mut self, node: &super::core::TimedActionNode, time: Instant) {
+        let act = self.take(node);
+        act.node().time.set(time);
+        self.insert(act);
+    }
+
+    pub fn remove(&mut self, node: &super::core::TimedActionNode) {
+        self.take(node);
+        self.len -= 1;
+    }
+
+    /// 取出所有定时器，到期时间保留在节点中
+    pub fn drain(&mut self) -> Vec<Rc<TimedAction>> {
+        let mut all = mem::take(&mut self.pending);
+        for level in self.levels.iter_mut() {
+            level.occupied = 0;
+            for slot in level.slots.iter_mut() {
+                all.append(slot);
+            }
+        }
+        self.len = 0;
+        all
+    }
+
+    fn iter<'a>(&'a self) -> impl Iterator<Item = &'a Rc<TimedAction>> + 'a {
+        self.pending.iter().chain(self.levels.iter().flat_map(|l| l.slots.iter().flat_map(|s| s.iter())))
+    }
+
+    fn insert(&mut self, act: Rc<TimedAction>) {
+        let deadline = self.ceil_tick(act.node().time.get());
+        let list = if deadline <= self.elapsed {
+            act.node().slot.set(PENDING);
+            &mut self.pending
+        }
+        else {
+            let level = level_for(self.elapsed, deadline);
+            let slot = ((deadline >> (level * SLOT_BITS)) as usize) & (SLOTS - 1);
+            act.node().slot.set(level * SLOTS + slot);
+            let l = &mut self.levels[level];
+            l.occupied |= 1 << slot;
+            &mut l.slots[slot]
+        };
+        act.node().index.set(list.len());
+        list.push(act);
+    }
+
+    fn take(&mut self, node: &super::core::TimedActionNode) -> Rc<TimedAction> {
+        let pos = node.slot.get();
+        let index = node.index.get();
+        let list = if pos == PENDING {
+            &mut self.pending
+        }
+        else {
+            &mut self.levels[pos / SLOTS].slots[pos % SLOTS]
+        };
+        debug_assert!(list.get(index).is_some_and(|ta| ::std::ptr::eq(ta.node(), node)),
+            "timed action is not in the wheel");
+        let act = list.swap_remove(index);
+        if let Some(moved) = list.get(index) {
+            moved.node().index.set(index);
+        }
+        if list.is_empty() && pos != PENDING {
+            self.levels[pos / SLOTS].occupied &= !(1 << (pos % SLOTS));
+        }
+        act
+    }
+
+    /// 最早需要处理的槽：所在的层、槽和开始的刻度
+    fn next_expiration(&self) -> Option<(usize, usize, u64)> {
+        for (level, l) in self.levels.iter().enumerate() {
+            let shift = level * SLOT_BITS;
+            if l.occupied == 0 {
+                continue;
+            }
+            let pos = ((self.elapsed >> shift) as usize) & (SLOTS - 1);
+            // 当前位置所在的槽只可能放着最高层绕了一圈的定时器，最后才轮到它
+            let start = (pos + 1) % SLOTS;
+            let slot = (start + l.occupied.rotate_right(start as u32).trailing_zeros() as usize) % SLOTS;
+            let level_range = 1u64 << (shift + SLOT_BITS);
+            let mut deadline = (self.elapsed & !(level_range - 1)) + ((slot as u64) << shift);
+            // 只有最高层会绕回：超出一圈的定时器在下一圈的槽中
+            if deadline <= self.elapsed {
+                deadline += level_range;
+            }
+            return Some((level, slot, deadline));
+        }
+        None
+    }
+
+    fn ceil_tick(&self, time: Instant) -> u64 {
+        if time <= self.origin {
+            return 0;
+        }
+        let nanos = (time - self.origin).as_nanos();
+        nanos.div_ceil(self.res_nanos).min(u64::MAX as u128) as u64
+    }
+
+    fn floor_tick(&self, time: Instant) -> u64 {
+        if time <= self.origin {
+            return 0;
+        }
+        ((time - self.origin).as_nanos() / self.res_nanos).min(u64::MAX as u128) as u64
+    }
+
+    fn instant(&self, tick: u64) -> Instant {
+        let nanos = tick as u128 * self.res_nanos;
+        let d = Duration::new((nanos / 1_000_000_000) as u64, (nanos % 1_000_000_000) as u32);
+        self.origin.checked_add(d).unwrap_or(self.origin)
+    }
+}
+
+/// elapsed 之后的 deadline 所在的层：两者不同的最高位决定，超出一圈的放在最高层
+fn level_for(elapsed: u64, deadline: u64) -> usize {
+    let masked = ((elapsed ^ deadline) | (SLOTS as u64 - 1)).min(MAX_TICKS - 1);
+    let significant = 63 - masked.leading_zeros() as usize;
+    significant / SLOT_BITS
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use super::super::Instant;
+    use super::super::core::{TimedAction, TimedActionBinaryHeap, TimedActionNode};
+    use super::TimerWheel;
+
+    struct Probe {
+        n: TimedActionNode,
+    }
+
+    impl TimedAction for Probe {
+        fn node(&self) -> &TimedActionNode {
+            &self.n
+        }
+
+        fn process(&self) -> Option<Instant> {
+            None
+        }
+    }
+
+    fn probe() -> Rc<Probe> {
+        Rc::new(Probe { n: TimedActionNode::new() })
+    }
+
+    /// xorshift64，固定种子使每次运行的操作序列相同
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+    }
+
+    /// 随机的启动、调整、取消和时间推进，时间轮和二叉堆每一步到期的定时器必须相同
+    ///
+    /// 到期时间和当前时间都取在刻度上，时间轮的取整不影响结果。
+    #[test]
+    fn matches_heap() {
+        for &seed in &[1, 0x9e37_79b9_7f4a_7c15, 0xdead_beef] {
+            let mut rng = Rng(seed);
+            let res = Duration::from_millis(1);
+            let mut wheel = TimerWheel::new(res);
+            let mut heap = TimedActionBinaryHeap::new();
+            let origin = wheel.origin;
+            let at = |tick: u64| origin + Duration::from_millis(tick);
+            // 同一个定时器在两边各有一个节点：(时间轮中的, 堆中的)
+            let mut live: Vec<(Rc<Probe>, Rc<Probe>)> = Vec::new();
+            let mut now = 0u64;
+
+            for _ in 0..20_000 {
+                match rng.below(10) {
+                    0..=3 => {
+                        // 大多在近处，偶尔跨越高层甚至超出一圈
+                        let delay = match rng.below(8) {
+                            0 => rng.below(1 << 30),
+                            1 => rng.below(1 << 18),
+                            _ => rng.below(1 << 8),
+                        };
+                        let (w, h) = (probe(), probe());
+                        wheel.push(w.clone(), at(now + delay));
+                        heap.push(h.clone(), at(now + delay));
+                        live.push((w, h));
+                    },
+                    4 | 5 if !live.is_empty() => {
+                        let i = rng.below(live.len() as u64) as usize;
+                        let time = at(now + rng.below(1 << 10));
+                        wheel.adjust(live[i].0.node(), time);
+                        heap.adjust(live[i].1.node(), time);
+                    },
+                    6 if !live.is_empty() => {
+                        let i = rng.below(live.len() as u64) as usize;
+                        let (w, h) = live.swap_remove(i);
+                        wheel.remove(w.node());
+                        heap.remove(h.node());
+                    },
+                    _ => {
+                        now += match rng.below(16) {
+                            0 => rng.below(1 << 20),
+                            _ => rng.below(1 << 6),
+                        };
+                        let mut from_wheel = Vec::new();
+                        while let Some(act) = wheel.peek(at(now)) {
+                            wheel.remove(act.node());
+                            from_wheel.push(live.iter().position(|p| ptr::eq(p.0.node(), act.node())).unwrap());
+                        }
+                        let mut from_heap = Vec::new();
+                        while let Some(act) = heap.peek(at(now)) {
+                            heap.remove(act.node());
+                            from_heap.push(live.iter().position(|p| ptr::eq(p.1.node(), act.node())).unwrap());
+                        }
+                        from_wheel.sort();
+                        from_heap.sort();
+                        assert_eq!(from_wheel, from_heap, "seed {:#x}, tick {}", seed, now);
+                        for &i in from_wheel.iter().rev() {
+                            live.swap_remove(i);
+                        }
+                    },
+                }
+                assert_eq!(wheel.len(), heap.len());
+                assert_eq!(wheel.len(), live.len());
+                match (wheel.peek_time(), heap.peek_time()) {
+                    (Some(w), Some(h)) => assert!(w <= h, "seed {:#x}, tick {}", seed, now),
+                    (None, None) => {},
+                    other => panic!("seed {:#x}, tick {}: {:?}", seed, now, other),
+                }
+            }
+        }
+    }
+}
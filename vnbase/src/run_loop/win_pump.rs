@@ -0,0 +1,114 @@
+//! 等待时同时分发 Windows 窗口消息，见 `WaitStrategy::MessagePump`
+//!
+//! 拥有窗口的线程必须及时取出窗口消息。循环由 `MsgWaitForMultipleObjectsEx` 等待，
+//! 窗口消息到达时全部取出并分发，投递和退出通过自动重置的事件唤醒。
+use std::io;
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr;
+use std::time::Duration;
+
+type RawHandle = *mut c_void;
+
+#[repr(C)]
+struct Point {
+    x: i32,
+    y: i32,
+}
+
+#[repr(C)]
+struct Msg {
+    hwnd: *mut c_void,
+    message: u32,
+    wparam: usize,
+    lparam: isize,
+    time: u32,
+    pt: Point,
+}
+
+const INFINITE: u32 = 0xFFFF_FFFF;
+const WAIT_OBJECT_0: u32 = 0;
+const WAIT_TIMEOUT: u32 = 0x102;
+const QS_ALLINPUT: u32 = 0x04FF;
+const MWMO_INPUTAVAILABLE: u32 = 0x0004;
+const PM_REMOVE: u32 = 0x0001;
+const WM_QUIT: u32 = 0x0012;
+
+extern "system" {
+    fn CreateEventW(attributes: *mut c_void, manual_reset: i32, initial_state: i32, name: *const u16) -> RawHandle;
+    fn SetEvent(event: RawHandle) -> i32;
+    fn CloseHandle(handle: RawHandle) -> i32;
+}
+
+#[link(name = "user32")]
+extern "system" {
+    fn MsgWaitForMultipleObjectsEx(count: u32, handles: *const RawHandle, ms: u32, wake_mask: u32, flags: u32) -> u32;
+    fn PeekMessageW(msg: *mut Msg, hwnd: *mut c_void, filter_min: u32, filter_max: u32, remove: u32) -> i32;
+    fn TranslateMessage(msg: *const Msg) -> i32;
+    fn DispatchMessageW(msg: *const Msg) -> isize;
+}
+
+/// 唤醒循环的自动重置事件
+pub struct PumpEvent(RawHandle);
+
+unsafe impl Send for PumpEvent {}
+unsafe impl Sync for PumpEvent {}
+
+impl PumpEvent {
+    pub fn new() -> io::Result<PumpEvent> {
+        let event = unsafe { CreateEventW(ptr::null_mut(), 0, 0, ptr::null()) };
+        if event.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(PumpEvent(event))
+    }
+
+    pub fn set(&self) {
+        unsafe { SetEvent(self.0); }
+    }
+}
+
+impl Drop for PumpEvent {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.0); }
+    }
+}
+
+/// 等待事件被设置、窗口消息到达或者超时，返回是否超时
+///
+/// 到达的窗口消息在返回前全部分发。
+pub fn wait(event: &PumpEvent, timeout: Option<Duration>) -> bool {
+    let ms = match timeout {
+        // 向上取整，避免在到期前醒来后空转
+        Some(d) => {
+            let ms = d.as_secs().saturating_mul(1000) + (d.subsec_nanos() as u64 + 999_999) / 1_000_000;
+            ms.min(INFINITE as u64 - 1) as u32
+        },
+        None => INFINITE,
+    };
+    let r = unsafe { MsgWaitForMultipleObjectsEx(1, &event.0, ms, QS_ALLINPUT, MWMO_INPUTAVAILABLE) };
+    match r {
+        WAIT_OBJECT_0 => false,
+        WAIT_TIMEOUT => true,
+        r if r == WAIT_OBJECT_0 + 1 => {
+            dispatch();
+            false
+        },
+        _ => panic!("run loop wait failed: {}", io::Error::last_os_error()),
+    }
+}
+
+/// 取出并分发当前所有的窗口消息，取到 `WM_QUIT` 时让循环退出
+fn dispatch() {
+    let mut msg: Msg = unsafe { mem::zeroed() };
+    while unsafe { PeekMessageW(&mut msg, ptr::null_mut(), 0, 0, PM_REMOVE) } != 0 {
+        if msg.message == WM_QUIT {
+            super::stop();
+            continue;
+        }
+        unsafe {
+            TranslateMessage(&msg);
+            DispatchMessageW(&msg);
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! 在 winit 的事件循环中驱动当前线程的循环，需要开启 `winit` 特性
+//!
+//! winit 的 `EventLoop` 占用了线程，不能再调用 `run_loop::run`。`run_app` 包装应用的 `ApplicationHandler`，
+//! winit 每一轮准备等待时（`about_to_wait`）处理一轮消息和到期的定时器，见 `run_loop::run_pending`，
+//! 并按下一个定时器的到期时间设置 `ControlFlow`；其它线程的投递通过 `EventLoopProxy::send_event` 唤醒 winit。
+//! 定时器、周期历程和循环内对象在窗口线程照常使用，循环被要求退出时 winit 的事件循环随之退出。
+//!
+//! 示例见 `examples/winit.rs`。
+use std::sync::Mutex;
+
+use winit::application::ApplicationHandler;
+use winit::event::{DeviceEvent, DeviceId, StartCause, WindowEvent};
+use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop, EventLoopProxy};
+use winit::error::EventLoopError;
+use winit::window::WindowId;
+
+use super::PumpStatus;
+
+/// 唤醒 winit 事件循环的用户事件，由 `set_proxy` 设置的唤醒函数发送
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Wake;
+
+/// 把 `run_pending` 的结果转换为 winit 的 `ControlFlow`，`Stop` 时为 None，应当退出
+pub fn control_flow(status: PumpStatus) -> Option<ControlFlow> {
+    match status {
+        PumpStatus::Wait => Some(ControlFlow::Wait),
+        PumpStatus::WaitUntil(t) => Some(ControlFlow::WaitUntil(t)),
+        PumpStatus::Stop => None,
+    }
+}
+
+/// 以 proxy 作为当前线程循环的外部唤醒函数，见 `run_loop::set_external_waker`
+pub fn set_proxy(proxy: EventLoopProxy<Wake>) {
+    let proxy = Mutex::new(proxy);
+    super::set_external_waker(move || {
+        // winit 已经退出时不需要唤醒
+        let _ = super::core::lock(&proxy).send_event(Wake);
+    });
+}
+
+/// 处理一轮当前线程的消息和到期的定时器，并设置 event_loop 之后的等待方式
+///
+/// 自己实现 `ApplicationHandler<Wake>` 时在 `about_to_wait` 中调用，`run_app` 已经包括。
+pub fn pump(event_loop: &ActiveEventLoop) {
+    match control_flow(super::run_pending()) {
+        Some(flow) => event_loop.set_control_flow(flow),
+        None => event_loop.exit(),
+    }
+}
+
+/// 以 app 运行 event_loop，期间驱动当前线程的循环，返回时清除外部唤醒函数
+///
+/// app 的 `about_to_wait` 先于循环的处理调用，其中设置的 `ControlFlow` 会被覆盖。
+pub fn run_app<A>(event_loop: EventLoop<Wake>, app: &mut A) -> Result<(), EventLoopError> where A: ApplicationHandler {
+    set_proxy(event_loop.create_proxy());
+    let r = event_loop.run_app(&mut Adapter { app: app });
+    super::clear_external_waker();
+    r
+}
+
+/// 把 winit 的事件转给应用，`Wake` 只用于让 winit 醒来
+struct Adapter<'a, A: 'a> {
+    app: &'a mut A,
+}
+
+impl<'a, A> ApplicationHandler<Wake> for Adapter<'a, A> where A: ApplicationHandler {
+    fn new_events(&mut self, event_loop: &ActiveEventLoop, cause: StartCause) {
+        self.app.new_events(event_loop, cause);
+    }
+
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        self.app.resumed(event_loop);
+    }
+
+    fn user_event(&mut self, _event_loop: &ActiveEventLoop, _event: Wake) {}
+
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, window_id: WindowId, event: WindowEvent) {
+        self.app.window_event(event_loop, window_id, event);
+    }
+
+    fn device_event(&mut self, event_loop: &ActiveEventLoop, device_id: DeviceId, event: DeviceEvent) {
+        self.app.device_event(event_loop, device_id, event);
+    }
+
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        self.app.about_to_wait(event_loop);
+        pump(event_loop);
+    }
+
+    fn suspended(&mut self, event_loop: &ActiveEventLoop) {
+        self.app.suspended(event_loop);
+    }
+
+    fn exiting(&mut self, event_loop: &ActiveEventLoop) {
+        self.app.exiting(event_loop);
+    }
+
+    fn memory_warning(&mut self, event_loop: &ActiveEventLoop) {
+        self.app.memory_warning(event_loop);
+    }
+}
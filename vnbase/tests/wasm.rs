@@ -0,0 +1,96 @@
+//! wasm32 上由宿主驱动的循环，用 `wasm-pack test --node -- --features wasm` 或者 `--headless --chrome` 运行
+#![cfg(all(feature = "wasm", target_arch = "wasm32"))]
+
+extern crate vnbase;
+extern crate wasm_bindgen_test;
+
+use std::cell::Cell;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use vnbase::run_loop::{self, oneshot};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+/// 收到 rx 的值后执行 f，测试按 2015 版编译，不能使用 async
+struct Then<F> {
+    rx: oneshot::Receiver<()>,
+    f: Option<F>,
+}
+
+impl<F: FnOnce() + Unpin> Future for Then<F> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        match Pin::new(&mut self.rx).poll(cx) {
+            Poll::Ready(r) => {
+                r.unwrap();
+                (self.f.take().unwrap())();
+                Poll::Ready(())
+            },
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+fn then<F: FnOnce() + Unpin>(rx: oneshot::Receiver<()>, f: F) -> Then<F> {
+    Then { rx: rx, f: Some(f) }
+}
+
+#[wasm_bindgen_test(async)]
+fn schedule_and_post() -> impl Future<Output = ()> {
+    let posted = Arc::new(AtomicBool::new(false));
+    let p = posted.clone();
+    run_loop::clone_handle().post(move || p.store(true, Ordering::SeqCst));
+
+    let (tx, rx) = run_loop::oneshot();
+    let mut tx = Some(tx);
+    let ticks = Rc::new(Cell::new(0));
+    let t = ticks.clone();
+    let schedule = run_loop::new_schedule()
+        .with_period(Duration::from_millis(10))
+        .with_callback(move |_| {
+            t.set(t.get() + 1);
+            if t.get() == 3 {
+                tx.take().unwrap().send(()).unwrap();
+                run_loop::stop();
+            }
+        })
+        .and_start();
+
+    // run 立即返回，之后由宿主驱动
+    run_loop::run();
+    assert!(!posted.load(Ordering::SeqCst));
+    assert_eq!(ticks.get(), 0);
+
+    then(rx, move || {
+        assert!(posted.load(Ordering::SeqCst));
+        assert_eq!(ticks.get(), 3);
+        schedule.cancel();
+    })
+}
+
+#[wasm_bindgen_test(async)]
+fn timer_and_object_after_restart() -> impl Future<Output = ()> {
+    let (tx, rx) = run_loop::oneshot();
+    let obj = run_loop::new_object(Cell::new(0));
+    let weak = obj.downgrade();
+    let timer = run_loop::new_timer()
+        .with_callback_once(move || {
+            weak.upgrade().unwrap().get_ref().unwrap().set(1);
+            tx.send(()).unwrap();
+            run_loop::stop();
+        })
+        .and_start(Duration::from_millis(20));
+    run_loop::run();
+
+    then(rx, move || {
+        assert_eq!(obj.get_ref().unwrap().get(), 1);
+        assert!(!timer.is_active());
+        assert_eq!(run_loop::next_timer_deadline(), None);
+    })
+}
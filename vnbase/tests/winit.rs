@@ -0,0 +1,65 @@
+//! 由 winit 的事件循环驱动循环，`cargo test --features winit --test winit`
+//!
+//! 不创建窗口，周期历程代替重绘请求。winit 要求在主线程创建事件循环，因此不使用测试框架；
+//! 没有显示环境（`DISPLAY`、`WAYLAND_DISPLAY`）时跳过。
+extern crate vnbase;
+extern crate winit;
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use vnbase::run_loop;
+use winit::application::ApplicationHandler;
+use winit::event::WindowEvent;
+use winit::event_loop::{ActiveEventLoop, EventLoop};
+use winit::window::WindowId;
+
+struct Headless;
+
+impl ApplicationHandler for Headless {
+    fn resumed(&mut self, _event_loop: &ActiveEventLoop) {}
+
+    fn window_event(&mut self, _event_loop: &ActiveEventLoop, _id: WindowId, _event: WindowEvent) {}
+}
+
+fn main() {
+    let event_loop = match EventLoop::with_user_event().build() {
+        Ok(event_loop) => event_loop,
+        Err(e) => {
+            println!("winit: skipped, no event loop: {}", e);
+            return;
+        },
+    };
+
+    // 周期历程在 winit 的等待超时后执行
+    let frames = Rc::new(Cell::new(0));
+    let f = frames.clone();
+    let _redraw = run_loop::new_schedule()
+        .with_period(Duration::from_millis(10))
+        .with_callback(move |_| f.set(f.get() + 1))
+        .and_start();
+
+    // 其它线程的投递通过 EventLoopProxy 唤醒 winit
+    let posted = Arc::new(AtomicBool::new(false));
+    let (handle, p) = (run_loop::clone_handle(), posted.clone());
+    let th = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        handle.post(move || {
+            p.store(true, Ordering::SeqCst);
+            run_loop::stop();
+        });
+    });
+
+    let start = Instant::now();
+    run_loop::winit::run_app(event_loop, &mut Headless).unwrap();
+    th.join().unwrap();
+
+    assert!(posted.load(Ordering::SeqCst));
+    assert!(frames.get() >= 5, "only {} frames", frames.get());
+    assert!(start.elapsed() < Duration::from_secs(5));
+    println!("winit: ok, {} frames", frames.get());
+}